@@ -0,0 +1,137 @@
+// where stamp bitmaps, outlines and the editor's own images are found. documents and manifests
+// store hrefs relative to an asset root, and the roots are searched in order, so the program no
+// longer has to be started from the directory holding assets/
+use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::vec::Vec;
+
+pub const ASSETS_ENV_VAR: &str = "ART_STAMPS_ASSETS";
+pub const DEFAULT_ASSET_DIR: &str = "assets";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetResolver {
+    pub roots: Vec<PathBuf>, // searched first to last
+}
+
+impl Default for AssetResolver {
+    fn default() -> AssetResolver {
+        AssetResolver{roots: vec![PathBuf::from(DEFAULT_ASSET_DIR)]}
+    }
+}
+
+impl AssetResolver {
+    pub fn new(roots: Vec<PathBuf>) -> AssetResolver {
+        AssetResolver{roots}
+    }
+    // --assets= directories in the order given, then the ART_STAMPS_ASSETS path list, then assets/
+    // in the working directory and beside the executable
+    pub fn configure(flags: &[String], env: Option<OsString>, executable: Option<PathBuf>) -> AssetResolver {
+        let mut ret = AssetResolver{roots: Vec::new()};
+        for flag in flags {
+            ret.add_root(PathBuf::from(flag));
+        }
+        if let Some(env) = env {
+            for root in std::env::split_paths(&env) {
+                ret.add_root(root);
+            }
+        }
+        ret.add_root(PathBuf::from(DEFAULT_ASSET_DIR));
+        if let Some(dir) = executable.as_ref().and_then(|executable| executable.parent()) {
+            ret.add_root(dir.join(DEFAULT_ASSET_DIR));
+        }
+        ret
+    }
+    pub fn from_env(flags: &[String]) -> AssetResolver {
+        AssetResolver::configure(flags, std::env::var_os(ASSETS_ENV_VAR), std::env::current_exe().ok())
+    }
+    // empty entries, as a trailing separator in the path list leaves, and repeats are skipped
+    pub fn add_root(&mut self, root: PathBuf) {
+        if !root.as_os_str().is_empty() && !self.roots.contains(&root) {
+            self.roots.push(root);
+        }
+    }
+    // the first root holding href. documents from before asset roots spelled hrefs from the
+    // working directory, assets/stamps/rect.bmp, so those are also looked up without the assets/.
+    // a file found nowhere, like a bitmap not drawn yet, belongs in the first root
+    pub fn resolve(&self, href: &str) -> PathBuf {
+        let path = Path::new(href);
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+        let legacy = self.legacy_relative(href);
+        for candidate in [Some(href), legacy].iter().flatten() {
+            for root in &self.roots {
+                let joined = root.join(candidate);
+                if joined.exists() {
+                    return joined;
+                }
+            }
+        }
+        if path.exists() {
+            return path.to_path_buf();
+        }
+        match self.roots.first() {
+            Some(root) => root.join(legacy.unwrap_or(href)),
+            None => path.to_path_buf(),
+        }
+    }
+    // the href a document stores for a file under one of the roots
+    pub fn relative(&self, href: &str) -> String {
+        for root in &self.roots {
+            if let Ok(rest) = Path::new(href).strip_prefix(root) {
+                return to_href(rest);
+            }
+        }
+        match self.legacy_relative(href) {
+            Some(rest) => rest.to_string(),
+            None => href.to_string(),
+        }
+    }
+    fn legacy_relative<'a>(&self, href: &'a str) -> Option<&'a str> {
+        href.strip_prefix(DEFAULT_ASSET_DIR).and_then(|rest| rest.strip_prefix('/'))
+    }
+}
+
+// hrefs separate directories with / whatever the platform does
+pub fn to_href(path: &Path) -> String {
+    path.components().map(|component| component.as_os_str().to_string_lossy().into_owned()).collect::<Vec<String>>().join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::ffi::OsString;
+    use super::AssetResolver;
+    use super::super::svgxml::{SVG, Transform, Color, stamp_svg_path};
+
+    #[test]
+    fn test_asset_resolver() {
+        let flags = vec!["/nonexistent/pack".to_string()];
+        let env = std::env::join_paths(&["/nonexistent/shared", "assets"]).unwrap();
+        let resolver = AssetResolver::configure(&flags, Some(env), Some(PathBuf::from("/opt/art-stamps/bin/art-stamps")));
+        assert_eq!(resolver.roots, vec![PathBuf::from("/nonexistent/pack"), PathBuf::from("/nonexistent/shared"),
+                                        PathBuf::from("assets"), PathBuf::from("/opt/art-stamps/bin/assets")]);
+        assert_eq!(AssetResolver::configure(&[], Some(OsString::new()), None), AssetResolver::default());
+
+        // found in the first root that has it, whichever spelling the document used
+        assert_eq!(resolver.resolve("stamps/rect.bmp"), PathBuf::from("assets/stamps/rect.bmp"));
+        assert_eq!(resolver.resolve("assets/stamps/rect.bmp"), PathBuf::from("assets/stamps/rect.bmp"));
+        assert_eq!(resolver.resolve("rect.svg"), PathBuf::from("assets/rect.svg"));
+        assert_eq!(resolver.resolve("/tmp/elsewhere.bmp"), PathBuf::from("/tmp/elsewhere.bmp"));
+        assert_eq!(resolver.resolve("stamps/undrawn.bmp"), PathBuf::from("/nonexistent/pack/stamps/undrawn.bmp"));
+
+        assert_eq!(resolver.relative("assets/stamps/rect.bmp"), "stamps/rect.bmp");
+        assert_eq!(resolver.relative("/nonexistent/shared/stamps/roof.bmp"), "stamps/roof.bmp");
+        assert_eq!(resolver.relative("stamps/rect.bmp"), "stamps/rect.bmp");
+        assert_eq!(resolver.relative("/tmp/elsewhere.bmp"), "/tmp/elsewhere.bmp");
+
+        // a document saved from the repository root keeps working once its hrefs are made relative
+        let mut svg = SVG::new(100, 100);
+        svg.add(Transform::new(64, 64), "assets/stamps/circle.bmp".to_string(), String::new(), Color::default());
+        svg.use_assets(resolver);
+        assert_eq!(svg.stamps[0].rect.href.url, "stamps/circle.bmp");
+        assert_eq!(stamp_svg_path("stamps/circle.bmp"), "circle.svg");
+        assert_eq!(svg.outline_path("stamps/circle.bmp"), "assets/circle.svg");
+        assert_eq!(svg.stamp_at((32., 32.), &mut Default::default()).unwrap(), Some(0));
+    }
+}
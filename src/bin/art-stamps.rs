@@ -1,12 +1,12 @@
 extern crate sdl2;
 extern crate art_stamps;
-use art_stamps::{SVG, HrefAndClipMask, Polygon};
+use art_stamps::{SVG, HrefAndClipMask, AssetResolver};
 use std::time;
 use std::string::String;
 use std::collections::HashMap;
 use std::env;
 use std::vec::Vec;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
 use sdl2::event::Event;
 
@@ -14,7 +14,7 @@ use sdl2::keyboard::Keycode;
 use sdl2::mouse::Cursor;
 use sdl2::pixels::Color;
 use std::io;
-use std::io::{Read, Write};
+use std::io::Read;
 use sdl2::rect::{Rect, Point};
 use sdl2::surface::Surface;
 use sdl2::pixels::PixelFormatEnum;
@@ -26,6 +26,14 @@ static DELTA_DURATION_PER_FRAME:time::Duration = time::Duration::from_millis(75)
 
 const MOUSE_CONSTANT: i32 = 1;
 const ROT_CONSTANT: f64 = 1.0;
+const HISTORY_LIMIT: usize = 1024;
+const SCALE_CONSTANT: f64 = 1.03125;
+const MIN_STAMP_SCALE: f64 = 1. / 16.;
+const MAX_STAMP_SCALE: f64 = 16.;
+const ZOOM_CONSTANT: f64 = 1.03125;
+const MIN_ZOOM: f64 = 1. / 32.;
+const MAX_ZOOM: f64 = 32.;
+const STAMP_BITMAP_SIZE: u32 = 64; // bitmaps drawn from an outline are this many pixels square
 struct TextureSurface<'r> {
     texture: Texture<'r>,
     surface: Surface<'r>,
@@ -41,6 +49,17 @@ fn mouse_move(delta:i32, repeat:time::Duration) -> i32 {
 }
 
 
+// where copy_ex has to draw a width x height texture so that it lands on t, and the point it rotates about
+fn stamp_destination(t: &art_stamps::Transform, width: u32, height: u32) -> (Rect, Point) {
+    let scaled_width = width as f64 * t.scalex;
+    let scaled_height = height as f64 * t.scaley;
+    (Rect::new((t.tx + t.midx - t.midx * t.scalex) as i32,
+               (t.ty + t.midy - t.midy * t.scaley) as i32,
+               scaled_width.round().max(1.0) as u32,
+               scaled_height.round().max(1.0) as u32),
+     Point::new((t.midx * t.scalex) as i32, (t.midy * t.scaley) as i32))
+}
+
 fn box_intersect (t0: &art_stamps::Transform, t1: &art_stamps::Transform) -> bool {
     art_stamps::poly_edge_intersect(&t0.to_bbox(), &t1.to_bbox())
 }
@@ -100,19 +119,34 @@ struct InventoryKey{
 struct Arrangement{
     svg: art_stamps::SVG,
     dirty: bool,
-    undo: Vec<art_stamps::g>,
+    history: art_stamps::History,
+    autosave: art_stamps::Autosave,
 }
 impl Arrangement {
     pub fn new(svg: art_stamps::SVG) -> Self {
-        Arrangement{svg:svg, dirty:true, undo:Vec::new()}
+        Arrangement{svg, dirty:true, history:art_stamps::History::new(HISTORY_LIMIT),
+                    autosave:art_stamps::Autosave::new(time::Instant::now())}
     }
-    pub fn get_mut(&mut self) -> &mut art_stamps::SVG {
+    fn touch(&mut self) {
         self.dirty = true;
-        &mut self.svg
+        self.autosave.changed(time::Instant::now());
     }
     pub fn get(&self) -> &art_stamps::SVG{
         &self.svg
     }
+    // every change to the document goes through here so that it can be undone
+    pub fn apply(&mut self, edit: art_stamps::Edit) {
+        self.touch();
+        self.history.apply(&mut self.svg, edit);
+    }
+    pub fn undo(&mut self) -> bool {
+        self.touch();
+        self.history.undo(&mut self.svg)
+    }
+    pub fn redo(&mut self) -> bool {
+        self.touch();
+        self.history.redo(&mut self.svg)
+    }
 }
 
 fn round_up_to_golden(angle: f64, locked: bool) -> f64 {
@@ -147,6 +181,18 @@ fn round_down_to_golden(angle: f64, locked: bool) -> f64 {
     iangle as f64
 }
 
+fn clamp_stamp_scale(scale: f64) -> f64 {
+    scale.clamp(MIN_STAMP_SCALE, MAX_STAMP_SCALE)
+}
+fn round_up_to_power_of_two(scale: f64) -> f64 {
+    let exponent = (scale.log2() + 0.001).floor() + 1.;// a scale already on a power of two moves on to the next one
+    exponent.exp2()
+}
+fn round_down_to_power_of_two(scale: f64) -> f64 {
+    let exponent = (scale.log2() - 0.001).ceil() - 1.;
+    exponent.exp2()
+}
+
 struct SceneGraph {
     inventory: Vec<InventoryItem>,
     inventory_map: HashMap<HrefAndClipMask, usize>,
@@ -171,12 +217,10 @@ impl SceneGraph {
       for g in self.arrangement.svg.stamps.iter() {
           if let None = self.inventory_map.get(&g.rect.href) {
               // now we need to prerender
-              let mut polygon = Polygon::default();
-              for clips in self.arrangement.svg.defs.clipPath.iter() {
-                  if "url(#".to_string() + &clips.id + ")" == g.rect.href.clip {
-                      polygon = clips.polygon.clone();
-                  }
-              }
+              let clip_shape = match self.arrangement.svg.clip_path(&g.rect.href.clip) {
+                  Some(clips) => clips.to_shape(),
+                  None => art_stamps::Shape::default(),
+              };
               let mut dst_surface: Surface;
               let width;
               let height;
@@ -200,22 +244,15 @@ impl SceneGraph {
                   continue
               }
               let pitch = dst_surface.pitch();
-              let polygon_points = &polygon.points;
-              let last_point_index = polygon_points.len().wrapping_sub(1);
+              let edge_count = clip_shape.edges().count();
               dst_surface.with_lock_mut(|data:&mut[u8]| {
                   // rasterize our friend the clip polygon
                   for y in 0..height {
-                      polygon_intercepts.resize(polygon_points.len() + 2, 0);
+                      polygon_intercepts.resize(edge_count + 2, 0);
                       let y_byte_offset = y as usize * pitch as usize;
                       polygon_intercepts[0] = std::i32::MIN; // clear the opposite of clip mask
                       let mut num_intercepts = 1;
-                      for (index, point0) in polygon_points.iter().enumerate() {
-                          let prev_point_index = if index == 0 {
-                              last_point_index
-                          } else {
-                              index - 1
-                          };
-                          let point1 = polygon_points[prev_point_index];
+                      for (point1, point0) in clip_shape.edges() {
                           let x0;
                           let x1;
                           let y0;
@@ -302,6 +339,12 @@ struct SceneState{
     window_height: u32,
     color: art_stamps::Color,
     locked: bool,
+    select_mode: bool, // clicks pick placed stamps instead of stamping
+    selected: Option<usize>, // index into svg.stamps
+    dragging: bool,
+    polygon_cache: art_stamps::ShapeCache,
+    save_error: Option<String>, // shown in the title bar until a save succeeds
+    library: art_stamps::Library,
 }
 
 impl SceneState {
@@ -310,12 +353,6 @@ impl SceneState {
     let mut w_offset = 0i32;
     let mut h_offset = 0i32;
     let mut max_width = 0i32;
-      static ROT_DELTAS:&'static[(&'static str, f64)]= &[
-          ("rect", 90.0),
-          ("pipe", 90.0),
-          ("lhalframp", 90.0), ("lquartramp", 90.0),
-          ("rhalframp", -90.0), ("rquartramp", -90.0),
-      ];
     for (index, (stamp, inventory)) in images.stamps.iter().zip(self.scene_graph.inventory.iter_mut()).enumerate() {
       if h_offset + stamp.surface.height() as i32 > canvas_viewport.height() as i32 {
         h_offset = 0;
@@ -324,11 +361,7 @@ impl SceneState {
       }
       inventory.stamp_index = index;
         inventory.stamp_name = stamp.name.clone();
-        for rot_delta in ROT_DELTAS {
-            if stamp.name.contains(rot_delta.0) {
-                inventory.rot_delta = rot_delta.1;
-            }
-        }
+        inventory.rot_delta = self.library.get(&stamp.name).map(|info| info.rotation).unwrap_or(0.0);
       inventory.stamp_source = Rect::new(w_offset, h_offset, stamp.surface.width(), stamp.surface.height());
       self.scene_graph.inventory_map.insert(HrefAndClipMask{url:inventory.stamp_name.clone(), clip:String::new()}, index);
       max_width = std::cmp::max(max_width, stamp.surface.width() as i32);
@@ -345,19 +378,28 @@ impl SceneState {
                 let final_transform = art_stamps::compose(&self.camera_transform, &g.transform);
                 let img = &mut images.stamps[*index];
 		img.texture.set_color_mod(g.rect.fill.r,g.rect.fill.g,g.rect.fill.b);
+                let (dest, center) = stamp_destination(&final_transform, g.rect.width, g.rect.height);
                 canvas.copy_ex(
                     &img.texture,
                     None,
-                    Some(Rect::new(final_transform.tx as i32, final_transform.ty as i32, g.rect.width, g.rect.height)),
+                    Some(dest),
                     final_transform.rotate,
-                    Point::new(final_transform.midx as i32, final_transform.midy as i32),
-                    false,
-                    false,
+                    center,
+                    final_transform.flipx,
+                    final_transform.flipy,
                 ).map_err(|err| format!("{:?}", err))?;
             } else {
                 // skip drawing unknown item
             }
         }
+        if let Some(index) = self.selected {
+            let g = &self.scene_graph.arrangement.get().stamps[index];
+            let outline = art_stamps::compose(&self.camera_transform, &g.transform).to_bbox();
+            let mut points: Vec<Point> = outline.iter().map(|p| Point::new(p.0 as i32, p.1 as i32)).collect();
+            points.push(points[0]);
+            canvas.set_draw_color(Color::RGBA(0, 0xc0, 0xff, 255));
+            canvas.draw_lines(&points[..])?;
+        }
         for stamp_loc in self.scene_graph.inventory.iter() {
           let dest = stamp_loc.stamp_source;
           let image = &mut images.stamps[stamp_loc.stamp_index];
@@ -374,18 +416,21 @@ impl SceneState {
         if let Some(active_stamp) = self.active_stamp {
             let img = &mut images.stamps[active_stamp];
 	        img.texture.set_color_mod(self.color.r,self.color.g,self.color.b);
+            let mut cursor_stamp_transform = self.cursor_transform.transform.clone();
+            // preview at the size the stamp will land in the document
+            cursor_stamp_transform.scalex *= self.camera_transform.scalex;
+            cursor_stamp_transform.scaley *= self.camera_transform.scaley;
+            cursor_stamp_transform.tx = (self.mouse_lock_x(self.cursor_transform.mouse_x) - img.surface.width()as i32/2) as f64;
+            cursor_stamp_transform.ty = (self.mouse_lock_y(self.cursor_transform.mouse_y) - img.surface.height() as i32/2) as f64;
+            let (dest, center) = stamp_destination(&cursor_stamp_transform, img.surface.width(), img.surface.height());
             canvas.copy_ex(
                 &img.texture,
                 None,
-                Some(Rect::new(self.mouse_lock_x(self.cursor_transform.mouse_x) - img.surface.width()as i32/2,
-                               self.mouse_lock_y(self.cursor_transform.mouse_y) - img.surface.height() as i32/2,
-                               img.surface.width(),
-                               img.surface.height())),
+                Some(dest),
                 self.cursor_transform.transform.rotate,
-                Point::new(self.cursor_transform.transform.midx as i32,
-                           self.cursor_transform.transform.midy as i32),//centre
-                false,//horiz
-                false,//vert
+                center,
+                self.cursor_transform.transform.flipx,
+                self.cursor_transform.transform.flipy,
             ).map_err(|err| format!("{:?}", err))?;            
         } else {
             images.default_cursor.texture.set_color_mod(self.color.r,self.color.g,self.color.b);
@@ -402,12 +447,13 @@ impl SceneState {
         }
         for mask in self.mask_transforms.iter() {
             let final_mask_transform = art_stamps::compose(&self.camera_transform, mask);
+            let (dest, center) = stamp_destination(&final_mask_transform, (2.0 * mask.midx) as u32, (2.0 * mask.midy) as u32);
             canvas.copy_ex(
                 &images.mask.texture,
                 None,
-                    Some(Rect::new(final_mask_transform.tx as i32, final_mask_transform.ty as i32, (2.0 * final_mask_transform.midx) as u32, (2.0 * final_mask_transform.midy) as u32)),
+                    Some(dest),
                     final_mask_transform.rotate,
-                    Point::new(final_mask_transform.midx as i32, final_mask_transform.midy as i32),
+                    center,
                     false,
                     false,
                 ).map_err(|err| format!("{:?}", err))?;
@@ -433,6 +479,7 @@ impl SceneState {
             //eprintln!("KEY PRESS {:?}; REPEAT {} {:?}?", keys_down, repeat, new_key);
         }
         let shifted_index = (keys_down.contains_key(&Keycode::LShift) as usize) | (keys_down.contains_key(&Keycode::RShift) as usize);
+        let previous_color = self.color;
         if keys_down.contains_key(&Keycode::Left) {
             self.cursor_transform.mouse_x -= mouse_move(MOUSE_CONSTANT, self.duration_per_frame);
             self.clear_cursor_if_stamp_used();
@@ -451,19 +498,19 @@ impl SceneState {
         }
         if keys_down.contains_key(&Keycode::W) {
             self.camera_transform.ty += mouse_move(MOUSE_CONSTANT, self.duration_per_frame) as f64;
-            if self.camera_transform.ty > 1.0 {
-                let to_round = self.camera_transform.ty as u32;
-                for stamp_loc in self.scene_graph.arrangement.undo.iter_mut() {
-                    stamp_loc.transform.ty += to_round as f64;
-                }
-                for stamp_loc in self.scene_graph.arrangement.svg.stamps.iter_mut() {
-                    stamp_loc.transform.ty += to_round as f64;
+            if self.camera_transform.ty > self.camera_transform.scaley {
+                let to_round = (self.camera_transform.ty / self.camera_transform.scaley) as u32;
+                let before = art_stamps::Layout::capture(self.scene_graph.arrangement.get());
+                let mut after = before.clone();
+                for origin in after.origins.iter_mut() {
+                    origin.1 += to_round as f64;
                 }
-                self.scene_graph.arrangement.svg.height += to_round;
+                after.height += to_round;
+                self.scene_graph.arrangement.apply(art_stamps::Edit::Resize{before, after});
                 for mask in self.mask_transforms.iter_mut() {
                     // mask.ty -= to_round as f64 we want it to remain, visually, in the same place
                 }
-                self.camera_transform.ty -= to_round as f64;
+                self.camera_transform.ty -= to_round as f64 * self.camera_transform.scaley;
             }
         }
         if keys_down.contains_key(&Keycode::A) {
@@ -562,20 +609,37 @@ impl SceneState {
                 }
             }
         }
-        if keys_down.contains_key(&Keycode::Escape) {
-            write_from_string(Path::new(&self.save_file_name),
-                              &self.scene_graph.arrangement.get().to_string().map_err(
-                                  |err| format!("{:?}", err)).unwrap()).map_err(
-                |err| format!("{:?}", err)).unwrap();
-            std::process::exit(0);
+        if keys_down.contains_key(&Keycode::Escape) && !repeat {
+            match self.save() {
+                Ok(()) => std::process::exit(0),
+                Err(err) => self.report_save_error(err), // stay open rather than lose the drawing
+            }
         }
-        /*
         if keys_down.contains_key(&Keycode::Q) {
-            self.camera_transform.scale /= 1.03125 / 4.;
+            let shift_down = keys_down.contains_key(&Keycode::LShift) || keys_down.contains_key(&Keycode::RShift);
+            if self.locked || shift_down {
+                if !repeat {
+                    self.duration_per_frame = RELAXED_DURATION_PER_FRAME;
+                    self.zoom_camera(-1, true);
+                }
+            } else {
+                self.zoom_camera(-1, false);
+            }
         }
         if keys_down.contains_key(&Keycode::E) {
-            self.camera_transform.scale *= 1.03125 / 4.;
-        }*/
+            let shift_down = keys_down.contains_key(&Keycode::LShift) || keys_down.contains_key(&Keycode::RShift);
+            if self.locked || shift_down {
+                if !repeat {
+                    self.duration_per_frame = RELAXED_DURATION_PER_FRAME;
+                    self.zoom_camera(1, true);
+                }
+            } else {
+                self.zoom_camera(1, false);
+            }
+        }
+        if keys_down.contains_key(&Keycode::Home) && !repeat {
+            self.fit_camera();
+        }
         if keys_down.contains_key(&Keycode::I) {
             self.mask_transforms[shifted_index].ty -= mouse_move(MOUSE_CONSTANT, self.duration_per_frame) as f64;
     /*        if self.mask_transforms[shifted_index].ty < 0.0 {
@@ -597,6 +661,21 @@ impl SceneState {
             self.mask_transforms[shifted_index].tx -= mouse_move(MOUSE_CONSTANT, self.duration_per_frame) as f64;
             constrain_mask_transform(&mut self.mask_transforms[shifted_index], self.window_width, self.window_height)
         }
+        if keys_down.contains_key(&Keycode::F) && !repeat {
+            if shifted_index != 0 {
+                self.cursor_transform.transform.flipy = !self.cursor_transform.transform.flipy;
+            } else {
+                self.cursor_transform.transform.flipx = !self.cursor_transform.transform.flipx;
+            }
+        }
+        if keys_down.contains_key(&Keycode::V) && !repeat {
+            self.select_mode = !self.select_mode;
+            self.selected = None;
+            self.dragging = false;
+            if self.select_mode {
+                self.active_stamp = None;
+            }
+        }
         if keys_down.contains_key(&Keycode::Tab) {
             if shifted_index != 0 {
                 self.locked = false;
@@ -621,16 +700,20 @@ impl SceneState {
             constrain_mask_transform(&mut self.mask_transforms[shifted_index], self.window_width, self.window_height)
         }
         if keys_down.contains_key(&Keycode::Backspace) && !repeat {
+            let last = self.scene_graph.arrangement.get().stamps.len().checked_sub(1);
             if keys_down.contains_key(&Keycode::LShift) || keys_down.contains_key(&Keycode::RShift) {
-                if self.scene_graph.arrangement.undo.len() != 0 {
-                    self.scene_graph.arrangement.svg.stamps.push(self.scene_graph.arrangement.undo.pop().unwrap());
-                    self.scene_graph.arrangement.dirty = true;
-                }
+                self.undo();
+            } else if let Some(index) = self.selected.take().or(last) {
+                let stamp = self.scene_graph.arrangement.get().stamps[index].clone();
+                self.scene_graph.arrangement.apply(art_stamps::Edit::Remove{index, stamp});
+            }
+        }
+        if keys_down.contains_key(&Keycode::Z) && !repeat
+            && (keys_down.contains_key(&Keycode::LCtrl) || keys_down.contains_key(&Keycode::RCtrl)) {
+            if shifted_index != 0 {
+                self.redo();
             } else {
-                if self.scene_graph.arrangement.svg.stamps.len() != 0 {
-                    self.scene_graph.arrangement.undo.push(self.scene_graph.arrangement.svg.stamps.pop().unwrap());
-                    self.scene_graph.arrangement.dirty = true;
-                }
+                self.undo();
             }
         }
         if keys_down.contains_key(&Keycode::Period) || keys_down.contains_key(&Keycode::KpPeriod) || keys_down.contains_key(&Keycode::Insert) {
@@ -638,10 +721,11 @@ impl SceneState {
             if self.locked || shift_down {
                 if !repeat {
                     self.duration_per_frame = RELAXED_DURATION_PER_FRAME;
-                    self.cursor_transform.transform.rotate = round_up_to_golden(self.cursor_transform.transform.rotate, self.locked && shift_down)
+                    let snap_locked = self.locked && shift_down;
+                    self.rotate_target(|rotate| round_up_to_golden(rotate, snap_locked))
                 }
             } else {
-                self.cursor_transform.transform.rotate += ROT_CONSTANT;
+                self.rotate_target(|rotate| rotate + ROT_CONSTANT);
             }
         }
         if keys_down.contains_key(&Keycode::Comma) || keys_down.contains_key(&Keycode::Kp0) ||  keys_down.contains_key(&Keycode::Delete) {
@@ -649,10 +733,33 @@ impl SceneState {
             if self.locked || shift_down {
                 if !repeat{
                     self.duration_per_frame = RELAXED_DURATION_PER_FRAME;
-                    self.cursor_transform.transform.rotate = round_down_to_golden(self.cursor_transform.transform.rotate, self.locked && shift_down)
+                    let snap_locked = self.locked && shift_down;
+                    self.rotate_target(|rotate| round_down_to_golden(rotate, snap_locked))
+                }
+            } else {
+                self.rotate_target(|rotate| rotate - ROT_CONSTANT);
+            }
+        }
+        if keys_down.contains_key(&Keycode::RightBracket) || keys_down.contains_key(&Keycode::KpPlus) {
+            let shift_down = keys_down.contains_key(&Keycode::LShift) || keys_down.contains_key(&Keycode::RShift);
+            if self.locked || shift_down {
+                if !repeat {
+                    self.duration_per_frame = RELAXED_DURATION_PER_FRAME;
+                    self.grow_cursor(1, true);
+                }
+            } else {
+                self.grow_cursor(1, false);
+            }
+        }
+        if keys_down.contains_key(&Keycode::LeftBracket) || keys_down.contains_key(&Keycode::KpMinus) {
+            let shift_down = keys_down.contains_key(&Keycode::LShift) || keys_down.contains_key(&Keycode::RShift);
+            if self.locked || shift_down {
+                if !repeat {
+                    self.duration_per_frame = RELAXED_DURATION_PER_FRAME;
+                    self.shrink_cursor(1, true);
                 }
             } else {
-                self.cursor_transform.transform.rotate -= ROT_CONSTANT;
+                self.shrink_cursor(1, false);
             }
         }
         if keys_down.contains_key(&Keycode::KpEnter) {
@@ -677,30 +784,228 @@ impl SceneState {
                 self.click();
             }
         }
+        if let Some(index) = self.selected {
+            let before = self.scene_graph.arrangement.get().stamps[index].rect.fill;
+            // picking the color the stamp already has is no step worth undoing
+            if self.color != previous_color && before != self.color {
+                self.scene_graph.arrangement.apply(art_stamps::Edit::Recolor{index, before, after:self.color});
+            }
+        }
+    }
+    // rotates the selected stamp if there is one, otherwise the stamp waiting under the cursor
+    fn rotate_target<F: Fn(f64) -> f64>(&mut self, f: F) {
+        if let Some(index) = self.selected {
+            let before = self.scene_graph.arrangement.get().stamps[index].transform.clone();
+            let mut after = before.clone();
+            after.rotate = f(before.rotate);
+            self.scene_graph.arrangement.apply(art_stamps::Edit::Move{index, before, after});
+        } else {
+            self.cursor_transform.transform.rotate = f(self.cursor_transform.transform.rotate);
+        }
+    }
+    // the selection may no longer exist once the history moves
+    fn undo(&mut self) {
+        if self.scene_graph.arrangement.undo() {
+            self.selected = None;
+        }
+    }
+    fn redo(&mut self) {
+        if self.scene_graph.arrangement.redo() {
+            self.selected = None;
+        }
+    }
+    fn select_at_cursor(&mut self) {
+        let document_point = match art_stamps::itransform(&self.camera_transform,
+                                                          (self.cursor_transform.mouse_x as f64, self.cursor_transform.mouse_y as f64)) {
+            Some(point) => point,
+            None => {
+                self.selected = None;
+                return;
+            },
+        };
+        self.selected = match self.scene_graph.arrangement.get().stamp_at(document_point, &mut self.polygon_cache) {
+            Ok(hit) => hit,
+            Err(err) => {
+                eprintln!("Unable to hit test stamps: {:?}", err);
+                None
+            },
+        };
+        if let Some(index) = self.selected {
+            self.color = self.scene_graph.arrangement.get().stamps[index].rect.fill;
+        }
+    }
+    // moves the selected stamp by however far the document point under the mouse moved
+    fn drag_selected(&mut self, from: (i32, i32), to: (i32, i32)) {
+        let from = art_stamps::itransform(&self.camera_transform, (from.0 as f64, from.1 as f64));
+        let to = art_stamps::itransform(&self.camera_transform, (to.0 as f64, to.1 as f64));
+        if let (Some(index), Some(from), Some(to)) = (self.selected, from, to) {
+            let before = self.scene_graph.arrangement.get().stamps[index].transform.clone();
+            let mut after = before.clone();
+            after.tx += to.0 - from.0;
+            after.ty += to.1 - from.1;
+            self.scene_graph.arrangement.apply(art_stamps::Edit::Move{index, before, after});
+        }
+    }
+    // zooms by steps notches (negative zooms out) keeping the document point under the mouse in place
+    fn zoom_camera(&mut self, steps: i32, snap: bool) {
+        let mouse = (self.cursor_transform.mouse_x as f64, self.cursor_transform.mouse_y as f64);
+        let anchor = art_stamps::itransform(&self.camera_transform, mouse);
+        let mut zoom = self.camera_transform.scalex;
+        if snap {
+            for _ in 0..steps.abs() {
+                zoom = if steps > 0 { round_up_to_power_of_two(zoom) } else { round_down_to_power_of_two(zoom) };
+            }
+        } else {
+            zoom *= ZOOM_CONSTANT.powi(steps);
+        }
+        zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.camera_transform.scalex = zoom;
+        self.camera_transform.scaley = zoom;
+        if let Some(anchor) = anchor {
+            let moved = art_stamps::ftransform(&self.camera_transform, anchor);
+            self.camera_transform.tx += mouse.0 - moved.0;
+            self.camera_transform.ty += mouse.1 - moved.1;
+        }
+    }
+    // frames every placed stamp (or the whole page if there are none) in the window
+    fn fit_camera(&mut self) {
+        let svg = self.scene_graph.arrangement.get();
+        let (lo, hi) = svg.bounds().unwrap_or(((0., 0.), (svg.width as f64, svg.height as f64)));
+        let (width, height) = ((hi.0 - lo.0).max(1.), (hi.1 - lo.1).max(1.));
+        let zoom = (self.window_width as f64 / width).min(self.window_height as f64 / height).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.camera_transform.rotate = 0.;
+        self.camera_transform.scalex = zoom;
+        self.camera_transform.scaley = zoom;
+        self.camera_transform.tx = (self.window_width as f64 - width * zoom) / 2. - lo.0 * zoom;
+        self.camera_transform.ty = (self.window_height as f64 - height * zoom) / 2. - lo.1 * zoom;
+    }
+    fn scale_cursor<F: Fn(f64) -> f64>(&mut self, f: F) {
+        let transform = &mut self.cursor_transform.transform;
+        transform.scalex = f(transform.scalex);
+        transform.scaley = f(transform.scaley);
+    }
+    fn grow_cursor(&mut self, steps: i32, snap: bool) {
+        if snap {
+            for _ in 0..steps {
+                self.scale_cursor(|scale| clamp_stamp_scale(round_up_to_power_of_two(scale)));
+            }
+        } else {
+            self.scale_cursor(|scale| clamp_stamp_scale(scale * SCALE_CONSTANT.powi(steps)));
+        }
+    }
+    fn shrink_cursor(&mut self, steps: i32, snap: bool) {
+        if snap {
+            for _ in 0..steps {
+                self.scale_cursor(|scale| clamp_stamp_scale(round_down_to_power_of_two(scale)));
+            }
+        } else {
+            self.scale_cursor(|scale| clamp_stamp_scale(scale / SCALE_CONSTANT.powi(steps)));
+        }
     }
     fn click(&mut self) {
         self.sub_click();
-        if self.scene_graph.arrangement.svg.stamps.len() == 0 {
+    }
+    // writes the drawing and, next to it, the session sidecar that lets the editor pick up where it left off
+    fn save(&mut self) -> Result<(), String> {
+        let now = time::Instant::now();
+        self.scene_graph.arrangement.autosave.attempted(now);
+        let document = self.scene_graph.arrangement.get().to_string().map_err(
+            |err| format!("{:?}", err))?;
+        let save_file_name = Path::new(&self.save_file_name);
+        if self.scene_graph.arrangement.autosave.backup_due(now) {
+            art_stamps::backup(save_file_name, art_stamps::BACKUP_COUNT).map_err(
+                |err| format!("backing up {}", err))?;
+            self.scene_graph.arrangement.autosave.backed_up(now);
+        }
+        art_stamps::write_atomically(save_file_name, &document).map_err(|err| err.to_string())?;
+        self.scene_graph.arrangement.autosave.saved();
+        self.save_error = None;
+        let session = art_stamps::Session{
+            color: self.color,
+            locked: self.locked,
+            camera: self.camera_transform.clone(),
+            masks: self.mask_transforms.to_vec(),
+        };
+        let session_file_name = art_stamps::session_path(save_file_name);
+        art_stamps::write_atomically(&session_file_name,
+                                     &session.to_string(&self.scene_graph.arrangement.history, &document).map_err(
+                                         |err| format!("{:?}", err))?).map_err(|err| err.to_string())
+    }
+    fn report_save_error(&mut self, err: String) {
+        eprintln!("Unable to save: {}", err);
+        self.save_error = Some(err);
+    }
+    // how long the main loop may sleep before autosave_if_due has something to do
+    fn autosave_due_in(&self) -> Option<time::Duration> {
+        self.scene_graph.arrangement.autosave.due_in(time::Instant::now())
+    }
+    fn autosave_if_due(&mut self) {
+        if self.scene_graph.arrangement.autosave.is_due(time::Instant::now()) {
+            if let Err(err) = self.save() {
+                self.report_save_error(err);
+            }
+        }
+    }
+    fn title(&self) -> String {
+        let mut ret = self.save_file_name.clone();
+        if let Some(active_stamp) = self.active_stamp {
+            if let Some(info) = self.library.get(&self.scene_graph.inventory[active_stamp].stamp_name) {
+                ret += &format!(" - {} ({})", info.name, info.category);
+            }
+        }
+        if let Some(err) = &self.save_error {
+            ret += &format!(" - NOT SAVED: {}", err);
+        }
+        ret
+    }
+    // document is the drawing as it was loaded; a missing or unreadable sidecar just leaves the defaults
+    fn restore_session(&mut self, document: &str) {
+        let session_file_name = art_stamps::session_path(Path::new(&self.save_file_name));
+        if !session_file_name.exists() {
             return;
         }
-        write_from_string(Path::new(&self.save_file_name),
-                          &self.scene_graph.arrangement.get().to_string().map_err(
-                              |err| format!("{:?}", err)).unwrap()).map_err(
-            |err| format!("{:?}", err)).unwrap();
+        let restored = read_to_string(&session_file_name).map_err(|err| format!("{:?}", err)).and_then(
+            |data| art_stamps::Session::from_str(&data, document, HISTORY_LIMIT).map_err(|err| format!("{:?}", err)));
+        match restored {
+            Ok((session, history)) => {
+                self.color = session.color;
+                self.locked = session.locked;
+                self.camera_transform = session.camera;
+                for (mask, restored_mask) in self.mask_transforms.iter_mut().zip(session.masks) {
+                    *mask = restored_mask;
+                }
+                self.scene_graph.arrangement.history = history;
+            },
+            Err(err) => eprintln!("Ignoring session {}: {}", session_file_name.display(), err),
+        }
     }
     fn sub_click(&mut self) {
         if let Some(hit) = self.scene_graph.hit_test(self.cursor_transform.mouse_x,
                                                      self.cursor_transform.mouse_y) {
             self.active_stamp = Some(hit.stamp_index);
             self.stamp_used = false;
+            let (scalex, scaley) = (self.cursor_transform.transform.scalex, self.cursor_transform.transform.scaley);
             self.cursor_transform.transform = art_stamps::Transform::new(hit.stamp_source.width(),
                                                                      hit.stamp_source.height());
+            // keep the chosen size when swapping to another stamp
+            self.cursor_transform.transform.scalex = scalex;
+            self.cursor_transform.transform.scaley = scaley;
             self.cursor_transform.transform.rotate += hit.rot_delta;
+            self.select_mode = false;
+            self.selected = None;
+        } else if self.select_mode {
+            self.select_at_cursor();
         } else if let Some(active_stamp) = self.active_stamp{ // draw the stamp
             let mut transform = self.cursor_transform.transform.clone();
             transform.rotate -= self.camera_transform.rotate;
-            transform.tx = self.mouse_lock_x(self.cursor_transform.mouse_x) as f64 - self.cursor_transform.transform.midx - self.camera_transform.tx;
-            transform.ty = self.mouse_lock_y(self.cursor_transform.mouse_y) as f64 - self.cursor_transform.transform.midy - self.camera_transform.ty;
+            let screen_center = (self.mouse_lock_x(self.cursor_transform.mouse_x) as f64,
+                                 self.mouse_lock_y(self.cursor_transform.mouse_y) as f64);
+            let document_center = match art_stamps::itransform(&self.camera_transform, screen_center) {
+                Some(center) => center,
+                None => return, // no telling where on the page a flattened camera points
+            };
+            transform.tx = document_center.0 - self.cursor_transform.transform.midx;
+            transform.ty = document_center.1 - self.cursor_transform.transform.midy;
             let new_item_url = self.scene_graph.inventory[active_stamp].stamp_name.clone();
             // add clip mask
             let mut any_intersect = false;
@@ -710,43 +1015,22 @@ impl SceneState {
                 }
             }
             let clip_mask;
+            let mut edits = Vec::new();
             if any_intersect {
-                let mut points = Vec::<art_stamps::F64Point>::new();
-                points.push((-transform.midx * 4.,-transform.midy * 4.));
-                points.push((transform.midx * 4.,-transform.midy * 4.));
-                points.push((transform.midx * 4., transform.midy * 4.));
-                points.push((-transform.midx * 4., transform.midy * 4.));
-                points.push((-transform.midx * 4.,-transform.midy * 4.));
-               let ret_location = (-transform.midx * 4.,-transform.midy * 4.);
-                for mask in self.mask_transforms.iter() {
-                    use art_stamps::ftransform;
-                    use art_stamps::itransform;
-                    points.push(itransform(&transform, ftransform(mask, (0.,0.))));
-                    points.push(itransform(&transform, ftransform(mask, (mask.midx * 2.,0.))));
-                    points.push(itransform(&transform, ftransform(mask, (mask.midx * 2.,mask.midy * 2.))));
-                    points.push(itransform(&transform, ftransform(mask, (0.,mask.midy  * 2.))));
-                    points.push(itransform(&transform, ftransform(mask, (0.,0.))));
-                    points.push(ret_location.clone());
-                }
-                let index = self.scene_graph.arrangement.get_mut().defs.clipPath.len();
+                let index = self.scene_graph.arrangement.get().defs.clipPath.len();
                 let id = format!("{}", index);
                 clip_mask = "url(#".to_string() + &id + ")";
-                self.scene_graph.arrangement.get_mut().defs.clipPath.push(art_stamps::ClipPath{
-                    id:id,
-                    polygon:Polygon{
-                        points:points,
-                    },
-                });
+                edits.push(art_stamps::Edit::AddClipPath(art_stamps::ClipPath::from_masks(id, &transform, &self.mask_transforms)));
             } else {
                 clip_mask = String::new();
             }
             // end add clip mask
-            self.scene_graph.arrangement.get_mut().add(
-                transform,
-                new_item_url,
-                clip_mask,
-                self.color,
-            );
+            edits.push(art_stamps::Edit::Insert{
+                index:self.scene_graph.arrangement.get().stamps.len(),
+                stamp:art_stamps::g::new(transform, new_item_url, clip_mask, self.color),
+            });
+            self.scene_graph.arrangement.apply(art_stamps::Edit::Batch(edits));
+            self.scene_graph.arrangement.history.seal();
             self.stamp_used = true;
         }
     }
@@ -764,11 +1048,15 @@ fn process(state: &mut SceneState, images: &mut Images, event: sdl2::event::Even
     let mut key_encountered = false;
     match event {
         Event::Quit{..} => {
-            write_from_string(Path::new(&state.save_file_name),
-                              &state.scene_graph.arrangement.get().to_string().map_err(
-                                  |err| format!("{:?}", err))?).map_err(
-                |err| format!("{:?}", err))?;
-            return Err("Exit".to_string())
+            match state.save() {
+                Ok(()) => return Err("Exit".to_string()),
+                // a second close after a failure is taken as the user accepting the loss
+                Err(err) => if state.save_error.is_some() {
+                    return Err(err)
+                } else {
+                    state.report_save_error(err)
+                },
+            }
         },
         Event::KeyDown {keycode: Option::Some(key_code), ..} =>{
             let repeat;
@@ -787,14 +1075,35 @@ fn process(state: &mut SceneState, images: &mut Images, event: sdl2::event::Even
         Event::KeyUp {keycode: Option::Some(key_code), ..} =>
         {
             state.last_return_mouse = None; // other keypresses clear this
+            state.scene_graph.arrangement.history.seal();
             keys_down.remove(&key_code);
         },
         Event::MouseButtonDown {x, y, ..} => {
             state.cursor_transform.mouse_x = x;
             state.cursor_transform.mouse_y = y;
             state.click();
+            state.dragging = state.select_mode && state.selected.is_some();
+        }
+        Event::MouseButtonUp {..} if state.dragging => {
+            state.dragging = false;
+            state.scene_graph.arrangement.history.seal();
         }
-        Event::MouseMotion {x, y, ..} => {
+        Event::MouseWheel {y, ..} => {
+            let shift_down = keys_down.contains_key(&Keycode::LShift) || keys_down.contains_key(&Keycode::RShift);
+            let ctrl_down = keys_down.contains_key(&Keycode::LCtrl) || keys_down.contains_key(&Keycode::RCtrl);
+            let snap = state.locked || shift_down;
+            if ctrl_down {
+                state.zoom_camera(y, snap);
+            } else if y > 0 {
+                state.grow_cursor(y, snap);
+            } else if y < 0 {
+                state.shrink_cursor(-y, snap);
+            }
+        }
+        Event::MouseMotion {x, y, xrel, yrel, ..} => {
+            if state.dragging {
+                state.drag_selected((x - xrel, y - yrel), (x, y));
+            }
             state.cursor_transform.mouse_x = x;
             state.cursor_transform.mouse_y = y;
             state.clear_cursor_if_stamp_used();
@@ -814,20 +1123,29 @@ fn process(state: &mut SceneState, images: &mut Images, event: sdl2::event::Even
     Ok(key_encountered)
 }
 
-fn process_dir<F: FnMut(&fs::DirEntry) -> Result<(), io::Error>>(dir: &Path, cb: &mut F) -> Result<(), io::Error> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            process_dir(&path, cb)?;
-        } else {
-            cb(&entry)?;
-        }
+// the stamp's bitmap, or one drawn from its outline when only the svg has been authored
+fn load_stamp_surface(info: &art_stamps::StampInfo, assets: &AssetResolver) -> Result<Surface<'static>, String> {
+    let bitmap = assets.resolve(&info.bitmap);
+    if bitmap.is_file() {
+        return Surface::load_bmp(bitmap);
     }
-    Ok(())
+    let outline = read_to_string(&assets.resolve(&info.outline)).map_err(|err| format!("{}: {}", info.outline, err))?;
+    let raster = art_stamps::render_stamp(&outline, STAMP_BITMAP_SIZE, STAMP_BITMAP_SIZE).map_err(
+        |err| format!("{}: {}", info.outline, err))?;
+    // ABGR8888 packs each pixel as R, G, B, A bytes on little endian machines, the order Raster keeps them in
+    let mut surface = Surface::new(raster.width, raster.height, PixelFormatEnum::ABGR8888)?;
+    let pitch = surface.pitch() as usize;
+    let row_bytes = raster.width as usize * 4;
+    surface.with_lock_mut(|data: &mut [u8]| {
+        for (y, row) in raster.pixels.chunks(row_bytes).enumerate() {
+            data[y * pitch..y * pitch + row_bytes].copy_from_slice(row);
+        }
+    });
+    Ok(surface)
 }
 
-pub fn run(mut svg: SVG, save_file_name: &str, dir: &Path, width:u32, height:u32) -> Result<(), String> {
+pub fn run(mut svg: SVG, save_file_name: &str, assets: &AssetResolver, library: art_stamps::Library, width:u32, height:u32) -> Result<(), String> {
+    let assets = &library.search_roots(assets);
     let sdl_context = Box::new(sdl2::init()?);
     let video_subsystem = Box::new(sdl_context.video()?);
     //let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
@@ -839,10 +1157,12 @@ pub fn run(mut svg: SVG, save_file_name: &str, dir: &Path, width:u32, height:u32
     let wsize = window.size();
     let mut canvas = Box::new(window.into_canvas().software().build().map_err(|e| e.to_string())?);
     let mut keys_down = Box::new(HashMap::<Keycode, ()>::new());
-    let surface = Surface::load_bmp(dir.join("cursor.bmp"))
+    let surface = Surface::load_bmp(assets.resolve("cursor.bmp"))
         .map_err(|err| format!("failed to load cursor image: {}", err))?;
+    library.register_outlines(&mut svg);
+    let loaded_document = svg.to_string().map_err(|err| format!("{:?}", err))?;
     svg.resize(wsize.0, wsize.1);
-    let mask_surface_path = dir.join("mask.bmp");
+    let mask_surface_path = assets.resolve("mask.bmp");
     let mask_surface_name = mask_surface_path.to_str().unwrap().to_string();
     let mask_surface = Surface::load_bmp(mask_surface_path)
         .map_err(|err| format!("Failed to load mask paper image: {}", err))?;
@@ -873,11 +1193,18 @@ pub fn run(mut svg: SVG, save_file_name: &str, dir: &Path, width:u32, height:u32
         window_height: canvas.viewport().height(),
         color:art_stamps::Color{r:0,g:0,b:0},
         locked:false,
+        select_mode:false,
+        selected:None,
+        dragging:false,
+        polygon_cache:HashMap::new(),
+        save_error:None,
+        library,
     });
     scene_state.mask_transforms[0].tx = 10.0 - scene_state.mask_transforms[0].midx * 2.0;
     scene_state.mask_transforms[1].tx = 0.0;
     scene_state.mask_transforms[1].ty = 10.0 - scene_state.mask_transforms[0].midy * 2.0;
-    let cursor_surface_path = dir.join("cursor.bmp");
+    scene_state.restore_session(&loaded_document);
+    let cursor_surface_path = assets.resolve("cursor.bmp");
     let cursor_surface_name = cursor_surface_path.to_str().unwrap().to_string();
     let cursor_surface = Surface::load_bmp(cursor_surface_path)
         .map_err(|err| format!("failed to load cursor image: {}", err))?;
@@ -889,14 +1216,13 @@ pub fn run(mut svg: SVG, save_file_name: &str, dir: &Path, width:u32, height:u32
         stamps:Vec::new(),
         max_selectable_stamp:0,
     });
-    process_dir(&dir.join("stamps"), &mut |p:&fs::DirEntry| {
-        let stamp_surface = Surface::load_bmp(p.path()).map_err(
-            |err| io::Error::new(io::ErrorKind::Other, format!("{}: {}", p.path().to_str().unwrap_or("??"), err)))?;
-        images.stamps.push(make_texture_surface!(texture_creator, stamp_surface, p.path().to_str().unwrap().to_string()).map_err(
-            |err| io::Error::new(io::ErrorKind::Other, format!("{}: {}", p.path().to_str().unwrap_or("?X?"), err)))?);
+    for info in scene_state.library.sorted_by_category() {
+        let stamp_surface = load_stamp_surface(info, assets).map_err(
+            |err| format!("Failed to load stamp {}: {}", info.bitmap, err))?;
+        images.stamps.push(make_texture_surface!(texture_creator, stamp_surface, info.bitmap.clone()).map_err(
+            |err| format!("Failed to load stamp {}: {}", info.bitmap, err))?);
         images.max_selectable_stamp += 1;
-        Ok(())
-    }).map_err(|err| format!("Failed to load stamp {}", err))?;
+    }
     //images.stamps.push(make_texture_surface!(texture_creator, xcursor_surface)?);
     scene_state.cursor.set();
     scene_state.compute_stamps_location(canvas.viewport(), &images);
@@ -1017,6 +1343,11 @@ fn main_loop<'a>(sdl_context: &sdl2::Sdl, scene_state: &mut SceneState, canvas:
                 process(scene_state, images, event, keys_down)?;
                 break;
             }
+        } else if let Some(timeout) = scene_state.autosave_due_in() {
+            // wake up in time to autosave even if nothing else happens
+            if let Some(event) = events.wait_event_timeout(timeout.as_millis() as u32 + 1) {
+                process(scene_state, images, event, keys_down)?;
+            }
         } else {
             for event in events.wait_iter() {
                 process(scene_state, images, event, keys_down)?;
@@ -1026,37 +1357,185 @@ fn main_loop<'a>(sdl_context: &sdl2::Sdl, scene_state: &mut SceneState, canvas:
         scene_state.scene_graph.prepare_textures(&texture_creator, images)?;
         scene_state.render(canvas, images)?;
     };
+    scene_state.autosave_if_due();
+    let title = scene_state.title();
+    if canvas.window().title() != title {
+        canvas.window_mut().set_title(&title).map_err(|err| format!("{:?}", err))?;
+    }
     Ok(())
 }
-fn write_from_string(filename: &Path, s: &String) -> Result<(), io::Error> {
-    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).open(filename)?;
-    file.write(s.as_bytes())?;
-    Ok(())
-}
-
 fn read_to_string(filename: &Path) ->  Result<String, io::Error> {
     let mut f = fs::File::open(filename)?;
     let mut buffer = String::new();
     f.read_to_string(&mut buffer)?;
     Ok(buffer)
 }
-fn main() -> Result<(), String> {
-    let mut args: Vec<_> = env::args().collect();
+// the stamps in each asset root followed by each extra stamp pack manifest. earlier roots override
+// ids they share with later ones, and packs override them all
+fn open_library(assets: &AssetResolver, packs: &[String]) -> Result<art_stamps::Library, String> {
+    let mut library = art_stamps::Library::default();
+    for dir in assets.roots.iter().rev().filter(|dir| dir.is_dir()) {
+        library.extend(art_stamps::Library::open(dir).map_err(|err| format!("{}: {}", dir.display(), err))?);
+    }
+    for pack in packs {
+        library.extend(art_stamps::Library::load(Path::new(pack)).map_err(|err| format!("{}: {}", pack, err))?.as_pack());
+    }
+    Ok(library)
+}
+
+fn load_svg(filename: &str, assets: &AssetResolver) -> Result<SVG, String> {
+    let file_data = read_to_string(Path::new(filename)).map_err(
+        |err| format!("{}: {}", filename, err))?;
+    let mut svg = SVG::from_str(&file_data).map_err(|err| format!("{}: {}", filename, err))?;
+    svg.use_assets(assets.clone());
+    // outside an asset directory the outlines are found by the naming convention alone
+    if let Ok(library) = open_library(assets, &[]) {
+        library.register_outlines(&mut svg);
+    }
+    Ok(svg)
+}
+
+fn render_command(args: &[String], assets: &AssetResolver) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err("Usage: art-stamps render /path/to/drawing.svg /path/to/output.png".to_string());
+    }
+    let svg = load_svg(&args[0], assets)?;
+    art_stamps::render_png(&svg, Path::new(&args[1])).map_err(|err| format!("{}: {}", args[1], err))
+}
+
+fn export_command(args: &[String], assets: &AssetResolver) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err("Usage: art-stamps export /path/to/drawing.svg /path/to/output.svg".to_string());
+    }
+    let svg = load_svg(&args[0], assets)?;
+    art_stamps::save_portable(&svg, Path::new(&args[1])).map_err(|err| format!("{}: {}", args[1], err))
+}
+
+fn plot_command(args: &[String], assets: &AssetResolver) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err("Usage: art-stamps plot /path/to/drawing.svg /path/to/output.svg".to_string());
+    }
+    let svg = load_svg(&args[0], assets)?;
+    art_stamps::save_plot(&svg, Path::new(&args[1])).map_err(|err| format!("{}: {}", args[1], err))
+}
+
+fn validate_command(args: &[String], assets: &AssetResolver) -> Result<(), String> {
+    if args.is_empty() {
+        return Err("Usage: art-stamps validate /path/to/drawing.svg...".to_string());
+    }
+    let mut num_problems = 0;
+    for filename in args {
+        let svg = match load_svg(filename, assets) {
+            Ok(svg) => svg,
+            Err(err) => {
+                eprintln!("{}", err);
+                num_problems += 1;
+                continue;
+            },
+        };
+        let mut checked = std::collections::BTreeSet::<&str>::new();
+        for (index, stamp) in svg.stamps.iter().enumerate() {
+            let url = &stamp.rect.href.url;
+            if checked.insert(url) {
+                for asset in &[svg.asset_path(url), PathBuf::from(svg.outline_path(url))] {
+                    if !asset.is_file() {
+                        eprintln!("{}: stamp {} references missing asset {}", filename, index, asset.display());
+                        num_problems += 1;
+                    }
+                }
+            }
+            let clip = &stamp.rect.href.clip;
+            if !clip.is_empty() && svg.clip_path(clip).is_none() {
+                eprintln!("{}: stamp {} references unknown clip-path {}", filename, index, clip);
+                num_problems += 1;
+            }
+        }
+    }
+    if num_problems != 0 {
+        return Err(format!("{} problem(s) found", num_problems));
+    }
+    Ok(())
+}
+
+fn info_command(args: &[String], assets: &AssetResolver) -> Result<(), String> {
+    if args.len() != 1 {
+        return Err("Usage: art-stamps info /path/to/drawing.svg".to_string());
+    }
+    let svg = load_svg(&args[0], assets)?;
+    let mut stamp_counts = std::collections::BTreeMap::<&str, usize>::new();
+    let mut color_counts = std::collections::BTreeMap::<String, usize>::new();
+    for stamp in svg.stamps.iter() {
+        *stamp_counts.entry(&stamp.rect.href.url).or_insert(0) += 1;
+        *color_counts.entry(stamp.rect.fill.to_string()).or_insert(0) += 1;
+    }
+    let extent = svg.extent();
+    println!("size: {}x{} (extent {}x{})", svg.width, svg.height, extent.0, extent.1);
+    if let Some((lo, hi)) = svg.bounds() {
+        println!("bounds: {},{} - {},{}", lo.0, lo.1, hi.0, hi.1);
+    }
+    println!("clip paths: {}", svg.defs.clipPath.len());
+    println!("stamps: {}", svg.stamps.len());
+    for (url, count) in stamp_counts.iter() {
+        println!("  {:5} {}", count, url);
+    }
+    println!("colors: {}", color_counts.len());
+    for (color, count) in color_counts.iter() {
+        println!("  {:5} {}", count, color);
+    }
+    Ok(())
+}
+
+// draws each library stamp's bitmap from its outline: the missing ones, or with --all every one
+fn bitmaps_command(args: &[String], assets: &AssetResolver) -> Result<(), String> {
+    let mut redraw_all = false;
+    let mut packs = Vec::new();
+    for arg in args {
+        if arg == "--all" {
+            redraw_all = true;
+        } else if let Some(pack) = arg.strip_prefix("--library=") {
+            packs.push(pack.to_string());
+        } else {
+            return Err("Usage: art-stamps bitmaps [--all] [--library=/path/to/stamps.xml]...".to_string());
+        }
+    }
+    let library = open_library(assets, &packs)?;
+    let assets = library.search_roots(assets);
+    for info in library.stamps.iter() {
+        let bitmap = assets.resolve(&info.bitmap);
+        if bitmap.is_file() && !redraw_all {
+            continue;
+        }
+        let outline = read_to_string(&assets.resolve(&info.outline)).map_err(|err| format!("{}: {}", info.outline, err))?;
+        let raster = art_stamps::render_stamp(&outline, STAMP_BITMAP_SIZE, STAMP_BITMAP_SIZE).map_err(
+            |err| format!("{}: {}", info.outline, err))?;
+        if let Some(dir) = bitmap.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        raster.save_bmp(&bitmap).map_err(|err| format!("{}: {}", info.bitmap, err))?;
+        println!("{} <- {}", bitmap.display(), info.outline);
+    }
+    Ok(())
+}
+
+fn edit_command(mut args: Vec<String>, assets: &AssetResolver) -> Result<(), String> {
     let mut fnargs = args.clone();
     fnargs.clear();
     let mut width = 800;
     let mut height = 600;
+    let mut packs = Vec::new();
     for arg in &mut args {
         if arg.starts_with("--width=") {
             for item in arg.rsplit('=') {
-                width = item.parse::<u32>().unwrap();
+                width = item.parse::<u32>().map_err(|err| format!("{}: {}", arg, err))?;
                 break;
             }
         } else if arg.starts_with("--height=") {
             for item in arg.rsplit('=') {
-                height = item.parse::<u32>().unwrap();
+                height = item.parse::<u32>().map_err(|err| format!("{}: {}", arg, err))?;
                 break;
             }            
+        } else if let Some(pack) = arg.strip_prefix("--library=") {
+            packs.push(pack.to_string());
         } else {
             fnargs.push(std::mem::replace(arg, String::new()));
         }
@@ -1076,13 +1555,15 @@ fn main() -> Result<(), String> {
             } else {
                 SVG::from_str(&data).unwrap()
             }
-        } else */if let Ok(file_data) = read_to_string(save_file_name) {
-            SVG::from_str(&file_data).unwrap()
+        } else */if save_file_name.exists() {
+            load_svg(&fnargs[1], assets)?
         } else {
-            SVG::new(1024,768)
-                
+            let mut svg = SVG::new(1024,768);
+            svg.use_assets(assets.clone());
+            svg
         };
-        let ret = run(svg, &fnargs[1], Path::new("assets"), width, height);
+        let library = open_library(assets, &packs)?;
+        let ret = run(svg, &fnargs[1], assets, library, width, height);
         //safe{loop{g_cb(g_arg);if g_stop{ break;}}}
         match ret {
             Err(x) => {
@@ -1096,3 +1577,29 @@ fn main() -> Result<(), String> {
         }
     }
 }
+
+fn main() {
+    // --assets=/path/to/assets works with every command, and may be given more than once
+    let mut asset_flags = Vec::new();
+    let mut args = Vec::new();
+    for arg in env::args() {
+        match arg.strip_prefix("--assets=") {
+            Some(dir) => asset_flags.push(dir.to_string()),
+            None => args.push(arg),
+        }
+    }
+    let assets = AssetResolver::from_env(&asset_flags);
+    let ret = match args.get(1).map(|arg| arg.as_str()) {
+        Some("render") => render_command(&args[2..], &assets),
+        Some("export") => export_command(&args[2..], &assets),
+        Some("plot") => plot_command(&args[2..], &assets),
+        Some("validate") => validate_command(&args[2..], &assets),
+        Some("info") => info_command(&args[2..], &assets),
+        Some("bitmaps") => bitmaps_command(&args[2..], &assets),
+        _ => edit_command(args, &assets),
+    };
+    if let Err(err) = ret {
+        eprintln!("art-stamps: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,305 @@
+// boolean operations on polygons given as lists of rings, read with the even-odd rule.
+// every edge of both operands is split where it meets another, each piece is kept if the
+// result differs on its two sides, and the kept pieces are chained back into rings that have
+// the result on their left: outer boundaries and holes wind opposite ways
+use std::collections::{BTreeSet, HashMap};
+use std::vec::Vec;
+use super::polygonsvg::{F64Point, point_in_polygon, add2d, sub2d, scale2d};
+
+pub type Ring = Vec<F64Point>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    Difference, // a less b
+    Xor,
+}
+
+impl Operation {
+    fn apply(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Operation::Union => in_a || in_b,
+            Operation::Intersection => in_a && in_b,
+            Operation::Difference => in_a && !in_b,
+            Operation::Xor => in_a != in_b,
+        }
+    }
+}
+
+// whether p is inside an odd number of the rings
+pub fn contains(rings: &[Ring], p: F64Point) -> bool {
+    let mut ret = false;
+    for ring in rings {
+        if point_in_polygon(p, ring) {
+            ret = !ret;
+        }
+    }
+    ret
+}
+
+pub fn union(a: &[Ring], b: &[Ring]) -> Vec<Ring> {
+    combine(a, b, Operation::Union)
+}
+pub fn intersection(a: &[Ring], b: &[Ring]) -> Vec<Ring> {
+    combine(a, b, Operation::Intersection)
+}
+pub fn difference(a: &[Ring], b: &[Ring]) -> Vec<Ring> {
+    combine(a, b, Operation::Difference)
+}
+pub fn xor(a: &[Ring], b: &[Ring]) -> Vec<Ring> {
+    combine(a, b, Operation::Xor)
+}
+
+// twice the signed area, positive when the ring turns the same way as (0,0) (1,0) (0,1)
+pub fn signed_area(ring: &[F64Point]) -> f64 {
+    let mut ret = 0.;
+    for (index, a) in ring.iter().enumerate() {
+        let b = ring[(index + 1) % ring.len()];
+        ret += a.0 * b.1 - b.0 * a.1;
+    }
+    ret
+}
+
+fn cross(a: F64Point, b: F64Point) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn length(a: F64Point) -> f64 {
+    (a.0 * a.0 + a.1 * a.1).sqrt()
+}
+
+// merges points closer than the tolerance so that pieces split from different edges meet exactly
+struct Vertices {
+    tolerance: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    points: Vec<F64Point>,
+}
+
+impl Vertices {
+    fn cell(&self, p: F64Point) -> (i64, i64) {
+        ((p.0 / self.tolerance).floor() as i64, (p.1 / self.tolerance).floor() as i64)
+    }
+    fn insert(&mut self, p: F64Point) -> usize {
+        let (cx, cy) = self.cell(p);
+        for x in cx - 1..cx + 2 {
+            for y in cy - 1..cy + 2 {
+                if let Some(indices) = self.cells.get(&(x, y)) {
+                    for index in indices {
+                        if length(sub2d(self.points[*index], p)) <= self.tolerance {
+                            return *index;
+                        }
+                    }
+                }
+            }
+        }
+        self.points.push(p);
+        self.cells.entry((cx, cy)).or_default().push(self.points.len() - 1);
+        self.points.len() - 1
+    }
+}
+
+fn edges(rings: &[Ring], ret: &mut Vec<(F64Point, F64Point)>) {
+    for ring in rings {
+        if ring.len() < 3 {
+            continue;
+        }
+        for (index, a) in ring.iter().enumerate() {
+            let b = ring[(index + 1) % ring.len()];
+            if *a != b {
+                ret.push((*a, b));
+            }
+        }
+    }
+}
+
+// parameters along e where f crosses it or one of f's ends touches it
+fn split_parameters(e: (F64Point, F64Point), f: (F64Point, F64Point), tolerance: f64, ret: &mut Vec<f64>) {
+    let d = sub2d(e.1, e.0);
+    let len2 = d.0 * d.0 + d.1 * d.1;
+    for end in &[f.0, f.1] {
+        let t = ((end.0 - e.0 .0) * d.0 + (end.1 - e.0 .1) * d.1) / len2;
+        if t > 0. && t < 1. && length(sub2d(*end, add2d(e.0, scale2d(d, t)))) <= tolerance {
+            ret.push(t);
+        }
+    }
+    let g = sub2d(f.1, f.0);
+    let denominator = cross(d, g);
+    if denominator.abs() <= 1.0e-12 * len2.sqrt() * length(g) {
+        return; // parallel: any overlap was found through the ends above
+    }
+    let offset = sub2d(f.0, e.0);
+    let t = cross(offset, g) / denominator;
+    let u = cross(offset, d) / denominator;
+    if t > 0. && t < 1. && (0. ..=1.).contains(&u) {
+        ret.push(t);
+    }
+}
+
+pub fn combine(a: &[Ring], b: &[Ring], operation: Operation) -> Vec<Ring> {
+    let mut all_edges = Vec::new();
+    edges(a, &mut all_edges);
+    edges(b, &mut all_edges);
+    if all_edges.is_empty() {
+        return Vec::new();
+    }
+    let mut extent = 0f64;
+    for edge in &all_edges {
+        extent = extent.max(edge.0 .0.abs()).max(edge.0 .1.abs());
+    }
+    let tolerance = (extent + 1.) * 1.0e-9;
+    let mut vertices = Vertices{tolerance, cells: HashMap::new(), points: Vec::new()};
+    let mut pieces = BTreeSet::<(usize, usize)>::new();
+    let mut ts = Vec::new();
+    for (index, e) in all_edges.iter().enumerate() {
+        ts.clear();
+        ts.push(0.);
+        ts.push(1.);
+        for (other_index, f) in all_edges.iter().enumerate() {
+            if other_index != index {
+                split_parameters(*e, *f, tolerance, &mut ts);
+            }
+        }
+        ts.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        let d = sub2d(e.1, e.0);
+        let mut previous = vertices.insert(e.0);
+        for t in &ts[1..] {
+            let current = vertices.insert(if *t == 1. { e.1 } else { add2d(e.0, scale2d(d, *t)) });
+            if current != previous {
+                pieces.insert((previous.min(current), previous.max(current)));
+            }
+            previous = current;
+        }
+    }
+    // keep the pieces the result lies on exactly one side of, pointing so it is on the left
+    let nudge = tolerance * 64.;
+    let mut kept = Vec::new();
+    for &(i, j) in &pieces {
+        let (p, q) = (vertices.points[i], vertices.points[j]);
+        let d = sub2d(q, p);
+        let normal = scale2d((-d.1, d.0), nudge / length(d));
+        let middle = scale2d(add2d(p, q), 0.5);
+        let left = add2d(middle, normal);
+        let right = sub2d(middle, normal);
+        let in_left = operation.apply(contains(a, left), contains(b, left));
+        let in_right = operation.apply(contains(a, right), contains(b, right));
+        if in_left != in_right {
+            kept.push(if in_left { (i, j) } else { (j, i) });
+        }
+    }
+    chain(&vertices.points, &kept)
+}
+
+// joins directed pieces into closed rings, taking the sharpest left turn where several leave one vertex
+fn chain(points: &[F64Point], pieces: &[(usize, usize)]) -> Vec<Ring> {
+    let mut outgoing = HashMap::<usize, Vec<usize>>::new();
+    for (index, piece) in pieces.iter().enumerate() {
+        outgoing.entry(piece.0).or_default().push(index);
+    }
+    let mut used = vec![false; pieces.len()];
+    let mut ret = Vec::new();
+    for start in 0..pieces.len() {
+        if used[start] {
+            continue;
+        }
+        let mut ring = Vec::new();
+        let mut current = start;
+        loop {
+            used[current] = true;
+            let (from, to) = pieces[current];
+            ring.push(points[from]);
+            if to == pieces[start].0 {
+                break;
+            }
+            let incoming = sub2d(points[to], points[from]);
+            let mut best: Option<(f64, usize)> = None;
+            if let Some(candidates) = outgoing.get(&to) {
+                for candidate in candidates {
+                    if used[*candidate] {
+                        continue;
+                    }
+                    let direction = sub2d(points[pieces[*candidate].1], points[to]);
+                    let turn = cross(incoming, direction).atan2(incoming.0 * direction.0 + incoming.1 * direction.1);
+                    match best {
+                        Some((best_turn, _)) if best_turn >= turn => {},
+                        _ => best = Some((turn, *candidate)),
+                    }
+                }
+            }
+            match best {
+                Some((_, next)) => current = next,
+                None => break, // an open chain only comes from numerical trouble; keep what closed
+            }
+        }
+        let ring = simplify(ring);
+        if ring.len() >= 3 {
+            ret.push(ring);
+        }
+    }
+    ret
+}
+
+// drops vertices that lie on the line through their neighbours
+fn simplify(mut ring: Ring) -> Ring {
+    let mut changed = true;
+    while changed && ring.len() >= 3 {
+        changed = false;
+        let mut index = 0;
+        while index < ring.len() && ring.len() >= 3 {
+            let previous = ring[(index + ring.len() - 1) % ring.len()];
+            let next = ring[(index + 1) % ring.len()];
+            let d = sub2d(next, previous);
+            let scale = length(d).max(1.0e-12);
+            if (cross(d, sub2d(ring[index], previous)) / scale).abs() <= 1.0e-9 * (1. + scale) {
+                ring.remove(index);
+                changed = true;
+            } else {
+                index += 1;
+            }
+        }
+    }
+    ring
+}
+
+#[cfg(test)]
+mod test {
+    use super::{union, intersection, difference, xor, contains, signed_area, Ring};
+
+    fn square(x: f64, y: f64, size: f64) -> Ring {
+        vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+    }
+    fn area(rings: &[Ring]) -> f64 {
+        rings.iter().map(|ring| signed_area(ring)).sum::<f64>().abs() / 2.
+    }
+
+    #[test]
+    fn test_boolean_operations() {
+        let a = vec![square(0., 0., 10.)];
+        let b = vec![square(5., 5., 10.)];
+        let joined = union(&a, &b);
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined[0].len(), 8);
+        assert!((area(&joined) - 175.).abs() < 1.0e-9);
+        let common = intersection(&a, &b);
+        assert_eq!(common.len(), 1);
+        assert!((area(&common) - 25.).abs() < 1.0e-9);
+        assert!((area(&difference(&a, &b)) - 75.).abs() < 1.0e-9);
+        let either = xor(&a, &b);
+        assert!(contains(&either, (2., 2.)) && contains(&either, (12., 12.)));
+        assert!(!contains(&either, (7., 7.)) && !contains(&either, (20., 20.)));
+
+        // cutting a window out of the middle leaves an outer ring and a hole winding the other way
+        let holed = difference(&a, &vec![square(3., 3., 4.)]);
+        assert_eq!(holed.len(), 2);
+        assert!(signed_area(&holed[0]) * signed_area(&holed[1]) < 0.);
+        assert!((area(&holed) - 84.).abs() < 1.0e-9);
+        assert!(!contains(&holed, (5., 5.)) && contains(&holed, (1., 5.)));
+
+        // squares sharing an edge merge without a seam, ones apart stay apart
+        let merged = union(&a, &vec![square(10., 0., 10.)]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].len(), 4);
+        assert_eq!(union(&a, &vec![square(20., 0., 10.)]).len(), 2);
+        assert!(intersection(&a, &vec![square(20., 0., 10.)]).is_empty());
+    }
+}
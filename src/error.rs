@@ -0,0 +1,132 @@
+// what can go wrong reading, writing or colliding with a drawing, so callers can tell a missing
+// stamp from a typo in a hand edited transform from a file that isn't xml at all
+use std::path::{Path, PathBuf};
+use std::fmt;
+use std::io;
+use super::serde_xml_rs;
+use super::serde_xml_rs::ErrorKind;
+
+#[derive(Debug)]
+pub enum Error {
+    Io{path: PathBuf, error: io::Error},
+    Output(io::Error), // writing a document out failed
+    Xml{position: Option<(u64, u64)>, message: String}, // line and column, counted from 1
+    Transform(String),
+    Points(String),
+    Color(String),
+    PathData(String),
+    MissingAsset(PathBuf),
+    UnknownClipPath(String), // the clip-path="url(#id)" reference no clipPath answers to
+    Invalid(String), // well formed, but not something we can use, like a stamp id listed twice
+}
+
+// serde hands a deserializer's error back as nothing but its message, so the attribute parsers
+// report through these prefixes and From<serde_xml_rs::Error> reads the kind back out of them
+const TRANSFORM_PREFIX: &str = "invalid transform: ";
+const POINTS_PREFIX: &str = "invalid points: ";
+const COLOR_PREFIX: &str = "invalid color: ";
+const PATH_DATA_PREFIX: &str = "invalid path data: ";
+type Kind = fn(String) -> Error;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io{path, error} => write!(f, "{}: {}", path.display(), error),
+            Error::Output(error) => write!(f, "writing failed: {}", error),
+            Error::Xml{position: Some((line, column)), message} => write!(f, "{}:{}: {}", line, column, message),
+            Error::Xml{position: None, message} => write!(f, "{}", message),
+            Error::Transform(message) => write!(f, "{}{}", TRANSFORM_PREFIX, message),
+            Error::Points(message) => write!(f, "{}{}", POINTS_PREFIX, message),
+            Error::Color(message) => write!(f, "{}{}", COLOR_PREFIX, message),
+            Error::PathData(message) => write!(f, "{}{}", PATH_DATA_PREFIX, message),
+            Error::MissingAsset(path) => write!(f, "missing asset {}", path.display()),
+            Error::UnknownClipPath(clip) => write!(f, "unknown clip path {}", clip),
+            Error::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io{error, ..} | Error::Output(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    // a stamp's file that could not be read, telling a missing one apart
+    pub fn asset(path: &Path, error: io::Error) -> Error {
+        if error.kind() == io::ErrorKind::NotFound {
+            Error::MissingAsset(path.to_path_buf())
+        } else {
+            Error::Io{path: path.to_path_buf(), error}
+        }
+    }
+    // for map_err on anything touching path
+    pub fn io(path: &Path) -> impl Fn(io::Error) -> Error + '_ {
+        move |error| Error::Io{path: path.to_path_buf(), error}
+    }
+    fn from_message(message: String) -> Error {
+        let kinds: [(&str, Kind); 4] = [
+            (TRANSFORM_PREFIX, Error::Transform),
+            (POINTS_PREFIX, Error::Points),
+            (COLOR_PREFIX, Error::Color),
+            (PATH_DATA_PREFIX, Error::PathData),
+        ];
+        for (prefix, kind) in kinds.iter() {
+            if let Some(rest) = message.strip_prefix(prefix) {
+                return kind(rest.to_string());
+            }
+        }
+        Error::Xml{position: None, message}
+    }
+}
+
+impl From<serde_xml_rs::Error> for Error {
+    fn from(error: serde_xml_rs::Error) -> Error {
+        match error.kind() {
+            ErrorKind::Custom(message) => Error::from_message(message.clone()),
+            ErrorKind::Syntax(syntax) => {
+                // xml-rs spells the position out as line:column ahead of the message
+                let text = syntax.to_string();
+                let (position, message) = text.split_once(' ').unwrap_or((&text, ""));
+                let mut numbers = position.split(':').map(|number| number.parse::<u64>());
+                match (numbers.next(), numbers.next(), numbers.next()) {
+                    (Some(Ok(line)), Some(Ok(column)), None) => Error::Xml{position: Some((line, column)), message: message.to_string()},
+                    _ => Error::Xml{position: None, message: text.clone()},
+                }
+            },
+            _ => Error::Xml{position: None, message: error.to_string()},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+    use super::super::svgxml::SVG;
+
+    #[test]
+    fn test_error_kinds() {
+        let stamp = |transform: &str, fill: &str| format!(
+            r##"<svg version="2.0" width="64" height="64" xmlns="http://www.w3.org/2000/svg"><g transform="{}"><rect x="0" y="0" width="64" height="64" fill="{}" mask="url(#stamps/rect.bmp)"/></g></svg>"##,
+            transform, fill);
+        assert!(SVG::from_str(&stamp("translate(1, 2)", "#102030")).is_ok());
+        match SVG::from_str(&stamp("translate(1, x)", "#102030")) {
+            Err(Error::Transform(_)) => {},
+            other => panic!("{:?}", other),
+        }
+        match SVG::from_str(&stamp("translate(1, 2)", "#1020zz")) {
+            Err(Error::Color(_)) => {},
+            other => panic!("{:?}", other),
+        }
+        match SVG::from_str("<svg version=\"2.0\" width=\"64\" height=\"64\">\n<g></svg>") {
+            Err(Error::Xml{position: Some((line, _)), ..}) => assert_eq!(line, 2),
+            other => panic!("{:?}", other),
+        }
+        let error = Error::asset(std::path::Path::new("nowhere.svg"), std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(error.to_string(), "missing asset nowhere.svg");
+    }
+}
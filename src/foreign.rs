@@ -0,0 +1,253 @@
+// this file keeps what a document holds beyond the stamps and clip paths the model knows, like a
+// <title>, a gradient or an id added in another editor, so that opening and saving a drawing
+// doesn't throw it away. serde_xml_rs has no way to hand back elements it skipped, so the document
+// is scanned for them separately
+use std::io;
+use std::vec::Vec;
+use super::error::Error;
+use super::xmlwriter::XmlWriter;
+
+// markup of an element, comment or stray text as the document spelled it, and how many of the
+// known elements beside it came first
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ForeignElement {
+    pub after: usize,
+    pub markup: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Foreign {
+    pub attrs: Vec<(String, String)>, // unescaped, in document order
+    pub elements: Vec<ForeignElement>,
+}
+
+impl Foreign {
+    // the known attributes followed by the foreign ones, ready for XmlWriter
+    pub fn with_attrs<'a>(&'a self, mut known: Vec<(&'a str, &'a str)>) -> Vec<(&'a str, &'a str)> {
+        known.extend(self.attrs.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+        known
+    }
+    // the elements that followed after known ones, and with last those that followed even more,
+    // since the known ones may have been deleted since
+    pub fn write_elements<W: io::Write>(&self, xml: &mut XmlWriter<W>, after: usize, last: bool) -> Result<(), Error> {
+        for element in &self.elements {
+            if element.after == after || (last && element.after > after) {
+                xml.element(&element.markup)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// an element of the scanned document, or with an empty name a comment, processing instruction,
+// CDATA section or run of text that isn't only whitespace
+#[derive(Debug)]
+pub struct Node {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    fn other(start: usize, end: usize) -> Node {
+        Node{name: String::new(), attrs: Vec::new(), start, end, children: Vec::new()}
+    }
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|attr| attr.0 == name).map(|attr| attr.1.as_str())
+    }
+    pub fn foreign_attrs(&self, known: &[&str]) -> Vec<(String, String)> {
+        self.attrs.iter().filter(|attr| !known.contains(&attr.0.as_str())).cloned().collect()
+    }
+    pub fn keep(&self, document: &str, after: usize) -> ForeignElement {
+        ForeignElement{after, markup: document[self.start..self.end].to_string()}
+    }
+}
+
+// the predefined entities and character references
+pub fn unescape(s: &str) -> Option<String> {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(at) = rest.find('&') {
+        ret += &rest[..at];
+        let semicolon = rest[at..].find(';')?;
+        let entity = &rest[at + 1..at + semicolon];
+        ret.push(match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => entity.strip_prefix('#')?.parse().ok()?,
+                };
+                std::char::from_u32(code)?
+            },
+        });
+        rest = &rest[at + semicolon + 1..];
+    }
+    ret += rest;
+    Some(ret)
+}
+
+struct Scanner<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.rest().starts_with(prefix);
+        if found {
+            self.pos += prefix.len();
+        }
+        found
+    }
+    fn until(&mut self, end: &str) -> Option<&'a str> {
+        let rest = self.rest();
+        let at = rest.find(end)?;
+        self.pos += at + end.len();
+        Some(&rest[..at])
+    }
+    fn name(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')?;
+        self.pos += len;
+        if len == 0 { None } else { Some(&rest[..len]) }
+    }
+    // skips a comment, processing instruction, CDATA section or doctype if one is next
+    fn misc(&mut self) -> Option<bool> {
+        if self.eat("<!--") {
+            self.until("-->")?;
+        } else if self.eat("<![CDATA[") {
+            self.until("]]>")?;
+        } else if self.eat("<?") {
+            self.until("?>")?;
+        } else if self.eat("<!") {
+            // a doctype's internal subset may hold > of its own
+            let mut depth = 0;
+            loop {
+                let c = self.rest().chars().next()?;
+                self.pos += c.len_utf8();
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    '>' if depth == 0 => break,
+                    _ => {},
+                }
+            }
+        } else {
+            return Some(false);
+        }
+        Some(true)
+    }
+    fn element(&mut self) -> Option<Node> {
+        let start = self.pos;
+        if !self.eat("<") {
+            return None;
+        }
+        let name = self.name()?.to_string();
+        let mut attrs = Vec::new();
+        loop {
+            self.skip_space();
+            if self.eat("/>") {
+                return Some(Node{name, attrs, start, end: self.pos, children: Vec::new()});
+            }
+            if self.eat(">") {
+                break;
+            }
+            let key = self.name()?.to_string();
+            self.skip_space();
+            if !self.eat("=") {
+                return None;
+            }
+            self.skip_space();
+            let quote = if self.eat("\"") { "\"" } else if self.eat("'") { "'" } else { return None };
+            attrs.push((key, unescape(self.until(quote)?)?));
+        }
+        let mut children = Vec::new();
+        loop {
+            let child_start = self.pos;
+            if self.eat("</") {
+                if self.name()? != name {
+                    return None;
+                }
+                self.skip_space();
+                if !self.eat(">") {
+                    return None;
+                }
+                return Some(Node{name, attrs, start, end: self.pos, children});
+            }
+            if self.misc()? {
+                children.push(Node::other(child_start, self.pos));
+            } else if self.rest().starts_with('<') {
+                children.push(self.element()?);
+            } else {
+                self.pos += self.rest().find('<')?;
+                if !self.s[child_start..self.pos].trim().is_empty() {
+                    children.push(Node::other(child_start, self.pos));
+                }
+            }
+        }
+    }
+}
+
+// the root element of a well formed document, or None for anything the deserializer should be
+// left to complain about
+pub fn scan(s: &str) -> Option<Node> {
+    let mut scanner = Scanner{s, pos: 0};
+    scanner.skip_space();
+    while scanner.misc()? {
+        scanner.skip_space();
+    }
+    scanner.element()
+}
+
+// the document with the given byte ranges turned to spaces, keeping line breaks so that errors
+// in what's left still point at the right line and column
+pub fn blank(s: &str, spans: &[(usize, usize)]) -> String {
+    let mut spans = spans.to_vec();
+    spans.sort();
+    let mut spans = spans.into_iter().peekable();
+    s.char_indices().map(|(at, c)| {
+        // spans ending before at are behind us, and the next one either covers at or starts later
+        while matches!(spans.peek(), Some(span) if span.1 <= at) {
+            spans.next();
+        }
+        let covered = matches!(spans.peek(), Some(span) if span.0 <= at);
+        if c != '\n' && covered { ' ' } else { c }
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{scan, blank};
+
+    #[test]
+    fn test_scan() {
+        let document = "<?xml version=\"1.0\"?>\n<!DOCTYPE svg [<!ENTITY x \"y\">]>\n<svg a='1 &amp; &#x32;'>\n<!-- note -->\n<title>A &lt; B</title><g/> text \n</svg>";
+        let root = scan(document).unwrap();
+        assert_eq!(root.name, "svg");
+        assert_eq!(root.attr("a"), Some("1 & 2"));
+        assert_eq!(root.children.iter().map(|child| child.name.as_str()).collect::<Vec<&str>>(), vec!["", "title", "g", ""]);
+        assert_eq!(&document[root.children[1].start..root.children[1].end], "<title>A &lt; B</title>");
+        assert_eq!(&document[root.start..root.start + 4], "<svg");
+        let blanked = blank(document, &[(root.children[1].start, root.children[1].end)]);
+        assert_eq!(blanked.len(), document.len());
+        assert!(!blanked.contains("title") && blanked.contains("<g/>"));
+        // spans may come in any order and overlap
+        assert_eq!(blank("abcdef\ngh", &[(5, 9), (1, 3), (2, 4)]), "a   e \n  ");
+        assert!(scan("<svg><g></svg>").is_none());
+        assert!(scan("<svg a=\"&bogus;\"/>").is_none());
+    }
+}
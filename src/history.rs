@@ -0,0 +1,381 @@
+// undoable edits to an SVG arrangement; each edit keeps the exact values it replaced
+// so that undoing returns the document to the same bytes it serialized to before
+use std::vec::Vec;
+use std::convert::TryFrom;
+use super::error::Error;
+use super::svgxml::{SVG, g, Color, ClipPath, Transform, F64Point, pack_polygon_points, transform_deserializer, point_deserializer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub origins: Vec<F64Point>, // (tx, ty) of every stamp
+}
+
+impl Layout {
+    pub fn capture(svg: &SVG) -> Layout {
+        Layout{
+            width: svg.width,
+            height: svg.height,
+            origins: svg.stamps.iter().map(|stamp| (stamp.transform.tx, stamp.transform.ty)).collect(),
+        }
+    }
+    fn restore(&self, svg: &mut SVG) {
+        svg.width = self.width;
+        svg.height = self.height;
+        for (stamp, origin) in svg.stamps.iter_mut().zip(self.origins.iter()) {
+            stamp.transform.tx = origin.0;
+            stamp.transform.ty = origin.1;
+        }
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.origins.is_empty() {
+            writeln!(f, "<layout width=\"{}\" height=\"{}\"/>", self.width, self.height)
+        } else {
+            writeln!(f, "<layout width=\"{}\" height=\"{}\" origins=\"{}\"/>",
+                     self.width, self.height, pack_polygon_points(&self.origins))
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize, PartialEq)]
+struct layout {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    #[serde(deserialize_with="point_deserializer")]
+    origins: Vec<F64Point>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Insert{index: usize, stamp: g},
+    Remove{index: usize, stamp: g},
+    Move{index: usize, before: Transform, after: Transform},
+    Recolor{index: usize, before: Color, after: Color},
+    AddClipPath(ClipPath),
+    Resize{before: Layout, after: Layout}, // the canvas size along with any shift of the stamps to fit it
+    Batch(Vec<Edit>),
+}
+
+impl Edit {
+    // stamps and transforms read back from a session are centered the way SVG::from_str centers
+    // the document's, so that a restored edit turns the stamp about the same point
+    fn insert(svg: &mut SVG, index: usize, stamp: &g) {
+        let mut stamp = stamp.clone();
+        stamp.transform = stamp.centered(&stamp.transform);
+        svg.stamps.insert(index, stamp);
+    }
+    fn place(svg: &mut SVG, index: usize, transform: &Transform) {
+        let stamp = &mut svg.stamps[index];
+        stamp.transform = stamp.centered(transform);
+    }
+    fn redo(&self, svg: &mut SVG) {
+        match self {
+            Edit::Insert{index, stamp} => Edit::insert(svg, *index, stamp),
+            Edit::Remove{index, ..} => {
+                svg.stamps.remove(*index);
+            },
+            Edit::Move{index, after, ..} => Edit::place(svg, *index, after),
+            Edit::Recolor{index, after, ..} => svg.stamps[*index].rect.fill = *after,
+            Edit::AddClipPath(clip) => svg.defs.clipPath.push(clip.clone()),
+            Edit::Resize{after, ..} => after.restore(svg),
+            Edit::Batch(edits) => for edit in edits {
+                edit.redo(svg);
+            },
+        }
+    }
+    fn undo(&self, svg: &mut SVG) {
+        match self {
+            Edit::Insert{index, ..} => {
+                svg.stamps.remove(*index);
+            },
+            Edit::Remove{index, stamp} => Edit::insert(svg, *index, stamp),
+            Edit::Move{index, before, ..} => Edit::place(svg, *index, before),
+            Edit::Recolor{index, before, ..} => svg.stamps[*index].rect.fill = *before,
+            Edit::AddClipPath(_) => {
+                svg.defs.clipPath.pop();
+            },
+            Edit::Resize{before, ..} => before.restore(svg),
+            Edit::Batch(edits) => for edit in edits.iter().rev() {
+                edit.undo(svg);
+            },
+        }
+    }
+    pub fn to_string(&self) -> Result<String, Error> {
+        Ok(match self {
+            Edit::Insert{index, stamp} => format!("<edit kind=\"insert\" index=\"{}\">\n{}\n</edit>\n", index, stamp.to_string()?),
+            Edit::Remove{index, stamp} => format!("<edit kind=\"remove\" index=\"{}\">\n{}\n</edit>\n", index, stamp.to_string()?),
+            Edit::Move{index, before, after} => format!("<edit kind=\"move\" index=\"{}\" before=\"{}\" after=\"{}\"/>\n",
+                                                        index, before.to_string()?, after.to_string()?),
+            Edit::Recolor{index, before, after} => format!("<edit kind=\"recolor\" index=\"{}\" before=\"{}\" after=\"{}\"/>\n",
+                                                           index, before.to_string(), after.to_string()),
+            Edit::AddClipPath(clip) => format!("<edit kind=\"clip\">\n{}</edit>\n", clip.to_string()?),
+            Edit::Resize{before, after} => format!("<edit kind=\"resize\">\n{}{}</edit>\n", before, after),
+            Edit::Batch(edits) => {
+                let mut ret = "<edit kind=\"batch\">\n".to_string();
+                for edit in edits {
+                    ret += &edit.to_string()?;
+                }
+                ret + "</edit>\n"
+            },
+        })
+    }
+    // folds a follow up edit of the same kind into this one, so holding a key is a single undo step
+    fn absorb(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (Edit::Move{index, after, ..}, Edit::Move{index: next_index, after: next_after, ..}) if index == next_index => {
+                *after = next_after.clone();
+                true
+            },
+            (Edit::Recolor{index, after, ..}, Edit::Recolor{index: next_index, after: next_after, ..}) if index == next_index => {
+                *after = *next_after;
+                true
+            },
+            (Edit::Resize{after, ..}, Edit::Resize{after: next_after, ..}) => {
+                *after = next_after.clone();
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+// how an edit is laid out in a session file; each kind only fills in the fields it needs
+#[allow(non_camel_case_types)]
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct edit {
+    kind: String,
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    before: String,
+    #[serde(default)]
+    after: String,
+    #[serde(default)]
+    g: Vec<g>,
+    #[serde(default)]
+    clipPath: Vec<ClipPath>,
+    #[serde(default)]
+    layout: Vec<layout>,
+    #[serde(default)]
+    edit: Vec<edit>,
+}
+
+fn parse_transform(input: &str) -> Result<Transform, Error> {
+    transform_deserializer(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(input)).map_err(
+        |err| Error::Transform(err.to_string()))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Invalid(message.to_string())
+}
+
+impl TryFrom<edit> for Edit {
+    type Error = Error;
+    fn try_from(mut e: edit) -> Result<Self, Self::Error> {
+        let index = e.index;
+        Ok(match e.kind.as_str() {
+            "insert" => Edit::Insert{index, stamp: e.g.pop().ok_or_else(|| invalid("insert edit without a stamp"))?},
+            "remove" => Edit::Remove{index, stamp: e.g.pop().ok_or_else(|| invalid("remove edit without a stamp"))?},
+            "move" => Edit::Move{index, before: parse_transform(&e.before)?, after: parse_transform(&e.after)?},
+            "recolor" => Edit::Recolor{index, before: Color::try_from(e.before.as_str()).map_err(Error::Color)?,
+                                       after: Color::try_from(e.after.as_str()).map_err(Error::Color)?},
+            "clip" => Edit::AddClipPath(e.clipPath.pop().ok_or_else(|| invalid("clip edit without a clipPath"))?),
+            "resize" => {
+                if e.layout.len() != 2 {
+                    return Err(Error::Invalid(format!("resize edit needs 2 layouts, not {}", e.layout.len())));
+                }
+                let after = e.layout.pop().unwrap();
+                let before = e.layout.pop().unwrap();
+                Edit::Resize{
+                    before: Layout{width: before.width, height: before.height, origins: before.origins},
+                    after: Layout{width: after.width, height: after.height, origins: after.origins},
+                }
+            },
+            "batch" => Edit::Batch(e.edit.into_iter().map(Edit::try_from).collect::<Result<Vec<Edit>, Error>>()?),
+            kind => return Err(Error::Invalid(format!("unknown edit kind {}", kind))),
+        })
+    }
+}
+
+pub struct History {
+    done: Vec<Edit>,
+    undone: Vec<Edit>,
+    limit: usize,
+    open: bool, // whether the next edit may be merged into the last one
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History{done: Vec::new(), undone: Vec::new(), limit, open: false}
+    }
+    // performs edit on svg and records it
+    pub fn apply(&mut self, svg: &mut SVG, edit: Edit) {
+        edit.redo(svg);
+        self.undone.clear();
+        if self.open {
+            if let Some(last) = self.done.last_mut() {
+                if last.absorb(&edit) {
+                    return;
+                }
+            }
+        }
+        self.done.push(edit);
+        if self.done.len() > self.limit {
+            self.done.remove(0);
+        }
+        self.open = true;
+    }
+    // ends the current run of mergeable edits, e.g. when a key or mouse button is released
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+    pub fn undo(&mut self, svg: &mut SVG) -> bool {
+        self.open = false;
+        match self.done.pop() {
+            Some(edit) => {
+                edit.undo(svg);
+                self.undone.push(edit);
+                true
+            },
+            None => false,
+        }
+    }
+    pub fn redo(&mut self, svg: &mut SVG) -> bool {
+        self.open = false;
+        match self.undone.pop() {
+            Some(edit) => {
+                edit.redo(svg);
+                self.done.push(edit);
+                true
+            },
+            None => false,
+        }
+    }
+    // rebuilds a history saved by to_string; the oldest edits are dropped past the limit
+    pub fn from_edits(limit: usize, done: Vec<edit>, undone: Vec<edit>) -> Result<History, Error> {
+        let convert = |edits: Vec<edit>| edits.into_iter().map(Edit::try_from).collect::<Result<Vec<Edit>, Error>>();
+        let mut ret = History::new(limit);
+        ret.done = convert(done)?;
+        ret.undone = convert(undone)?;
+        if ret.done.len() > limit {
+            let excess = ret.done.len() - limit;
+            ret.done.drain(..excess);
+        }
+        Ok(ret)
+    }
+    pub fn to_string(&self) -> Result<String, Error> {
+        let mut ret = "<undo>\n".to_string();
+        for edit in &self.done {
+            ret += &edit.to_string()?;
+        }
+        ret += "</undo>\n<redo>\n";
+        for edit in &self.undone {
+            ret += &edit.to_string()?;
+        }
+        Ok(ret + "</redo>\n")
+    }
+    pub fn len(&self) -> usize {
+        self.done.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.done.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{History, Edit, Layout};
+    use super::super::svgxml::{SVG, g, Color, ClipPath, Polygon, Transform};
+
+    fn stamp(tx: f64, ty: f64, clip: &str) -> g {
+        let mut transform = Transform::new(64, 64);
+        transform.tx = tx;
+        transform.ty = ty;
+        transform.rotate = 30.;
+        g::new(transform, "assets/stamps/rect.bmp".to_string(), clip.to_string(), Color{r:0x10, g:0x20, b:0x30})
+    }
+
+    #[test]
+    fn test_undo_is_byte_identical() {
+        let mut svg = SVG::new(800, 600);
+        svg.stamps.push(stamp(10.1, 20.3, ""));
+        let mut history = History::new(64);
+        let mut snapshots = vec![svg.to_string().unwrap()];
+        history.apply(&mut svg, Edit::Batch(vec![
+            Edit::AddClipPath(ClipPath{id:"0".to_string(), polygon:Polygon{points:vec![(0., 0.), (4., 0.), (4., 4.)]}, path:None}),
+            Edit::Insert{index: 1, stamp: stamp(100.7, 5.5, "url(#0)")},
+        ]));
+        snapshots.push(svg.to_string().unwrap());
+        history.seal();
+        let mut moved = svg.stamps[0].transform.clone();
+        moved.tx += 0.1;
+        moved.rotate = 45.;
+        let edit = Edit::Move{index: 0, before: svg.stamps[0].transform.clone(), after: moved};
+        history.apply(&mut svg, edit);
+        snapshots.push(svg.to_string().unwrap());
+        history.seal();
+        let edit = Edit::Recolor{index: 1, before: svg.stamps[1].rect.fill, after: Color{r:0xff, g:0, b:0}};
+        history.apply(&mut svg, edit);
+        snapshots.push(svg.to_string().unwrap());
+        history.seal();
+        let mut grown = Layout::capture(&svg);
+        grown.height += 3;
+        for origin in grown.origins.iter_mut() {
+            origin.1 += 3.;
+        }
+        let edit = Edit::Resize{before: Layout::capture(&svg), after: grown};
+        history.apply(&mut svg, edit);
+        snapshots.push(svg.to_string().unwrap());
+        history.seal();
+        let removed = svg.stamps[0].clone();
+        history.apply(&mut svg, Edit::Remove{index: 0, stamp: removed});
+        snapshots.push(svg.to_string().unwrap());
+        assert_eq!(history.len(), 5);
+        for expected in snapshots.iter().rev().skip(1) {
+            assert!(history.undo(&mut svg));
+            assert_eq!(&svg.to_string().unwrap(), expected);
+        }
+        assert!(!history.undo(&mut svg));
+        for expected in snapshots.iter().skip(1) {
+            assert!(history.redo(&mut svg));
+            assert_eq!(&svg.to_string().unwrap(), expected);
+        }
+        assert!(!history.redo(&mut svg));
+    }
+
+    #[test]
+    fn test_merge_and_limit() {
+        let mut svg = SVG::new(800, 600);
+        svg.stamps.push(stamp(0., 0., ""));
+        let original = svg.to_string().unwrap();
+        let mut history = History::new(4);
+        for _ in 0..10 {
+            let mut after = svg.stamps[0].transform.clone();
+            after.rotate += 1.;
+            let edit = Edit::Move{index: 0, before: svg.stamps[0].transform.clone(), after};
+            history.apply(&mut svg, edit);
+        }
+        assert_eq!(history.len(), 1);
+        assert!(history.undo(&mut svg));
+        assert_eq!(svg.to_string().unwrap(), original);
+        assert!(history.redo(&mut svg));
+        for _ in 0..10 {
+            history.seal();
+            let fill = svg.stamps[0].rect.fill;
+            history.apply(&mut svg, Edit::Recolor{index: 0, before: fill, after: Color{r:fill.r + 1, ..fill}});
+        }
+        assert_eq!(history.len(), 4);
+        // a fresh edit drops anything that could have been redone
+        history.undo(&mut svg);
+        let edit = Edit::Recolor{index: 0, before: svg.stamps[0].rect.fill, after: Color::default()};
+        history.apply(&mut svg, edit);
+        assert!(!history.redo(&mut svg));
+    }
+}
@@ -5,6 +5,33 @@ extern crate serde;
 extern crate regex;
 mod polygonsvg;
 mod svgxml;
-pub use svgxml::{Color, SVG, Transform, HrefAndClipMask, Polygon, F64Point, ClipPath, g};
-pub use svgxml::{itransform, ftransform, poly_edge_intersect, compose};
-
+mod raster;
+mod history;
+mod session;
+mod save;
+mod library;
+mod boolean;
+mod shape;
+mod path;
+mod plot;
+mod portable;
+mod assets;
+mod error;
+mod xmlwriter;
+mod foreign;
+pub use svgxml::{Color, SVG, Transform, Affine, HrefAndClipMask, Polygon, F64Point, ClipPath, ClipRings, g};
+pub use svgxml::{itransform, ftransform, poly_edge_intersect, compose, stamp_svg_path, pack_path_data};
+pub use svgxml::{ShapeCache, CHORD_TOLERANCE};
+pub use raster::{Raster, rasterize, render_png, render_stamp};
+pub use history::{Edit, History, Layout};
+pub use session::{Session, session_path};
+pub use save::{Autosave, write_atomically, backup, list_backups, BACKUP_COUNT};
+pub use library::{Library, StampInfo};
+pub use boolean::{Ring, Operation, combine, union, intersection, difference, xor, signed_area};
+pub use shape::Shape;
+pub use plot::{plot_layers, plot_svg, save_plot};
+pub use portable::{portable_svg, save_portable};
+pub use error::Error;
+pub use assets::{AssetResolver, ASSETS_ENV_VAR, DEFAULT_ASSET_DIR, to_href};
+pub use xmlwriter::{XmlWriter, XmlStyle};
+pub use foreign::{Foreign, ForeignElement};
@@ -0,0 +1,335 @@
+// the set of stamps the editor offers, described by a stamps.xml manifest next to the bitmaps
+// so that a stamp pack can be dropped in without touching the code
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+use std::io::Read;
+use super::error::Error;
+use super::serde_xml_rs::from_str;
+use super::svgxml::{SVG, stamp_svg_path};
+use super::assets::{AssetResolver, to_href};
+
+pub const MANIFEST_NAME: &str = "stamps.xml";
+const DEFAULT_CATEGORY: &str = "uncategorized";
+
+// stamps in older asset directories that start out turned, matched against the bitmap name
+static LEGACY_ROTATIONS: &[(&str, f64)] = &[
+    ("rect", 90.0),
+    ("pipe", 90.0),
+    ("lhalframp", 90.0), ("lquartramp", 90.0),
+    ("rhalframp", -90.0), ("rquartramp", -90.0),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StampInfo {
+    pub id: String,
+    pub name: String, // shown to the user
+    pub category: String,
+    pub bitmap: String, // the url placed stamps reference, relative to the asset root, e.g. stamps/rect.bmp; drawn from the outline if missing
+    pub outline: String, // svg the mask and collision polygon are drawn from, relative to the asset root
+    pub rotation: f64, // degrees the stamp is turned when picked from the palette
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Library {
+    pub stamps: Vec<StampInfo>,
+    pub roots: Vec<PathBuf>, // the directories the hrefs of the stamps are relative to
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize)]
+struct stamp {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    category: String,
+    #[serde(default)]
+    bitmap: String,
+    #[serde(default)]
+    outline: String,
+    #[serde(default)]
+    rotation: f64,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize)]
+struct library {
+    #[serde(default)]
+    stamp: Vec<stamp>,
+}
+
+fn collect_files(dir: &Path, extension: &str, recurse: bool, found: &mut Vec<String>) -> Result<(), Error> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir).map_err(Error::io(dir))? {
+        let path = entry.map_err(Error::io(dir))?.path();
+        if path.is_dir() {
+            if recurse {
+                collect_files(&path, extension, recurse, found)?;
+            }
+        } else if path.extension().map(|ext| ext == extension).unwrap_or(false) {
+            found.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+impl Library {
+    fn add_root(&mut self, root: &Path) {
+        if !root.as_os_str().is_empty() && !self.roots.iter().any(|mine| mine == root) {
+            self.roots.push(root.to_path_buf());
+        }
+    }
+    // bitmap and outline paths in the manifest are relative to base_dir, the directory holding it,
+    // and are kept that way
+    pub fn from_str(s: &str, base_dir: &Path) -> Result<Library, Error> {
+        let parsed: library = from_str(s)?;
+        let mut ret = Library::default();
+        ret.add_root(base_dir);
+        for item in parsed.stamp {
+            if ret.find(&item.id).is_some() {
+                return Err(Error::Invalid(format!("stamp id {} is listed twice", item.id)));
+            }
+            let bitmap = if item.bitmap.is_empty() {
+                format!("stamps/{}.bmp", item.id)
+            } else {
+                item.bitmap
+            };
+            ret.stamps.push(StampInfo{
+                name: if item.name.is_empty() { item.id.clone() } else { item.name },
+                category: if item.category.is_empty() { DEFAULT_CATEGORY.to_string() } else { item.category },
+                outline: if item.outline.is_empty() { stamp_svg_path(&bitmap) } else { item.outline },
+                id: item.id,
+                bitmap,
+                rotation: item.rotation,
+            });
+        }
+        Ok(ret)
+    }
+    pub fn load(manifest: &Path) -> Result<Library, Error> {
+        let mut data = String::new();
+        std::fs::File::open(manifest).and_then(|mut file| file.read_to_string(&mut data)).map_err(Error::io(manifest))?;
+        Library::from_str(&data, manifest.parent().unwrap_or_else(|| Path::new("")))
+    }
+    // every bitmap under dir/stamps, and every svg in dir that has no bitmap drawn yet
+    pub fn scan(dir: &Path) -> Result<Library, Error> {
+        let mut found = Vec::new();
+        collect_files(&dir.join("stamps"), "bmp", true, &mut found)?;
+        let relative = |path: &String| to_href(Path::new(path).strip_prefix(dir).unwrap_or_else(|_| Path::new(path)));
+        let mut bitmaps: Vec<String> = found.iter().map(relative).collect();
+        let mut outlines = Vec::new();
+        collect_files(dir, "svg", false, &mut outlines)?;
+        for outline in outlines.iter().map(relative) {
+            if let Some(stem) = Path::new(&outline).file_stem() {
+                let bitmap = format!("stamps/{}.bmp", stem.to_string_lossy());
+                if !bitmaps.contains(&bitmap) && stamp_svg_path(&bitmap) == outline {
+                    bitmaps.push(bitmap);
+                }
+            }
+        }
+        bitmaps.sort();
+        let mut ret = Library::default();
+        ret.add_root(dir);
+        for bitmap in bitmaps {
+            let id = Path::new(&bitmap).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut rotation = 0.0;
+            for legacy in LEGACY_ROTATIONS {
+                if id.contains(legacy.0) {
+                    rotation = legacy.1;
+                }
+            }
+            ret.stamps.push(StampInfo{
+                name: id.clone(),
+                id,
+                category: DEFAULT_CATEGORY.to_string(),
+                outline: stamp_svg_path(&bitmap),
+                bitmap,
+                rotation,
+            });
+        }
+        Ok(ret)
+    }
+    // the manifest in dir, followed by any stamps in dir it does not list yet
+    pub fn open(dir: &Path) -> Result<Library, Error> {
+        let manifest = dir.join(MANIFEST_NAME);
+        let scanned = Library::scan(dir)?;
+        if !manifest.exists() {
+            return Ok(scanned);
+        }
+        let mut ret = Library::load(&manifest)?;
+        for info in scanned.stamps {
+            if ret.get(&info.bitmap).is_none() && ret.find(&info.id).is_none() {
+                ret.stamps.push(info);
+            }
+        }
+        Ok(ret)
+    }
+    pub fn find(&self, id: &str) -> Option<&StampInfo> {
+        self.stamps.iter().find(|info| info.id == id)
+    }
+    // looks a placed stamp up by the url it references
+    pub fn get(&self, bitmap: &str) -> Option<&StampInfo> {
+        self.stamps.iter().find(|info| info.bitmap == bitmap)
+    }
+    // in the order they first appear, which is the order the palette shows them
+    pub fn categories(&self) -> Vec<&str> {
+        let mut ret: Vec<&str> = Vec::new();
+        for info in &self.stamps {
+            if !ret.contains(&info.category.as_str()) {
+                ret.push(&info.category);
+            }
+        }
+        ret
+    }
+    // the same stamps spelled from the parent of the directory they came from, castle/stamps/rect.bmp
+    // rather than stamps/rect.bmp, with that parent searched first, so that a pack's stamps never
+    // resolve to the files of a shipped stamp that has the same href. the directory itself stays a
+    // root for documents placed before packs were told apart
+    pub fn as_pack(mut self) -> Library {
+        let dir = match self.roots.first() {
+            Some(dir) => dir.clone(),
+            None => return self,
+        };
+        let dir = if dir.file_name().is_some() { dir } else { std::fs::canonicalize(&dir).unwrap_or(dir) };
+        let (name, parent) = match (dir.file_name(), dir.parent()) {
+            (Some(name), Some(parent)) => (name.to_os_string(), parent.to_path_buf()),
+            _ => return self,
+        };
+        let parent = if parent.as_os_str().is_empty() { PathBuf::from(".") } else { parent };
+        let prefixed = |href: &mut String| if !Path::new(href.as_str()).is_absolute() {
+            *href = to_href(&Path::new(&name).join(href.as_str()));
+        };
+        for info in self.stamps.iter_mut() {
+            prefixed(&mut info.bitmap);
+            prefixed(&mut info.outline);
+        }
+        self.roots.insert(0, parent);
+        self
+    }
+    // stamps of other replace ours with the same id and the rest are appended; its roots are
+    // searched after ours
+    pub fn extend(&mut self, other: Library) {
+        for root in &other.roots {
+            self.add_root(root);
+        }
+        for info in other.stamps {
+            let existing = self.stamps.iter().position(|mine| mine.id == info.id);
+            match existing {
+                Some(index) => self.stamps[index] = info,
+                None => self.stamps.push(info),
+            }
+        }
+    }
+    // palette order: grouped by category, keeping manifest order within each
+    pub fn sorted_by_category(&self) -> Vec<&StampInfo> {
+        let mut ret = Vec::with_capacity(self.stamps.len());
+        for category in self.categories() {
+            ret.extend(self.stamps.iter().filter(|info| info.category == category));
+        }
+        ret
+    }
+    // assets, falling back on the directories our stamps came from
+    pub fn search_roots(&self, assets: &AssetResolver) -> AssetResolver {
+        let mut ret = assets.clone();
+        for root in &self.roots {
+            ret.add_root(root.clone());
+        }
+        ret
+    }
+    // tells svg which outline to mask and collide each of our bitmaps with, and where to find them
+    pub fn register_outlines(&self, svg: &mut SVG) {
+        svg.assets = self.search_roots(&svg.assets);
+        for info in &self.stamps {
+            svg.outlines.insert(info.bitmap.clone(), info.outline.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use super::Library;
+    use super::super::svgxml::SVG;
+
+    #[test]
+    fn test_manifest() {
+        let library = Library::from_str(r#"<library>
+<stamp id="larch" name="Left arch" category="arches" bitmap="stamps/larch.bmp"/>
+<stamp id="roof" category="roofs" bitmap="stamps/roof.bmp" outline="outlines/roof.svg" rotation="-90"/>
+<stamp id="rarch" name="Right arch" category="arches" bitmap="stamps/rarch.bmp"/>
+<stamp id="blob" bitmap="blob.bmp"/>
+<stamp id="star" category="shapes" outline="star.svg"/>
+</library>"#, Path::new("assets")).unwrap();
+        assert_eq!(library.categories(), vec!["arches", "roofs", "uncategorized", "shapes"]);
+        // a stamp without a bitmap gets one drawn from its outline where the naming convention puts it
+        assert_eq!(library.find("star").unwrap().bitmap, "stamps/star.bmp");
+        let roof = library.find("roof").unwrap();
+        assert_eq!(roof.name, "roof");
+        assert_eq!(roof.bitmap, "stamps/roof.bmp");
+        assert_eq!(roof.outline, "outlines/roof.svg");
+        assert_eq!(roof.rotation, -90.);
+        // without an outline the svg sits where the bitmap naming convention puts it
+        assert_eq!(library.get("stamps/larch.bmp").unwrap().outline, "larch.svg");
+        let order: Vec<&str> = library.sorted_by_category().iter().map(|info| info.id.as_str()).collect();
+        assert_eq!(order, vec!["larch", "rarch", "roof", "blob", "star"]);
+
+        // the hrefs are found under the directory the manifest sits in
+        let mut svg = SVG::new(10, 10);
+        svg.assets.roots.clear();
+        library.register_outlines(&mut svg);
+        assert_eq!(svg.outline_path("stamps/roof.bmp"), "assets/outlines/roof.svg");
+        assert_eq!(svg.outline_path("stamps/car.bmp"), "assets/car.svg");
+
+        let mut merged = library.clone();
+        merged.extend(Library::from_str(r#"<library><stamp id="roof" category="roofs" bitmap="steep.bmp"/><stamp id="car" bitmap="car.bmp"/></library>"#,
+                                        Path::new("pack")).unwrap());
+        assert_eq!(merged.stamps.len(), 6);
+        assert_eq!(merged.find("roof").unwrap().bitmap, "steep.bmp");
+        assert_eq!(merged.roots, vec![PathBuf::from("assets"), PathBuf::from("pack")]);
+        assert_eq!(merged.stamps[5].id, "car");
+
+        assert!(Library::from_str(r#"<library><stamp id="a" bitmap="a.bmp"/><stamp id="a" bitmap="b.bmp"/></library>"#, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_pack_override_loads_its_own_files() {
+        let parent = std::env::temp_dir().join(format!("art-stamps-pack-{}", std::process::id()));
+        let pack = parent.join("castle");
+        std::fs::create_dir_all(pack.join("stamps")).unwrap();
+        std::fs::write(pack.join(super::MANIFEST_NAME), r#"<library><stamp id="rect"/></library>"#).unwrap();
+        std::fs::copy("assets/circle.svg", pack.join("rect.svg")).unwrap();
+        std::fs::copy("assets/stamps/circle.bmp", pack.join("stamps/rect.bmp")).unwrap();
+
+        let mut library = Library::open(Path::new("assets")).unwrap();
+        let shipped = library.find("rect").unwrap().clone();
+        library.extend(Library::load(&pack.join(super::MANIFEST_NAME)).unwrap().as_pack());
+        let info = library.find("rect").unwrap().clone();
+        assert_eq!(info.bitmap, "castle/stamps/rect.bmp");
+        assert_eq!(library.roots, vec![PathBuf::from("assets"), parent.clone(), pack.clone()]);
+
+        let mut svg = SVG::new(10, 10);
+        library.register_outlines(&mut svg);
+        assert_eq!(svg.asset_path(&info.bitmap), pack.join("stamps/rect.bmp"));
+        assert_eq!(PathBuf::from(svg.outline_path(&info.bitmap)), pack.join("rect.svg"));
+        // the shipped stamp of the same name still finds its own files
+        assert_eq!(svg.outline_path(&shipped.bitmap), "assets/rect.svg");
+        let circle = svg.load_polygon("stamps/circle.bmp", 0.25).unwrap().area();
+        assert!((svg.load_polygon(&info.bitmap, 0.25).unwrap().area() - circle).abs() < 1.0e-9);
+        assert!((svg.load_polygon(&shipped.bitmap, 0.25).unwrap().area() - circle).abs() > 1.0);
+        std::fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn test_shipped_manifest_matches_assets() {
+        let manifest = Library::open(Path::new("assets")).unwrap();
+        let scanned = Library::scan(Path::new("assets")).unwrap();
+        assert_eq!(manifest.stamps.len(), scanned.stamps.len());
+        for legacy in &scanned.stamps {
+            let info = manifest.get(&legacy.bitmap).unwrap();
+            assert_eq!(info.rotation, legacy.rotation, "{}", info.id);
+            assert_eq!(info.outline, legacy.outline);
+            assert!(Path::new("assets").join(&info.outline).is_file(), "{}", info.outline);
+        }
+    }
+}
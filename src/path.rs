@@ -0,0 +1,409 @@
+// the d attribute of an svg <path>: parsed into absolute segments when a stamp is read, and
+// flattened into polylines when its outline is needed, curves cut finer where they bend more
+use std::vec::Vec;
+use serde::{Deserialize, Deserializer};
+use serde;
+use super::polygonsvg::{F64Point, add2d, sub2d, scale2d};
+use super::error::Error;
+
+// how far a flattened curve may stray from the true one, in stamp pixels
+pub const FLATTEN_TOLERANCE: f64 = 0.1;
+const MAX_SUBDIVISION: usize = 16;
+const MAX_ARC_SEGMENTS: f64 = 1024.;
+
+// every command made absolute: H and V become lines, S and T get their reflected control points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathSegment {
+    MoveTo(F64Point),
+    LineTo(F64Point),
+    CubicTo(F64Point, F64Point, F64Point),
+    QuadTo(F64Point, F64Point),
+    ArcTo{radii: F64Point, rotation: f64, large_arc: bool, sweep: bool, to: F64Point},
+    Close,
+}
+
+// one run of the pen; open ones are still filled as if closed, but not stroked back to the start
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Subpath {
+    pub points: Vec<F64Point>,
+    pub closed: bool,
+}
+
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn skip_separators(&mut self) {
+        while self.pos < self.bytes.len() && (self.bytes[self.pos].is_ascii_whitespace() || self.bytes[self.pos] == b',') {
+            self.pos += 1;
+        }
+    }
+    fn done(&mut self) -> bool {
+        self.skip_separators();
+        self.pos == self.bytes.len()
+    }
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        match self.bytes.get(self.pos) {
+            Some(c) if c.is_ascii_alphabetic() && *c != b'e' && *c != b'E' => {
+                self.pos += 1;
+                Some(*c)
+            },
+            _ => None,
+        }
+    }
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+    // numbers need no separator when the next one starts with a sign or a second decimal point
+    fn number(&mut self) -> Result<f64, String> {
+        self.skip_separators();
+        let start = self.pos;
+        if let Some(b'+') | Some(b'-') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+        let mut mantissa = self.digits();
+        if self.bytes.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            mantissa += self.digits();
+        }
+        if mantissa == 0 {
+            return Err(format!("expected a number at offset {} of path data", start));
+        }
+        if let Some(b'e') | Some(b'E') = self.bytes.get(self.pos) {
+            let before_exponent = self.pos;
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.bytes.get(self.pos) {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                self.pos = before_exponent;
+            }
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).map_err(|e| format!("{}", e))?;
+        text.parse().map_err(|e| format!("{} in path data: {}", e, text))
+    }
+    // arc flags are a single digit and may run straight into what follows
+    fn flag(&mut self) -> Result<bool, String> {
+        self.skip_separators();
+        let ret = match self.bytes.get(self.pos) {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            _ => return Err(format!("expected an arc flag at offset {} of path data", self.pos)),
+        };
+        self.pos += 1;
+        Ok(ret)
+    }
+    fn point(&mut self) -> Result<F64Point, String> {
+        let x = self.number()?;
+        Ok((x, self.number()?))
+    }
+}
+
+fn reflect(control: Option<F64Point>, current: F64Point) -> F64Point {
+    match control {
+        Some(control) => sub2d(scale2d(current, 2.), control),
+        None => current,
+    }
+}
+
+pub fn parse_path(d: &str) -> Result<Vec<PathSegment>, String> {
+    let mut scanner = Scanner{bytes: d.as_bytes(), pos: 0};
+    let mut ret = Vec::new();
+    let mut current = (0., 0.);
+    let mut start = (0., 0.);
+    let mut previous: Option<u8> = None;
+    let mut cubic_control: Option<F64Point> = None; // the second control point, if the last segment was C or S
+    let mut quad_control: Option<F64Point> = None; // likewise for Q and T
+    while !scanner.done() {
+        let letter = match (scanner.command(), previous) {
+            (Some(letter), _) => letter,
+            (None, Some(b'Z')) | (None, Some(b'z')) => return Err("numbers after a closepath in path data".to_string()),
+            (None, Some(letter)) => letter, // the previous command repeats
+            (None, None) => return Err("path data has to start with a moveto".to_string()),
+        };
+        let origin = if letter.is_ascii_lowercase() { current } else { (0., 0.) };
+        let mut next_cubic_control = None;
+        let mut next_quad_control = None;
+        match letter.to_ascii_uppercase() {
+            b'M' => {
+                current = add2d(origin, scanner.point()?);
+                start = current;
+                ret.push(PathSegment::MoveTo(current));
+                previous = Some(if letter == b'M' { b'L' } else { b'l' });
+                cubic_control = None;
+                quad_control = None;
+                continue;
+            },
+            b'L' => {
+                current = add2d(origin, scanner.point()?);
+                ret.push(PathSegment::LineTo(current));
+            },
+            b'H' => {
+                current = (origin.0 + scanner.number()?, current.1);
+                ret.push(PathSegment::LineTo(current));
+            },
+            b'V' => {
+                current = (current.0, origin.1 + scanner.number()?);
+                ret.push(PathSegment::LineTo(current));
+            },
+            b'C' | b'S' => {
+                let first = if letter.eq_ignore_ascii_case(&b'C') {
+                    add2d(origin, scanner.point()?)
+                } else {
+                    reflect(cubic_control, current)
+                };
+                let second = add2d(origin, scanner.point()?);
+                current = add2d(origin, scanner.point()?);
+                ret.push(PathSegment::CubicTo(first, second, current));
+                next_cubic_control = Some(second);
+            },
+            b'Q' | b'T' => {
+                let control = if letter.eq_ignore_ascii_case(&b'Q') {
+                    add2d(origin, scanner.point()?)
+                } else {
+                    reflect(quad_control, current)
+                };
+                current = add2d(origin, scanner.point()?);
+                ret.push(PathSegment::QuadTo(control, current));
+                next_quad_control = Some(control);
+            },
+            b'A' => {
+                let radii = (scanner.number()?, scanner.number()?);
+                let rotation = scanner.number()?;
+                let large_arc = scanner.flag()?;
+                let sweep = scanner.flag()?;
+                current = add2d(origin, scanner.point()?);
+                ret.push(PathSegment::ArcTo{radii, rotation, large_arc, sweep, to: current});
+            },
+            b'Z' => {
+                current = start;
+                ret.push(PathSegment::Close);
+            },
+            _ => return Err(format!("unknown path command {}", letter as char)),
+        }
+        cubic_control = next_cubic_control;
+        quad_control = next_quad_control;
+        previous = Some(letter);
+    }
+    Ok(ret)
+}
+
+pub fn path_deserializer<'de, D>(deserializer: D) -> Result<Vec<PathSegment>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let input = String::deserialize(deserializer)?;
+  parse_path(input.as_str()).map_err(|err| serde::de::Error::custom(Error::PathData(err)))
+}
+
+fn distance_to_line(p: F64Point, a: F64Point, b: F64Point) -> f64 {
+    let ab = sub2d(b, a);
+    let ap = sub2d(p, a);
+    let length = (ab.0 * ab.0 + ab.1 * ab.1).sqrt();
+    if length == 0. {
+        return (ap.0 * ap.0 + ap.1 * ap.1).sqrt();
+    }
+    (ab.0 * ap.1 - ab.1 * ap.0).abs() / length
+}
+
+fn midpoint(a: F64Point, b: F64Point) -> F64Point {
+    scale2d(add2d(a, b), 0.5)
+}
+
+// the curve stays inside the hull of its control points, so once they are all within tolerance
+// of the chord the chord is close enough; otherwise split in half and try again
+fn flatten_cubic(p0: F64Point, c1: F64Point, c2: F64Point, p3: F64Point, tolerance: f64, depth: usize, out: &mut Vec<F64Point>) {
+    if depth >= MAX_SUBDIVISION || distance_to_line(c1, p0, p3).max(distance_to_line(c2, p0, p3)) <= tolerance {
+        out.push(p3);
+        return;
+    }
+    let (a, b, c) = (midpoint(p0, c1), midpoint(c1, c2), midpoint(c2, p3));
+    let (ab, bc) = (midpoint(a, b), midpoint(b, c));
+    let middle = midpoint(ab, bc);
+    flatten_cubic(p0, a, ab, middle, tolerance, depth + 1, out);
+    flatten_cubic(middle, bc, c, p3, tolerance, depth + 1, out);
+}
+
+fn angle_between(u: F64Point, v: F64Point) -> f64 {
+    (u.0 * v.1 - u.1 * v.0).atan2(u.0 * v.0 + u.1 * v.1)
+}
+
+// converts the endpoint form svg uses to a center and sweep, as in the svg implementation notes,
+// and steps along it in angles small enough that no chord sags more than tolerance
+fn flatten_arc(from: F64Point, to: F64Point, radii: F64Point, rotation: f64, (large_arc, sweep): (bool, bool), tolerance: f64, out: &mut Vec<F64Point>) {
+    let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+    if from == to {
+        return;
+    }
+    if rx == 0. || ry == 0. {
+        out.push(to);
+        return;
+    }
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let half = scale2d(sub2d(from, to), 0.5);
+    let (x1, y1) = (cos * half.0 + sin * half.1, -sin * half.0 + cos * half.1);
+    let lambda = x1 * x1 / (rx * rx) + y1 * y1 / (ry * ry);
+    if lambda > 1. {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coefficient = (numerator.max(0.) / denominator).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+    let (cx1, cy1) = (coefficient * rx * y1 / ry, -coefficient * ry * x1 / rx);
+    let center = add2d((cos * cx1 - sin * cy1, sin * cx1 + cos * cy1), midpoint(from, to));
+    let start_angle = angle_between((1., 0.), ((x1 - cx1) / rx, (y1 - cy1) / ry));
+    let mut sweep_angle = angle_between(((x1 - cx1) / rx, (y1 - cy1) / ry), ((-x1 - cx1) / rx, (-y1 - cy1) / ry));
+    if !sweep && sweep_angle > 0. {
+        sweep_angle -= 2. * std::f64::consts::PI;
+    } else if sweep && sweep_angle < 0. {
+        sweep_angle += 2. * std::f64::consts::PI;
+    }
+    let radius = rx.max(ry);
+    let step = if tolerance < radius { 2. * (1. - tolerance / radius).acos() } else { std::f64::consts::FRAC_PI_2 };
+    let count = (sweep_angle.abs() / step).ceil().clamp(1., MAX_ARC_SEGMENTS) as usize;
+    for index in 1..count {
+        let (s, c) = (start_angle + sweep_angle * index as f64 / count as f64).sin_cos();
+        out.push(add2d(center, (rx * c * cos - ry * s * sin, rx * c * sin + ry * s * cos)));
+    }
+    out.push(to);
+}
+
+fn finish(subpath: &mut Subpath, ret: &mut Vec<Subpath>) {
+    let mut done = std::mem::take(subpath);
+    if done.closed && done.points.len() > 1 && done.points.first() == done.points.last() {
+        done.points.pop();
+    }
+    if done.points.len() >= 2 {
+        ret.push(done);
+    }
+}
+
+// polylines no further than tolerance from the path anywhere
+pub fn flatten(segments: &[PathSegment], tolerance: f64) -> Vec<Subpath> {
+    let mut ret = Vec::new();
+    let mut subpath = Subpath::default();
+    let mut current = (0., 0.);
+    let mut start = (0., 0.);
+    for segment in segments {
+        // drawing on after a closepath starts a new subpath where the old one began
+        if subpath.points.is_empty() {
+            subpath.points.push(current);
+        }
+        match *segment {
+            PathSegment::MoveTo(p) => {
+                finish(&mut subpath, &mut ret);
+                subpath.points.push(p);
+                start = p;
+                current = p;
+            },
+            PathSegment::LineTo(p) => {
+                subpath.points.push(p);
+                current = p;
+            },
+            PathSegment::CubicTo(c1, c2, p) => {
+                flatten_cubic(current, c1, c2, p, tolerance, 0, &mut subpath.points);
+                current = p;
+            },
+            PathSegment::QuadTo(c, p) => {
+                // a quadratic is the cubic with its controls two thirds of the way to the quadratic one
+                let c1 = add2d(current, scale2d(sub2d(c, current), 2. / 3.));
+                let c2 = add2d(p, scale2d(sub2d(c, p), 2. / 3.));
+                flatten_cubic(current, c1, c2, p, tolerance, 0, &mut subpath.points);
+                current = p;
+            },
+            PathSegment::ArcTo{radii, rotation, large_arc, sweep, to} => {
+                flatten_arc(current, to, radii, rotation, (large_arc, sweep), tolerance, &mut subpath.points);
+                current = to;
+            },
+            PathSegment::Close => {
+                subpath.closed = true;
+                finish(&mut subpath, &mut ret);
+                current = start;
+            },
+        }
+    }
+    finish(&mut subpath, &mut ret);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_path, flatten, PathSegment, FLATTEN_TOLERANCE};
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path("M10 10h5v-5L0,0zm1-1l2 2").unwrap(), vec![
+            PathSegment::MoveTo((10., 10.)),
+            PathSegment::LineTo((15., 10.)),
+            PathSegment::LineTo((15., 5.)),
+            PathSegment::LineTo((0., 0.)),
+            PathSegment::Close,
+            PathSegment::MoveTo((11., 9.)),
+            PathSegment::LineTo((13., 11.)),
+        ]);
+        // repeated coordinates continue the last command, and a repeated moveto draws lines
+        assert_eq!(parse_path("m1 1 2 2 3-3").unwrap(), vec![
+            PathSegment::MoveTo((1., 1.)),
+            PathSegment::LineTo((3., 3.)),
+            PathSegment::LineTo((6., 0.)),
+        ]);
+        // S and T mirror the previous control point about the current point
+        assert_eq!(parse_path("M0 0C0 1 1 2 2 2s2 -1 2 -2Q5 0 6 0t2 0").unwrap(), vec![
+            PathSegment::MoveTo((0., 0.)),
+            PathSegment::CubicTo((0., 1.), (1., 2.), (2., 2.)),
+            PathSegment::CubicTo((3., 2.), (4., 1.), (4., 0.)),
+            PathSegment::QuadTo((5., 0.), (6., 0.)),
+            PathSegment::QuadTo((7., 0.), (8., 0.)),
+        ]);
+        // compact numbers and flags as editors write them
+        assert_eq!(parse_path("M.5.5a10 10 0 1018-1e1").unwrap(), vec![
+            PathSegment::MoveTo((0.5, 0.5)),
+            PathSegment::ArcTo{radii: (10., 10.), rotation: 0., large_arc: true, sweep: false, to: (18.5, -9.5)},
+        ]);
+        assert!(parse_path("10 10").is_err());
+        assert!(parse_path("M0 0 X1 1").is_err());
+        assert!(parse_path("M0 0 L1").is_err());
+        assert!(parse_path("M0 0 A1 1 0 2 0 3 3").is_err());
+        assert!(parse_path("M0 0z 1 1").is_err());
+    }
+
+    #[test]
+    fn test_flatten_path() {
+        // a circle out of two half arcs stays within tolerance of its radius everywhere
+        let circle = flatten(&parse_path("M20 10A10 10 0 0 1 0 10A10 10 0 0 1 20 10Z").unwrap(), FLATTEN_TOLERANCE);
+        assert_eq!(circle.len(), 1);
+        assert!(circle[0].closed);
+        assert!(circle[0].points.len() > 16);
+        for (index, a) in circle[0].points.iter().enumerate() {
+            let b = circle[0].points[(index + 1) % circle[0].points.len()];
+            let middle = ((a.0 + b.0) / 2. - 10., (a.1 + b.1) / 2. - 10.);
+            assert!(((a.0 - 10.).hypot(a.1 - 10.) - 10.).abs() < 1.0e-9);
+            assert!(10. - middle.0.hypot(middle.1) <= FLATTEN_TOLERANCE);
+        }
+        // the first arc sweeps through the bottom, where y grows
+        assert!(circle[0].points[1..circle[0].points.len() / 2].iter().all(|p| p.1 > 10.));
+
+        // flat curves stay coarse and tight ones get finer
+        let gentle = flatten(&parse_path("M0 0Q50 1 100 0").unwrap(), FLATTEN_TOLERANCE);
+        let sharp = flatten(&parse_path("M0 0Q50 100 100 0").unwrap(), FLATTEN_TOLERANCE);
+        assert!(gentle[0].points.len() < sharp[0].points.len());
+        assert!(!gentle[0].closed);
+
+        // drawing on after a close starts over from where the closed subpath began
+        let reopened = flatten(&parse_path("M0 0H4V4Z L-4 0").unwrap(), FLATTEN_TOLERANCE);
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(reopened[0].points, vec![(0., 0.), (4., 0.), (4., 4.)]);
+        assert_eq!(reopened[1].points, vec![(0., 0.), (-4., 0.)]);
+    }
+}
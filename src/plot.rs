@@ -0,0 +1,120 @@
+// this file flattens an SVG arrangement into plain filled outlines, for pen plotters and vinyl cutters
+// that can follow paths but know nothing of masks or the stamp bitmaps they reference
+use std::path::Path;
+use std::vec::Vec;
+use std::io::Write;
+use super::error::Error;
+use super::svgxml::{SVG, Color, ShapeCache, pack_polygon_points, pack_path_data};
+use super::shape::Shape;
+use super::xmlwriter::{XmlStyle, to_xml_string};
+
+// the visible part of every color in the drawing, in the order the colors first appear. a stamp
+// hides whatever it covers of the stamps below it, so no two layers overlap
+pub fn plot_layers(svg: &SVG) -> Result<Vec<(Color, Shape)>, Error> {
+    let mut cache = ShapeCache::new();
+    let mut ret: Vec<(Color, Shape)> = Vec::new();
+    for stamp in &svg.stamps {
+        let rect = &stamp.rect;
+        let (x0, y0) = (rect.x as f64, rect.y as f64);
+        let (x1, y1) = (x0 + rect.width as f64, y0 + rect.height as f64);
+        let mut local = svg.stamp_shape(stamp, &mut cache)?.intersection(
+            &Shape::from_ring(vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1)]));
+        if !rect.href.clip.is_empty() {
+            match svg.clip_path(&rect.href.clip) {
+                Some(clip_path) => local = local.intersection(&clip_path.to_shape()),
+                None => return Err(Error::UnknownClipPath(rect.href.clip.clone())),
+            }
+        }
+        if local.is_empty() {
+            continue;
+        }
+        let placed = local.transformed(&stamp.transform);
+        for layer in ret.iter_mut() {
+            if layer.0 != rect.fill {
+                layer.1 = layer.1.difference(&placed);
+            }
+        }
+        match ret.iter().position(|layer| layer.0 == rect.fill) {
+            Some(index) => ret[index].1 = ret[index].1.union(&placed),
+            None => ret.push((rect.fill, placed)),
+        }
+    }
+    ret.retain(|layer| !layer.1.is_empty());
+    Ok(ret)
+}
+
+// one group per color, holding a polygon for every piece without holes and an even-odd path for the rest
+pub fn plot_svg(svg: &SVG) -> Result<String, Error> {
+    let (width, height) = svg.extent();
+    let layers = plot_layers(svg)?;
+    to_xml_string(XmlStyle::Lines, |xml| {
+        xml.start("svg", &[("version", &svg.version), ("width", &width.to_string()), ("height", &height.to_string()),
+                           ("xmlns", "http://www.w3.org/2000/svg")])?;
+        for (color, shape) in &layers {
+            xml.start("g", &[("fill", &color.to_string()), ("stroke", "none")])?;
+            for component in shape.components() {
+                if component.rings.len() == 1 {
+                    xml.empty("polygon", &[("points", &pack_polygon_points(&component.rings[0]))])?;
+                } else {
+                    xml.empty("path", &[("fill-rule", "evenodd"), ("d", &pack_path_data(&component.rings))])?;
+                }
+            }
+            xml.end()?;
+        }
+        xml.end()
+    })
+}
+
+pub fn save_plot(svg: &SVG, filename: &Path) -> Result<(), Error> {
+    let data = plot_svg(svg)?;
+    let mut file = std::fs::File::create(filename).map_err(Error::io(filename))?;
+    file.write_all(data.as_bytes()).map_err(Error::io(filename))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{plot_layers, plot_svg};
+    use super::super::svgxml::{SVG, Transform, Color, ClipPath};
+
+    fn placed(x: f64, y: f64) -> Transform {
+        let mut ret = Transform::new(64, 64);
+        ret.tx = x;
+        ret.ty = y;
+        ret
+    }
+
+    #[test]
+    fn test_plot_layers() {
+        let red = Color{r: 0xff, g: 0, b: 0};
+        let blue = Color{r: 0, g: 0, b: 0xff};
+        let square = "assets/stamps/medsquare.bmp".to_string();
+        let mut svg = SVG::new(400, 400);
+        // two red squares overlapping by half, a blue one over their middle, and a red one clipped in half
+        svg.add(placed(0., 0.), square.clone(), String::new(), red);
+        svg.add(placed(15., 0.), square.clone(), String::new(), red);
+        svg.add(placed(20., 20.), square.clone(), String::new(), blue);
+        svg.defs.clipPath.push(ClipPath::from_masks("0".to_string(), &placed(200., 0.), &[placed(232., 0.)]));
+        svg.add(placed(200., 0.), square.clone(), "url(#0)".to_string(), red);
+
+        let layers = plot_layers(&svg).unwrap();
+        assert_eq!(layers.iter().map(|layer| layer.0).collect::<Vec<Color>>(), vec![red, blue]);
+        let (ref reds, ref blues) = (&layers[0].1, &layers[1].1);
+        assert!((blues.area() - 900.).abs() < 1.0e-6);
+        // 45x30 of merged squares less the 25x10 corner under the blue one, and 15x30 left of the clipped one
+        assert!((reds.area() - (45. * 30. - 25. * 10. + 15. * 30.)).abs() < 1.0e-6);
+        assert_eq!(reds.components().len(), 2);
+        assert!(reds.contains((20., 20.)) && !reds.contains((50., 50.)) && blues.contains((50., 50.)));
+        assert!(reds.contains((225., 30.)) && !reds.contains((240., 30.)));
+
+        let text = plot_svg(&svg).unwrap();
+        assert!(text.contains("<g fill=\"#ff0000\" stroke=\"none\">") && text.contains("<g fill=\"#0000ff\" stroke=\"none\">"));
+        assert_eq!(text.matches("<polygon").count(), 3);
+        assert!(!text.contains("mask") && !text.contains("clip") && !text.contains(".bmp"));
+        svg.version = "2.0\" onload=\"".to_string();
+        assert!(plot_svg(&svg).unwrap().starts_with("<svg version=\"2.0&quot; onload=&quot;\" "));
+
+        svg.stamps[3].rect.href.clip = "url(#1)".to_string();
+        assert!(plot_layers(&svg).is_err());
+    }
+}
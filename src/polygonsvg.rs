@@ -1,15 +1,21 @@
-// this file deserializes an SVG into a polygon (ignoring a lot of the complicated features of SVG like masks)
+// this file deserializes a stamp SVG (ignoring a lot of the complicated features of SVG) into a
+// StampOutline that honors fills and masks, for drawing stamp bitmaps and carving doorways,
+// and into the Shape the stamp collides with
 use std::path::Path;
 use std::collections::HashMap;
 use std::vec::Vec;
 use super::serde_xml_rs::from_str;
-use super::serde_xml_rs;
 use std::io::Read;
 use std::fmt::Write;
 use serde::{Deserialize, Deserializer};
 use serde;
-use regex::Regex;
+use regex::{Regex, Captures};
 use std::convert::TryFrom;
+use super::boolean;
+use super::boolean::Ring;
+use super::shape::Shape;
+use super::error::Error;
+use super::path::{PathSegment, Subpath, path_deserializer, flatten, FLATTEN_TOLERANCE};
 pub type F64Point = (f64, f64);
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -19,7 +25,18 @@ pub struct Transform {
     pub rotate: f64,
     pub tx: f64,
     pub ty: f64,
-    pub scale: f64,
+    // stretch along the stamp's own axes, applied before rotating
+    pub scalex: f64,
+    pub scaley: f64,
+    // mirror the stamp across its vertical (flipx) or horizontal (flipy) center line
+    #[serde(default)]
+    pub flipx: bool,
+    #[serde(default)]
+    pub flipy: bool,
+    // set when the transform came from a file and can't be expressed by the fields above
+    // (skews, ...); it then maps stamp coordinates to the document on its own
+    #[serde(default)]
+    pub matrix: Option<Affine>,
 }
 impl Default for Transform {
     fn default() -> Self {
@@ -29,13 +46,67 @@ impl Default for Transform {
 impl Transform {
   pub fn new(width: u32, height: u32) -> Transform {
       Transform{
-          scale:1.0,
+          scalex:1.0,
+          scaley:1.0,
+          flipx:false,
+          flipy:false,
           midx:width as f64/2.0,
           midy:height as f64/2.0,
           rotate:0.0,
           tx:0.0,
           ty:0.0,
+          matrix:None,
+      }
+  }
+  // the x and y scale with the flips folded in as signs
+  pub fn signed_scale(&self) -> F64Point {
+      (if self.flipx { -self.scalex } else { self.scalex },
+       if self.flipy { -self.scaley } else { self.scaley })
+  }
+  pub fn set_signed_scale(&mut self, scale: F64Point) {
+      self.scalex = scale.0.abs();
+      self.scaley = scale.1.abs();
+      self.flipx = scale.0 < 0.0;
+      self.flipy = scale.1 < 0.0;
+  }
+  // the matrix equivalent to ftransform
+  pub fn to_affine(&self) -> Affine {
+      if let Some(matrix) = self.matrix {
+          return matrix;
+      }
+      let scale = self.signed_scale();
+      Affine::translate(self.tx + self.midx, self.ty + self.midy).mul(
+          &Affine::rotate(self.rotate)).mul(
+          &Affine::scale(scale.0, scale.1)).mul(
+          &Affine::translate(-self.midx, -self.midy))
+  }
+  // decomposes m into a scale, flip and rotation about (midx, midy) followed by a translation,
+  // keeping m around whenever that loses information
+  pub fn from_affine(m: &Affine, midx: f64, midy: f64) -> Transform {
+      let center = m.apply((midx, midy));
+      let sx = (m.a * m.a + m.b * m.b).sqrt();
+      let mut ret = Transform{
+          rotate:m.b.atan2(m.a) * 180. / std::f64::consts::PI,
+          midx,
+          midy,
+          tx:center.0 - midx,
+          ty:center.1 - midy,
+          ..Transform::new(0, 0)
+      };
+      if sx != 0.0 {
+          ret.set_signed_scale((sx, m.determinant() / sx));
+      }
+      if ret.to_affine().approx_eq(m) {
+          return ret;
+      }
+      Transform{matrix:Some(*m), ..ret}
+  }
+  // moves the rotation center, eg to the middle of the rect a general matrix was applied to
+  pub fn recenter(&self, midx: f64, midy: f64) -> Transform {
+      if self.midx == midx && self.midy == midy {
+          return self.clone();
       }
+      Transform::from_affine(&self.to_affine(), midx, midy)
   }
   pub fn to_bbox(&self) -> [(f64,f64);4] {
       [ftransform(self, (0.,0.)),
@@ -44,25 +115,31 @@ impl Transform {
        ftransform(self, (self.midx * 2., 0.)),
        ]
   }
-  pub fn to_string(&self) -> Result<String, serde_xml_rs::Error> {
+  pub fn to_string(&self) -> Result<String, Error> {
+    if let Some(matrix) = self.matrix {
+      return Ok(matrix.to_string());
+    }
+    // the same order ftransform applies them in, so any svg viewer places the stamp where the
+    // editor does. older editors wrote a uniform scale(s) first, which is still read back
     let mut components = [String::new(),String::new(),String::new(),String::new(),String::new()];
     let mut num_components = 0usize;
-    if self.scale != 1.0 {
-      components[num_components] = format!("scale({})", self.scale);
-      num_components += 1;
-    }
     if self.tx != 0.0 || self.ty != 0.0 {
       components[num_components] = format!("translate({}, {})", self.tx, self.ty);
       num_components += 1;      
     }
     if self.midx != 0.0 || self.midy != 0.0 {
-      components[num_components] = format!("translate({}, {})", self.midy, self.midy);
+      components[num_components] = format!("translate({}, {})", self.midx, self.midy);
       num_components += 1;      
     }
     if self.rotate != 0.0 {
       components[num_components] = format!("rotate({})", self.rotate);
       num_components += 1;
     }
+    let scale = self.signed_scale();
+    if scale != (1.0, 1.0) {
+      components[num_components] = format!("scale({}, {})", scale.0, scale.1);
+      num_components += 1;
+    }
     if self.midx != 0.0 || self.midy != 0.0 {
       components[num_components] = format!("translate({}, {})", -self.midx, -self.midy);
       num_components += 1;      
@@ -73,33 +150,160 @@ impl Transform {
 
 
 pub fn ftransform(t:&Transform, p: F64Point) -> F64Point {
+    if let Some(matrix) = &t.matrix {
+        return matrix.apply(p);
+    }
     let centered = (p.0 - t.midx, p.1 - t.midy);
+    let scale = t.signed_scale();
+    let scaled = (centered.0 * scale.0, centered.1 * scale.1);
     let rotated;
     if t.rotate != 0.0 {
         let rotate_rad = -t.rotate * std::f64::consts::PI/180.;
-        rotated = (centered.0 * rotate_rad.cos() + centered.1 * rotate_rad.sin(),
-                       -centered.0 * rotate_rad.sin() + centered.1 * rotate_rad.cos());
+        rotated = (scaled.0 * rotate_rad.cos() + scaled.1 * rotate_rad.sin(),
+                       -scaled.0 * rotate_rad.sin() + scaled.1 * rotate_rad.cos());
     } else {
-        rotated = centered;
+        rotated = scaled;
     }
-    let scaled = (rotated.0 * t.scale, rotated.1 * t.scale);
-    let recentered = (scaled.0 + t.midx, scaled.1 + t.midy);
+    let recentered = (rotated.0 + t.midx, rotated.1 + t.midy);
     (recentered.0 + t.tx, recentered.1 + t.ty)
 }
 
-pub fn itransform(t:&Transform, p: F64Point) -> F64Point {
+// None when t flattens everything onto a line or a point, so no point comes from a single place
+pub fn itransform(t:&Transform, p: F64Point) -> Option<F64Point> {
+    if let Some(matrix) = &t.matrix {
+        return matrix.invert().map(|inverse| inverse.apply(p));
+    }
+    let scale = t.signed_scale();
+    if scale.0 == 0.0 || scale.1 == 0.0 || !scale.0.is_finite() || !scale.1.is_finite() {
+        return None;
+    }
     let untranslated = (p.0 - t.tx, p.1 - t.ty);
     let recentered = (untranslated.0 - t.midx, untranslated.1 - t.midy);
-    let unscaled = (recentered.0/t.scale, recentered.1/t.scale);
     let rotate_rad = t.rotate * std::f64::consts::PI/180.;
-    let rotated = (unscaled.0 * rotate_rad.cos() + unscaled.1 * rotate_rad.sin(),
-                   -unscaled.0 * rotate_rad.sin() + unscaled.1 * rotate_rad.cos());
-    let centered = (rotated.0 + t.midx, rotated.1 + t.midy);
-    centered
+    let rotated = (recentered.0 * rotate_rad.cos() + recentered.1 * rotate_rad.sin(),
+                   -recentered.0 * rotate_rad.sin() + recentered.1 * rotate_rad.cos());
+    let unscaled = (rotated.0/scale.0, rotated.1/scale.1);
+    Some((unscaled.0 + t.midx, unscaled.1 + t.midy))
+}
+
+// an SVG matrix(a b c d e f), mapping (x, y) to (a x + c y + e, b x + d y + f)
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct Affine {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+impl Default for Affine {
+    fn default() -> Self {
+        Affine{a:1., b:0., c:0., d:1., e:0., f:0.}
+    }
+}
+impl Affine {
+    pub fn translate(tx: f64, ty: f64) -> Affine {
+        Affine{e:tx, f:ty, ..Affine::default()}
+    }
+    pub fn scale(sx: f64, sy: f64) -> Affine {
+        Affine{a:sx, d:sy, ..Affine::default()}
+    }
+    pub fn rotate(degrees: f64) -> Affine {
+        let rad = degrees * std::f64::consts::PI / 180.;
+        Affine{a:rad.cos(), b:rad.sin(), c:-rad.sin(), d:rad.cos(), e:0., f:0.}
+    }
+    pub fn skew_x(degrees: f64) -> Affine {
+        Affine{c:(degrees * std::f64::consts::PI / 180.).tan(), ..Affine::default()}
+    }
+    pub fn skew_y(degrees: f64) -> Affine {
+        Affine{b:(degrees * std::f64::consts::PI / 180.).tan(), ..Affine::default()}
+    }
+    // the transform applying other first, then self
+    pub fn mul(&self, other: &Affine) -> Affine {
+        Affine{
+            a:self.a * other.a + self.c * other.b,
+            b:self.b * other.a + self.d * other.b,
+            c:self.a * other.c + self.c * other.d,
+            d:self.b * other.c + self.d * other.d,
+            e:self.a * other.e + self.c * other.f + self.e,
+            f:self.b * other.e + self.d * other.f + self.f,
+        }
+    }
+    // the most any length is stretched, the larger singular value of the linear part
+    pub fn max_scale(&self) -> f64 {
+        let sum = self.a * self.a + self.b * self.b + self.c * self.c + self.d * self.d;
+        let determinant = self.determinant();
+        ((sum + (sum * sum - 4. * determinant * determinant).max(0.).sqrt()) / 2.).sqrt()
+    }
+    pub fn determinant(&self) -> f64 {
+        self.a * self.d - self.b * self.c
+    }
+    pub fn invert(&self) -> Option<Affine> {
+        let det = self.determinant();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        Some(Affine{
+            a:self.d / det,
+            b:-self.b / det,
+            c:-self.c / det,
+            d:self.a / det,
+            e:(self.c * self.f - self.d * self.e) / det,
+            f:(self.b * self.e - self.a * self.f) / det,
+        })
+    }
+    pub fn apply(&self, p: F64Point) -> F64Point {
+        (self.a * p.0 + self.c * p.1 + self.e, self.b * p.0 + self.d * p.1 + self.f)
+    }
+    pub fn approx_eq(&self, other: &Affine) -> bool {
+        [self.a - other.a, self.b - other.b, self.c - other.c, self.d - other.d].iter().all(|x| x.abs() < 1.0e-9) &&
+            [self.e - other.e, self.f - other.f].iter().all(|x| x.abs() < 1.0e-6)
+    }
+    // parses any SVG transform list, eg "translate(10) rotate(45, 32 32) skewX(10)"
+    pub fn parse(input: &str) -> Result<Affine, String> {
+        let mut ret = Affine::default();
+        let mut rest = input.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        while !rest.is_empty() {
+            let open = rest.find('(').ok_or_else(|| "Missing ( in transform ".to_string() + input)?;
+            let close = rest.find(')').ok_or_else(|| "Missing ) in transform ".to_string() + input)?;
+            if close < open {
+                return Err("Unbalanced ) in transform ".to_string() + input);
+            }
+            let name = rest[..open].trim();
+            let mut args = Vec::<f64>::new();
+            for arg in rest[open + 1..close].split(|c: char| c.is_whitespace() || c == ',') {
+                if !arg.is_empty() {
+                    args.push(arg.parse::<f64>().map_err(f64_err)?);
+                }
+            }
+            let item = match (name, args.len()) {
+                ("matrix", 6) => Affine{a:args[0], b:args[1], c:args[2], d:args[3], e:args[4], f:args[5]},
+                ("translate", 1) => Affine::translate(args[0], 0.),
+                ("translate", 2) => Affine::translate(args[0], args[1]),
+                ("scale", 1) => Affine::scale(args[0], args[0]),
+                ("scale", 2) => Affine::scale(args[0], args[1]),
+                ("rotate", 1) => Affine::rotate(args[0]),
+                ("rotate", 3) => Affine::translate(args[1], args[2]).mul(
+                    &Affine::rotate(args[0])).mul(&Affine::translate(-args[1], -args[2])),
+                ("skewX", 1) => Affine::skew_x(args[0]),
+                ("skewY", 1) => Affine::skew_y(args[0]),
+                _ => return Err(format!("Unsupported transform {}({}) in {}", name, &rest[open + 1..close], input)),
+            };
+            ret = ret.mul(&item);
+            rest = rest[close + 1..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        }
+        Ok(ret)
+    }
+}
+
+impl std::fmt::Display for Affine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "matrix({}, {}, {}, {}, {}, {})", self.a, self.b, self.c, self.d, self.e, self.f)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
-pub struct defs {
+fn default_stroke_width() -> f64 {
+    1.0
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq,Default)]
@@ -110,6 +314,16 @@ struct Rect {
     pub height:f64,
     #[serde(default)]
     pub fill: String,
+    #[serde(default)]
+    pub mask: String,
+    #[serde(default)]
+    pub stroke: String,
+    #[serde(default="default_stroke_width")]
+    #[serde(rename="stroke-width")]
+    pub stroke_width: f64,
+    #[serde(default)]
+    #[serde(deserialize_with="affine_deserializer")]
+    pub transform: Affine,
 }
 impl Rect {
     fn to_polygon(&self) -> [F64Point;4] {
@@ -129,8 +343,18 @@ struct Ellipse {
     pub ry: f64,
     #[serde(default)]
     pub fill: String,
+    #[serde(default)]
+    pub mask: String,
+    #[serde(default)]
+    pub stroke: String,
+    #[serde(default="default_stroke_width")]
+    #[serde(rename="stroke-width")]
+    pub stroke_width: f64,
+    #[serde(default)]
+    #[serde(deserialize_with="affine_deserializer")]
+    pub transform: Affine,
 }
-const POLYGON_RESOLUTION:usize = 16;
+const MAX_ELLIPSE_SEGMENTS: f64 = 1024.;
 impl From<Circle> for Ellipse {
     fn from(c :Circle) -> Ellipse {
         Ellipse{
@@ -139,17 +363,24 @@ impl From<Circle> for Ellipse {
             rx:c.r,
             ry:c.r,
             fill:c.fill,
+            mask:c.mask,
+            stroke:c.stroke,
+            stroke_width:c.stroke_width,
+            transform:c.transform,
         }
     }
 }
 impl Ellipse {
-    fn to_polygon(&self)  -> [F64Point;POLYGON_RESOLUTION] {
-        let mut ret = [F64Point::default();POLYGON_RESOLUTION];
-        for (index, item) in ret.iter_mut().enumerate() {
-            let angle = index as f64 * std::f64::consts::PI * 2./ POLYGON_RESOLUTION as f64;
-            *item = (self.cx + self.rx * angle.cos(), self.cy + self.ry * angle.sin());
-        }
-        ret
+    // enough sides that no chord strays more than tolerance inside the curve, judged at the
+    // larger radius where the curve bends least and chords are longest
+    fn to_polygon(&self, tolerance: f64) -> Vec<F64Point> {
+        let radius = self.rx.abs().max(self.ry.abs());
+        let step = if tolerance < radius { 2. * (1. - tolerance / radius).acos() } else { std::f64::consts::PI };
+        let count = (2. * std::f64::consts::PI / step).ceil().clamp(3., MAX_ELLIPSE_SEGMENTS) as usize;
+        (0..count).map(|index| {
+            let angle = index as f64 * std::f64::consts::PI * 2. / count as f64;
+            (self.cx + self.rx * angle.cos(), self.cy + self.ry * angle.sin())
+        }).collect()
     }
 }
 
@@ -160,10 +391,20 @@ struct Circle {
     pub r: f64,
     #[serde(default)]
     pub fill: String,
+    #[serde(default)]
+    pub mask: String,
+    #[serde(default)]
+    pub stroke: String,
+    #[serde(default="default_stroke_width")]
+    #[serde(rename="stroke-width")]
+    pub stroke_width: f64,
+    #[serde(default)]
+    #[serde(deserialize_with="affine_deserializer")]
+    pub transform: Affine,
 }
 impl Circle {
-    fn to_polygon(&self)  -> [F64Point;POLYGON_RESOLUTION] {
-        return Ellipse::from(self.clone()).to_polygon()
+    fn to_polygon(&self, tolerance: f64) -> Vec<F64Point> {
+        Ellipse::from(self.clone()).to_polygon(tolerance)
     }
 }
 
@@ -172,123 +413,449 @@ impl Circle {
 struct Polygon {
     #[serde(deserialize_with="point_deserializer")]
     pub points: Vec<F64Point>,
+    #[serde(default)]
+    pub fill: String,
+    #[serde(default)]
+    pub mask: String,
+    #[serde(default)]
+    pub stroke: String,
+    #[serde(default="default_stroke_width")]
+    #[serde(rename="stroke-width")]
+    pub stroke_width: f64,
+    #[serde(default)]
+    #[serde(deserialize_with="affine_deserializer")]
+    pub transform: Affine,
 }
 
-impl Polygon {
-    fn to_polygon(&self)  -> &[F64Point] {
-        return &self.points
+#[derive(Debug, Deserialize, PartialEq, Default)]
+struct PathElement {
+    #[serde(deserialize_with="path_deserializer")]
+    pub d: Vec<PathSegment>,
+    #[serde(default)]
+    pub fill: String,
+    #[serde(default)]
+    pub mask: String,
+    #[serde(default)]
+    pub stroke: String,
+    #[serde(default="default_stroke_width")]
+    #[serde(rename="stroke-width")]
+    pub stroke_width: f64,
+    #[serde(default)]
+    #[serde(deserialize_with="affine_deserializer")]
+    pub transform: Affine,
+}
+
+// the outline a stamp collides with, its overlapping sub-shapes merged and its masks carved out,
+// with curves no further than tolerance stamp pixels from their polygons
+pub fn to_shape(s: &str, tolerance: f64) -> Result<Shape, Error> {
+    Ok(Shape::new(StampOutline::from_str(s)?.to_rings(tolerance)))
+}
+
+// the same shapes kept in document order, so a stamp can be painted the way a browser would,
+// black mask shapes cutting holes in the white ones painted before them
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+enum Element {
+    rect(Rect),
+    ellipse(Ellipse),
+    circle(Circle),
+    polygon(Polygon),
+    path(PathElement),
+    g(Group),
+    defs(Group),
+    mask(MaskElement),
+    // <title>, <desc>, <metadata> and anything else that draws nothing we know how to draw
+    other,
+}
+
+// written out rather than derived because serde_xml_rs only takes a #[serde(other)] variant for an
+// element with no attributes and no content, and <title>Door</title> has content
+impl<'de> Deserialize<'de> for Element {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Element, D::Error> {
+        struct ElementVisitor;
+        impl<'de> serde::de::Visitor<'de> for ElementVisitor {
+            type Value = Element;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an svg element")
+            }
+            fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Element, A::Error> {
+                use serde::de::VariantAccess;
+                let (name, variant): (String, _) = data.variant()?;
+                Ok(match name.as_str() {
+                    "rect" => Element::rect(variant.newtype_variant()?),
+                    "ellipse" => Element::ellipse(variant.newtype_variant()?),
+                    "circle" => Element::circle(variant.newtype_variant()?),
+                    "polygon" => Element::polygon(variant.newtype_variant()?),
+                    "path" => Element::path(variant.newtype_variant()?),
+                    "g" => Element::g(variant.newtype_variant()?),
+                    "defs" => Element::defs(variant.newtype_variant()?),
+                    "mask" => Element::mask(variant.newtype_variant()?),
+                    _ => {
+                        variant.newtype_variant::<serde::de::IgnoredAny>()?;
+                        Element::other
+                    },
+                })
+            }
+        }
+        const ELEMENTS: &[&str] = &["rect", "ellipse", "circle", "polygon", "path", "g", "defs", "mask"];
+        deserializer.deserialize_enum("Element", ELEMENTS, ElementVisitor)
     }
 }
 
+impl Element {
+    // maps the element's own coordinates, which its mask is drawn in too, to its parent's
+    fn transform(&self) -> Affine {
+        match self {
+            Element::rect(r) => r.transform,
+            Element::ellipse(e) => e.transform,
+            Element::circle(c) => c.transform,
+            Element::polygon(poly) => poly.transform,
+            Element::path(path) => path.transform,
+            Element::g(group) => group.transform,
+            Element::defs(_) | Element::mask(_) | Element::other => Affine::default(),
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Default, Deserialize, PartialEq)]
-struct GTransform {
-    #[serde(deserialize_with="transform_deserializer")]
-    pub transform: Transform,
+#[derive(Debug, Deserialize)]
+struct Group {
     #[serde(default)]
-    pub polygon: Vec<Polygon>,
+    #[serde(deserialize_with="affine_deserializer")]
+    pub transform: Affine,
     #[serde(default)]
-    pub rect: Vec<Rect>,
+    pub fill: String,
     #[serde(default)]
-    pub ellipse: Vec<Ellipse>,
+    pub stroke: String,
     #[serde(default)]
-    pub circle: Vec<Circle>,
+    pub mask: String,
+    #[serde(default)]
+    #[serde(rename="$value")]
+    pub children: Vec<Element>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MaskElement {
+    pub id: String,
+    #[serde(default)]
+    #[serde(rename="$value")]
+    pub children: Vec<Element>,
 }
-impl GTransform {
-    pub fn to_polygon(&self) ->Vec<F64Point> {
-        let mut ret = Vec::<F64Point>::new();
-        for poly in &self.polygon {
-            poly_join(&mut ret, poly.to_polygon());
+
+#[derive(Debug, Deserialize)]
+struct StampDocument {
+    #[serde(default)]
+    pub width: f64,
+    #[serde(default)]
+    pub height: f64,
+    #[serde(default)]
+    #[serde(rename="$value")]
+    pub children: Vec<Element>,
+}
+
+// luminance of a fill attribute, None for fill="none"; unknown colors paint black like an unset fill
+fn fill_luminance(fill: &str) -> Option<f64> {
+    match fill {
+        "none" => None,
+        "white" => Some(1.0),
+        fill => match super::svgxml::Color::try_from(fill) {
+            Ok(color) => Some((color.r as f64 * 0.2125 + color.g as f64 * 0.7154 + color.b as f64 * 0.0721) / 255.),
+            Err(_) => Some(0.0),
+        },
+    }
+}
+
+// a premultiplied luminance and alpha pair, as a mask or a layer of the stamp sees a single point
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Paint {
+    pub luminance: f64,
+    pub alpha: f64,
+}
+
+impl Paint {
+    fn over(&mut self, top: Paint) {
+        self.luminance = top.luminance + self.luminance * (1. - top.alpha);
+        self.alpha = top.alpha + self.alpha * (1. - top.alpha);
+    }
+    fn scaled(&self, factor: f64) -> Paint {
+        Paint{luminance: self.luminance * factor, alpha: self.alpha * factor}
+    }
+}
+
+fn pull_masks(elements: &mut Vec<Element>, masks: &mut HashMap<String, Vec<Element>>) {
+    for element in std::mem::take(elements) {
+        match element {
+            Element::mask(mask) => {
+                let mut children = mask.children;
+                pull_masks(&mut children, masks);
+                masks.insert(mask.id, children);
+            },
+            Element::defs(mut defs) => pull_masks(&mut defs.children, masks),
+            Element::other => {},
+            Element::g(mut group) => {
+                pull_masks(&mut group.children, masks);
+                elements.push(Element::g(group));
+            },
+            shape => elements.push(shape),
         }
-        for rect in &self.rect {
-            poly_join(&mut ret, &rect.to_polygon()[..]);
+    }
+}
+
+// the rings a layer paints white, and the rings it paints at all (which hide what is beneath)
+struct Region {
+    white: Vec<Ring>,
+    covered: Vec<Ring>,
+}
+
+impl Region {
+    fn over(&mut self, top: Region) {
+        self.white = boolean::union(&boolean::difference(&self.white, &top.covered), &top.white);
+        self.covered = boolean::union(&self.covered, &top.covered);
+    }
+    fn transformed(mut self, transform: &Affine) -> Region {
+        for ring in self.white.iter_mut().chain(self.covered.iter_mut()) {
+            for vertex in ring.iter_mut() {
+                *vertex = transform.apply(*vertex);
+            }
         }
-        for el in &self.ellipse {
-            poly_join(&mut ret, &el.to_polygon()[..]);
+        self
+    }
+    fn masked(self, mask: Option<Vec<Ring>>) -> Region {
+        match mask {
+            Some(mask) => Region{
+                white: boolean::intersection(&self.white, &mask),
+                covered: boolean::intersection(&self.covered, &mask),
+            },
+            None => self,
         }
-        for cir in &self.circle {
-            poly_join(&mut ret, &cir.to_polygon()[..]);
+    }
+}
+
+// a stamp svg with its masks resolved, ready to be sampled at any point of its width x height box
+pub struct StampOutline {
+    pub width: f64,
+    pub height: f64,
+    elements: Vec<Element>,
+    masks: HashMap<String, Vec<Element>>,
+}
+
+impl StampOutline {
+    pub fn from_str(s: &str) -> Result<StampOutline, Error> {
+        let mut document: StampDocument = from_str(s)?;
+        let mut masks = HashMap::new();
+        pull_masks(&mut document.children, &mut masks);
+        Ok(StampOutline{width: document.width, height: document.height, elements: document.children, masks})
+    }
+    // the outline the stamp collides with: where it paints white-ish once masks are applied.
+    // strokes are left out, and curves are cut into chords that stray at most tolerance inside
+    pub fn to_rings(&self, tolerance: f64) -> Vec<Ring> {
+        self.region(&self.elements, "black", 0, tolerance).white
+    }
+    fn mask_region(&self, mask: &str, depth: usize, tolerance: f64) -> Option<Vec<Ring>> {
+        if mask.is_empty() || depth > 16 {
+            return None;
         }
-        for vertex in &mut ret {
-            *vertex = ftransform(&self.transform, *vertex);
+        self.masks.get(mask.trim().trim_start_matches("url(#").trim_end_matches(')')).map(
+            |children| self.region(children, "black", depth + 1, tolerance).white)
+    }
+    fn region(&self, elements: &[Element], inherited_fill: &str, depth: usize, tolerance: f64) -> Region {
+        let mut ret = Region{white: Vec::new(), covered: Vec::new()};
+        for element in elements {
+            // a scaled up element needs finer chords in its own coordinates
+            let scale = element.transform().max_scale();
+            if scale == 0. || !scale.is_finite() {
+                continue;
+            }
+            let tolerance = tolerance / scale;
+            let (rings, fill, mask) = match element {
+                Element::rect(r) => (vec![r.to_polygon().to_vec()], &r.fill, &r.mask),
+                Element::ellipse(e) => (vec![e.to_polygon(tolerance)], &e.fill, &e.mask),
+                Element::circle(c) => (vec![c.to_polygon(tolerance)], &c.fill, &c.mask),
+                Element::polygon(poly) => (vec![poly.points.clone()], &poly.fill, &poly.mask),
+                Element::path(path) => (flatten(&path.d, tolerance).into_iter().map(|subpath| subpath.points).collect(), &path.fill, &path.mask),
+                Element::g(group) => {
+                    let fill = if group.fill.is_empty() { inherited_fill } else { &group.fill };
+                    let layer = self.region(&group.children, fill, depth, tolerance).masked(self.mask_region(&group.mask, depth, tolerance));
+                    ret.over(layer.transformed(&group.transform));
+                    continue;
+                },
+                Element::defs(_) | Element::mask(_) | Element::other => continue,
+            };
+            let fill = if fill.is_empty() { inherited_fill } else { fill };
+            let luminance = match fill_luminance(fill) {
+                Some(luminance) => luminance,
+                None => continue,
+            };
+            let covered = rings;
+            let layer = Region{white: if luminance >= 0.5 { covered.clone() } else { Vec::new() }, covered};
+            ret.over(layer.masked(self.mask_region(mask, depth, tolerance)).transformed(&element.transform()));
         }
         ret
     }
-}
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-struct PolygonSVG {
-    #[serde(default)]
-    pub defs: defs,
-    #[serde(default)]
-    #[serde(rename="g")]
-    pub section: GTransform,
-}
-fn poly_join(ret: &mut Vec<F64Point>, new_polygon:&[F64Point]){
-    if ret.len() == 0 {
-        ret.extend_from_slice(new_polygon);
-    } else {
-        let last = ret[ret.len() - 1];
-        ret.extend_from_slice(new_polygon);
-        ret.push(last);
+    // what the stamp paints at p
+    pub fn sample(&self, p: F64Point) -> Paint {
+        self.paint(&self.elements, p, ("black", "none"), 0)
+    }
+    fn mask_value(&self, mask: &str, p: F64Point, depth: usize) -> f64 {
+        if mask.is_empty() || depth > 16 {
+            return 1.0; // masks masking themselves give up instead of recursing forever
+        }
+        match self.masks.get(mask.trim().trim_start_matches("url(#").trim_end_matches(')')) {
+            Some(children) => self.paint(children, p, ("black", "none"), depth + 1).luminance,
+            None => 1.0,
+        }
+    }
+    // inherited is the (fill, stroke) of the enclosing group
+    fn paint(&self, elements: &[Element], p: F64Point, inherited: (&str, &str), depth: usize) -> Paint {
+        let mut ret = Paint::default();
+        for element in elements {
+            // an element that collapses to a line or a point paints nothing
+            let p = match element.transform().invert() {
+                Some(inverse) => inverse.apply(p),
+                None => continue,
+            };
+            let corners;
+            let subpaths;
+            let (shape, fill, stroke, stroke_width, mask) = match element {
+                Element::rect(r) => {
+                    corners = r.to_polygon();
+                    (Primitive::Polygon(&corners[..]), &r.fill, &r.stroke, r.stroke_width, &r.mask)
+                },
+                Element::ellipse(e) => (Primitive::Ellipse(e.cx, e.cy, e.rx, e.ry), &e.fill, &e.stroke, e.stroke_width, &e.mask),
+                Element::circle(c) => (Primitive::Ellipse(c.cx, c.cy, c.r, c.r), &c.fill, &c.stroke, c.stroke_width, &c.mask),
+                Element::polygon(poly) => (Primitive::Polygon(&poly.points), &poly.fill, &poly.stroke, poly.stroke_width, &poly.mask),
+                Element::path(path) => {
+                    subpaths = flatten(&path.d, FLATTEN_TOLERANCE);
+                    (Primitive::Path(&subpaths), &path.fill, &path.stroke, path.stroke_width, &path.mask)
+                },
+                Element::g(group) => {
+                    let fill = if group.fill.is_empty() { inherited.0 } else { &group.fill };
+                    let stroke = if group.stroke.is_empty() { inherited.1 } else { &group.stroke };
+                    let layer = self.paint(&group.children, p, (fill, stroke), depth);
+                    ret.over(layer.scaled(self.mask_value(&group.mask, p, depth)));
+                    continue;
+                },
+                Element::defs(_) | Element::mask(_) | Element::other => continue,
+            };
+            let fill = if fill.is_empty() { inherited.0 } else { fill };
+            let stroke = if stroke.is_empty() { inherited.1 } else { stroke };
+            let mut layer = Paint::default();
+            if shape.contains(p) {
+                if let Some(luminance) = fill_luminance(fill) {
+                    layer.over(Paint{luminance, alpha: 1.0});
+                }
+            }
+            if let Some(luminance) = fill_luminance(stroke) {
+                if shape.edge_distance(p) <= stroke_width / 2. {
+                    layer.over(Paint{luminance, alpha: 1.0});
+                }
+            }
+            ret.over(layer.scaled(self.mask_value(mask, p, depth)));
+        }
+        ret
     }
 }
 
-impl PolygonSVG {
-    pub fn from_str(s: &str) -> Result<Self,serde_xml_rs::Error> {
-        use super::serde_xml_rs::from_str;
-        from_str(s)
+// the geometry of a stamp primitive, in the coordinates of its group
+enum Primitive<'a> {
+    Polygon(&'a [F64Point]),
+    Path(&'a [Subpath]), // filled even-odd
+    Ellipse(f64, f64, f64, f64), // cx, cy, rx, ry
+}
+
+impl<'a> Primitive<'a> {
+    fn contains(&self, p: F64Point) -> bool {
+        match *self {
+            Primitive::Polygon(points) => point_in_polygon(p, points),
+            Primitive::Path(subpaths) => subpaths.iter().filter(|subpath| point_in_polygon(p, &subpath.points)).count() % 2 == 1,
+            Primitive::Ellipse(cx, cy, rx, ry) => {
+                if rx <= 0. || ry <= 0. {
+                    return false;
+                }
+                let (dx, dy) = ((p.0 - cx) / rx, (p.1 - cy) / ry);
+                dx * dx + dy * dy <= 1.
+            },
+        }
     }
-    pub fn to_polygon(&self) ->Vec<F64Point> {
-        let mut ret = self.section.to_polygon();
-        ret
+    // how far p is from the outline, measured along the ray from the center for ellipses
+    fn edge_distance(&self, p: F64Point) -> f64 {
+        match *self {
+            Primitive::Polygon(points) => polyline_distance(p, points, true),
+            Primitive::Path(subpaths) => subpaths.iter().map(
+                |subpath| polyline_distance(p, &subpath.points, subpath.closed)).fold(f64::MAX, f64::min),
+            Primitive::Ellipse(cx, cy, rx, ry) => {
+                if rx <= 0. || ry <= 0. {
+                    return f64::MAX;
+                }
+                let (dx, dy) = ((p.0 - cx) / rx, (p.1 - cy) / ry);
+                let k = (dx * dx + dy * dy).sqrt();
+                if k == 0. {
+                    return rx.min(ry);
+                }
+                let offset = (p.0 - cx, p.1 - cy);
+                dot2d(offset, offset).sqrt() * (1. - 1. / k).abs()
+            },
+        }
     }
 }
-pub fn to_polygon(s: &str) -> Result<Vec<F64Point>, serde_xml_rs::Error> {
-    Ok(PolygonSVG::from_str(s)?.to_polygon())
+fn polyline_distance(p: F64Point, points: &[F64Point], closed: bool) -> f64 {
+    let mut ret = f64::MAX;
+    let count = if closed { points.len() } else { points.len().saturating_sub(1) };
+    for (index, a) in points.iter().enumerate().take(count) {
+        let b = points[(index + 1) % points.len()];
+        let ab = sub2d(b, *a);
+        let length2 = dot2d(ab, ab);
+        let t = if length2 > 0. { (dot2d(sub2d(p, *a), ab) / length2).clamp(0., 1.) } else { 0. };
+        let d = sub2d(p, add2d(*a, scale2d(ab, t)));
+        ret = ret.min(dot2d(d, d).sqrt());
+    }
+    ret
 }
 fn f64_err(e: std::num::ParseFloatError) -> String {
   format!("{}", e).to_string()
 }
 
-const TFORM_REGEX_STR: &'static str = r"^\s*(?:scale\(\s*([^\)]+)\)\s*)?(?:translate\(\s*([^,]+),\s*([^\)]+)\)\s*)?\s*(?:translate\(\s*([^,]+),\s*([^\)]+)\)\s*)?(?:rotate\(\s*([^\)]+)\)\s*)?(?:translate\(\s*([^,]+),\s*([^\)]+)\s*\)?)\s*$";
+// the forms the editor writes, one number to each capture. anything else, like scale(2 3) or a
+// matrix, is a general svg transform list for Affine::parse
+const TFORM_REGEX_STR: &'static str = r"^\s*(?:scale\(\s*([-+0-9.eE]+)\s*\)\s*)?(?:translate\(\s*([-+0-9.eE]+),\s*([-+0-9.eE]+)\s*\)\s*)?\s*(?:translate\(\s*([-+0-9.eE]+),\s*([-+0-9.eE]+)\s*\)\s*)?(?:rotate\(\s*([-+0-9.eE]+)\s*\)\s*)?(?:scale\(\s*([-+0-9.eE]+),\s*([-+0-9.eE]+)\s*\)\s*)?(?:translate\(\s*([-+0-9.eE]+),\s*([-+0-9.eE]+)\s*\)?)\s*$";
 fn gen_transform_deserializer(input:&str) -> Result<Transform, String> {
   let tform = Regex::new(TFORM_REGEX_STR).unwrap(); // don't use lazy static dependency
   // only happens during IO, so the simplicity is worth it
-  let matches_opt = tform.captures(input);
-  if matches_opt.is_none() {
-    return Err("No matches for ".to_string() + input)
-  }
-  let matches = matches_opt.unwrap();
-  let mut ret = Transform{scale:1.0,rotate:0.0,tx:0.0,ty:0.0,midx:0.0,midy:0.0};
-  if let Some(scale) = matches.get(1) {
-    ret.scale = scale.as_str().parse::<f64>().map_err(f64_err)?;
+  match tform.captures(input).and_then(|matches| editor_transform(&matches)) {
+    Some(ret) => Ok(ret),
+    // not written by the editor: keep the exact matrix (centered by SVG::from_str once the rect is known)
+    None => Ok(Transform::from_affine(&Affine::parse(input)?, 0.0, 0.0)),
   }
-  if let Some(tx) = matches.get(2) {
-    if let Some(ty) = matches.get(3) {
-      ret.tx = tx.as_str().parse::<f64>().map_err(f64_err)?;
-      ret.ty = ty.as_str().parse::<f64>().map_err(f64_err)?;
+}
+// None when the captures don't describe a transform the editor could have written
+fn editor_transform(matches: &Captures) -> Option<Transform> {
+  let number = |index: usize| -> Option<Option<f64>> {
+    match matches.get(index) {
+      Some(capture) => capture.as_str().parse::<f64>().ok().map(Some),
+      None => Some(None),
     }
+  };
+  let mut ret = Transform::new(0, 0);
+  let mut scale = (1.0, 1.0);
+  if let Some(uniform_scale) = number(1)? {
+    scale = (uniform_scale, uniform_scale);
   }
-  if let Some(midx) = matches.get(4) {
-    if let Some(midy) = matches.get(5) {
-      ret.midx = midx.as_str().parse::<f64>().map_err(f64_err)?;
-      ret.midy = midy.as_str().parse::<f64>().map_err(f64_err)?;
-    }
+  if let (Some(sx), Some(sy)) = (number(7)?, number(8)?) {
+    scale.0 *= sx;
+    scale.1 *= sy;
   }
-  let ix;
-  let iy;
-  if let Some(rmidx) = matches.get(7) {
-    if let Some(rmidy) = matches.get(8) {
-      ix = rmidx.as_str().parse::<f64>().map_err(f64_err)?;
-      iy = rmidy.as_str().parse::<f64>().map_err(f64_err)?;
-    } else {
-      ix = 0.0;
-      iy = 0.0;
-    }
-  } else {
-    ix = 0.0;
-    iy = 0.0;
+  ret.set_signed_scale(scale);
+  if let (Some(tx), Some(ty)) = (number(2)?, number(3)?) {
+    ret.tx = tx;
+    ret.ty = ty;
   }
+  if let (Some(midx), Some(midy)) = (number(4)?, number(5)?) {
+    ret.midx = midx;
+    ret.midy = midy;
+  }
+  let (ix, iy) = match (number(9)?, number(10)?) {
+    (Some(rmidx), Some(rmidy)) => (rmidx, rmidy),
+    _ => (0.0, 0.0),
+  };
   if ret.midx != -ix || ret.midy != -iy {
     if ret.midx == 0.0 && ret.midy == 0.0 && ret.tx == -ix && ret.ty == -iy {
       ret.midx = ret.tx;
@@ -296,14 +863,13 @@ fn gen_transform_deserializer(input:&str) -> Result<Transform, String> {
       ret.tx = 0.0;
       ret.ty = 0.0;
     } else {
-      return Err(format!("translate({},{}) != -translate({},{}) = translate({}, {})",
-                         ret.midx, ret.midy, ix, iy, -ix, -iy))
+      return None;
     }
   }
-  if let Some(rotate) = matches.get(6) {
-    ret.rotate = rotate.as_str().parse::<f64>().map_err(f64_err)?;
+  if let Some(rotate) = number(6)? {
+    ret.rotate = rotate;
   }
-  Ok(ret)
+  Some(ret)
 }
 
 pub fn point_deserializer<'de, D>(deserializer: D) -> Result<Vec<F64Point>, D::Error>
@@ -311,16 +877,24 @@ where
   D: Deserializer<'de>,
 {
   let input = String::deserialize(deserializer)?;
-  unpack_polygon_points(input.as_str()).map_err(serde::de::Error::custom)
+  unpack_polygon_points(input.as_str()).map_err(|err| serde::de::Error::custom(Error::Points(err)))
 }
 
 
+pub fn affine_deserializer<'de, D>(deserializer: D) -> Result<Affine, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let input = String::deserialize(deserializer)?;
+  Affine::parse(input.as_str()).map_err(|err| serde::de::Error::custom(Error::Transform(err)))
+}
+
 pub fn transform_deserializer<'de, D>(deserializer: D) -> Result<Transform, D::Error>
 where
   D: Deserializer<'de>,
 {
   let input = String::deserialize(deserializer)?;
-  gen_transform_deserializer(input.as_str()).map_err(serde::de::Error::custom)
+  gen_transform_deserializer(input.as_str()).map_err(|err| serde::de::Error::custom(Error::Transform(err)))
 }
 fn unpack_polygon_points(input:&str) -> Result<Vec<F64Point>, String> {
     let mut ret = Vec::<F64Point>::new();
@@ -413,7 +987,7 @@ pub struct RayParamAndHitCount {
     segment:(F64Point,F64Point),
 }
 pub fn ray_vs_polygon(
-    origin: F64Point, dir: F64Point, poly_transform: &Transform, poly: &[F64Point],
+    origin: F64Point, dir: F64Point, poly_transform: &Transform, poly: &Shape,
 ) -> Option<RayParamAndHitCount> {
     if let Some(mut ret) = ray_vs_polygon_helper(origin,dir,poly_transform, poly) {
         if ret.inside {
@@ -435,17 +1009,14 @@ pub fn ray_vs_polygon(
     
 }
 pub fn ray_vs_polygon_helper(
-    origin: F64Point, dir: F64Point, poly_transform: &Transform, poly: &[F64Point],
+    origin: F64Point, dir: F64Point, poly_transform: &Transform, poly: &Shape,
 ) -> Option<RayParamAndHitCount> {
-    if poly.len() == 0 {
-        return None;
-    }
-    let mut last = ftransform(poly_transform, poly[poly.len() - 1]);
     let mut hit_count = 0;
     let mut ret: Option<f64> = None;
     let mut segment: (F64Point,F64Point) = ((0.0,0.0),(0.0,0.0));
-    for point in poly {
-        let cur_point = ftransform(poly_transform, *point);
+    for (last, cur_point) in poly.edges() {
+        let last = ftransform(poly_transform, last);
+        let cur_point = ftransform(poly_transform, cur_point);
         //eprintln!("CHecking {:?} - {:?}", last, cur_point);
         if let Some(t) = ray_vs_segment(origin, dir, last, cur_point) {
             if let Some(t_old) = ret {
@@ -460,7 +1031,6 @@ pub fn ray_vs_polygon_helper(
             hit_count += 1;
             //eprintln!("Ray {:?} -> {:?} hit {:?}->{:?} at {:?} hc={}", origin, dir, last, cur_point, ret, hit_count);
         }
-        last = cur_point;
     }
     if let Some(t) = ret {
         Some(RayParamAndHitCount{
@@ -472,7 +1042,25 @@ pub fn ray_vs_polygon_helper(
         None
     }
 }
-pub fn origin_inside_polygon(origin: F64Point, dir: F64Point, poly_transform: &Transform, poly: &[F64Point]) -> Option<f64> {
+// even-odd, which is how the editor carves the paper masks out of a clip path
+pub fn point_in_polygon(p: F64Point, poly: &[F64Point]) -> bool {
+    let mut inside = false;
+    if poly.is_empty() {
+        return false;
+    }
+    let mut last = poly[poly.len() - 1];
+    for cur in poly {
+        if (cur.1 > p.1) != (last.1 > p.1) {
+            let x = (last.0 - cur.0) * (p.1 - cur.1) / (last.1 - cur.1) + cur.0;
+            if p.0 < x {
+                inside = !inside;
+            }
+        }
+        last = *cur;
+    }
+    inside
+}
+pub fn origin_inside_polygon(origin: F64Point, dir: F64Point, poly_transform: &Transform, poly: &Shape) -> Option<f64> {
     let ret = ray_vs_polygon(origin, dir, poly_transform, poly);
     if let Some(ray_param) = ret {
         if ray_param.inside {
@@ -483,7 +1071,7 @@ pub fn origin_inside_polygon(origin: F64Point, dir: F64Point, poly_transform: &T
 }
 
 
-pub fn segment_inside_polygon(a: F64Point, b: F64Point, poly_transform: &Transform, poly: &[F64Point], up: F64Point) -> Option<PolyIntersection> {
+pub fn segment_inside_polygon(a: F64Point, b: F64Point, poly_transform: &Transform, poly: &Shape, up: F64Point) -> Option<PolyIntersection> {
     let default_segment  = ((0.0,0.0),(0.0,0.0));
     let mut a_p_intersection = ray_vs_polygon(a, sub2d(b, a), poly_transform, poly);
     let mut b_p_intersection = ray_vs_polygon(b, sub2d(a, b), poly_transform, poly);
@@ -551,6 +1139,7 @@ mod test {
   fn test_ray_polygon_intersect() {
       use super::origin_inside_polygon;
       use super::Transform;
+      use super::Shape;
       let t = &Transform::default();
       /*
       // a ray that would otherwise hit a polygon does not trigger a "inside" unless it starts inside
@@ -564,14 +1153,26 @@ mod test {
       assert_eq!(origin_inside_polygon((12.75,12.75),(-1.,-1.),t, &[(13.,11.),(11.,13.), (14.,13.)]), Some(0.75));
 */
       assert_eq!(origin_inside_polygon((0.,180.),(128.,64.),t,
-                                       &[(640.0, 500.0),
+                                       &Shape::from_ring(vec![(640.0, 500.0),
                                          (672.0, 500.0),
                                          (672.0, 532.0),
                                          (640.0, 532.0),
                                          (640.0, 500.0),
                                          (672.0, 500.0),
                                          (672.0, 532.0),
-                                         (640.0, 532.0)]), None);
+                                         (640.0, 532.0)])), None);
+  }
+  #[test]
+  fn test_point_in_polygon() {
+      use super::point_in_polygon;
+      let square = [(0., 0.), (10., 0.), (10., 10.), (0., 10.)];
+      assert!(point_in_polygon((5., 5.), &square));
+      assert!(!point_in_polygon((15., 5.), &square));
+      // a square with a hole carved by a return edge, like the editor's clip paths
+      let holed = [(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.),
+                   (4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.), (0., 0.)];
+      assert!(point_in_polygon((2., 2.), &holed));
+      assert!(!point_in_polygon((5., 5.), &holed));
   }
   #[test]
   fn test_capture_segment_inside_polygon() {
@@ -579,23 +1180,24 @@ mod test {
       use super::segment_inside_polygon;
       use super::ray_vs_polygon;
       use super::RayParamAndHitCount;
+      use super::Shape;
       assert_eq!(ray_vs_polygon(
           (0.0,180.0),
           super::sub2d((128.0,244.0), (0.0,180.0)),
           &Transform::default(),
-          &[(16.0 + 624.0, 16.0+484.0), (48.0 + 624.0, 16.0+484.0), (48.0+624.0, 48.0+484.0), (16.0+624.0, 48.0+484.0)],
+          &Shape::from_ring(vec![(16.0 + 624.0, 16.0+484.0), (48.0 + 624.0, 16.0+484.0), (48.0+624.0, 48.0+484.0), (16.0+624.0, 48.0+484.0)]),
       ), Some(RayParamAndHitCount{t:5.,inside:false, segment:((640.0,500.0),(672.0,500.0))}));
       assert_eq!(segment_inside_polygon(
           (128.0,244.0), (0.0,180.0),
           &Transform::default(), 
-          &[(16.0 + 624.0, 16.0+484.0), (48.0 + 624.0, 16.0+484.0), (48.0+624.0, 48.0+484.0), (16.0+624.0, 48.0+484.0)],
+          &Shape::from_ring(vec![(16.0 + 624.0, 16.0+484.0), (48.0 + 624.0, 16.0+484.0), (48.0+624.0, 48.0+484.0), (16.0+624.0, 48.0+484.0)]),
           (0.0,-1.0),
       ), None);
       /*
       assert_eq!(segment_inside_polygon(
           (128.0,244.0), (0.0,180.0),
           &Transform{
-              midx:32.0, midy:32.0,rotate:0.0, tx:624.0, ty:484.0, scale:1.0,
+              midx:32.0, midy:32.0,rotate:0.0, tx:624.0, ty:484.0, ..Transform::default()
           }, 
           &[(16.0, 16.0), (48.0, 16.0), (48.0, 48.0), (16.0, 48.0)],
           (0.0,-1.0),
@@ -608,64 +1210,65 @@ mod test {
       use super::Transform;
       use super::segment_inside_polygon;
       use super::PolyIntersection;
-      let aabb = [(-4.,2.), (3.,2.), (3.,-1.),(-4.,-1.)];
-      assert_eq!(segment_inside_polygon((-100.,-100.),(100.,-100.), t,&aabb[..], (0.,1.)), None);
-      assert_eq!(segment_inside_polygon((-5.,0.5),(1.,0.5), t, &aabb[..], (0.,1.)),
+      use super::Shape;
+      let aabb = Shape::from_ring(vec![(-4.,2.), (3.,2.), (3.,-1.),(-4.,-1.)]);
+      assert_eq!(segment_inside_polygon((-100.,-100.),(100.,-100.), t,&aabb, (0.,1.)), None);
+      assert_eq!(segment_inside_polygon((-5.,0.5),(1.,0.5), t, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(-5.,0.)}));
                  //xx Some(PolyIntersection{outward:(0.,1.5)}));
-      assert_eq!(segment_inside_polygon((0.,0.5),(1.,0.5), t, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((0.,0.5),(1.,0.5), t, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,1.5)}));
 
-      assert_eq!(segment_inside_polygon((-5.,0.5),(4.,0.5), t, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-5.,0.5),(4.,0.5), t, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.0,1.5)}));
-      assert_eq!(segment_inside_polygon((-3.,0.5),(4.,0.5), t, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-3.,0.5),(4.,0.5), t, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(6.,0.)}));
                  //xx Some(PolyIntersection{outward:(0.,1.5)}));
-      assert_eq!(segment_inside_polygon((-5.,0.5),(2.5,0.5), t, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-5.,0.5),(2.5,0.5), t, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(-6.5,0.)}));
                  //xx Some(PolyIntersection{outward:(0.0,1.5)}));
 
-      assert_eq!(segment_inside_polygon((0.,-4.),(0.,4.), t, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((0.,-4.),(0.,4.), t, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,1.5)}));
 
 
-      let shift_right = &Transform{midx:32.,midy:32.,rotate:0.,tx:1.,ty:1.,scale:1.};
+      let shift_right = &Transform{midx:32.,midy:32.,rotate:0.,tx:1.,ty:1.,..Transform::default()};
       
-      assert_eq!(segment_inside_polygon((-100.,-100.),(100.,-100.), shift_right,&aabb[..], (0.,1.)), None);
-      assert_eq!(segment_inside_polygon((-5.,0.5),(1.,0.5), shift_right, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-100.,-100.),(100.,-100.), shift_right,&aabb, (0.,1.)), None);
+      assert_eq!(segment_inside_polygon((-5.,0.5),(1.,0.5), shift_right, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(-4.,0.)}));
                  //xx Some(PolyIntersection{outward:(0.,2.5)}));
-      assert_eq!(segment_inside_polygon((0.,0.5),(1.,0.5), shift_right, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((0.,0.5),(1.,0.5), shift_right, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,2.5)}));
 
-      assert_eq!(segment_inside_polygon((-5.,0.5),(4.,0.5), shift_right, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-5.,0.5),(4.,0.5), shift_right, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,2.5)}));
-      assert_eq!(segment_inside_polygon((-3.,0.5),(4.,0.5), shift_right, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-3.,0.5),(4.,0.5), shift_right, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,2.5)}));
-      assert_eq!(segment_inside_polygon((-5.,0.5),(2.5,0.5), shift_right, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-5.,0.5),(2.5,0.5), shift_right, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(-5.5,0.)}));
                  //xx Some(PolyIntersection{outward:(0.0,2.5)}));
 
-      assert_eq!(segment_inside_polygon((0.,-4.),(0.,4.), shift_right, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((0.,-4.),(0.,4.), shift_right, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,1.5)}));
 
-      let shift_scale = &Transform{midx:0.5,midy:0.5,rotate:0.,tx:1.,ty:1.,scale:2.};
+      let shift_scale = &Transform{midx:0.5,midy:0.5,rotate:0.,tx:1.,ty:1.,scalex:2.,scaley:2.,..Transform::default()};
       
-      assert_eq!(segment_inside_polygon((-100.,-100.),(100.,-100.), shift_scale,&aabb[..], (0.,1.)), None);
-      assert_eq!(segment_inside_polygon((-8.,0.5),(1.,0.5), shift_scale, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-100.,-100.),(100.,-100.), shift_scale,&aabb, (0.,1.)), None);
+      assert_eq!(segment_inside_polygon((-8.,0.5),(1.,0.5), shift_scale, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(-8.5,0.)}));
                  //xx Some(PolyIntersection{outward:(0.0,4.)}));
-      assert_eq!(segment_inside_polygon((0.,0.5),(1.,0.5), shift_scale, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((0.,0.5),(1.,0.5), shift_scale, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,4.)}));
 
-      assert_eq!(segment_inside_polygon((-5.,0.5),(4.,0.5), shift_scale, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-5.,0.5),(4.,0.5), shift_scale, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,4.)}));
-      assert_eq!(segment_inside_polygon((-3.,0.5),(4.,0.5), shift_scale, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-3.,0.5),(4.,0.5), shift_scale, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,4.)}));
-      assert_eq!(segment_inside_polygon((-5.,0.5),(2.5,0.5), shift_scale, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((-5.,0.5),(2.5,0.5), shift_scale, &aabb, (0.,1.)),
                  Some(PolyIntersection{outward:(0.,4.)}));
 
-      assert_eq!(segment_inside_polygon((0.,-4.),(0.,4.), shift_scale, &aabb[..], (0.,1.)),
+      assert_eq!(segment_inside_polygon((0.,-4.),(0.,4.), shift_scale, &aabb, (0.,1.)),
       //xx            Some(PolyIntersection{outward:(0.,4.5)}));
                  Some(PolyIntersection{outward:(0.,-5.5)}));
 
@@ -695,6 +1298,108 @@ mod test {
     tform.captures("translate(290, 80) translate(64, 64) rotate(220) translate(-64, -64)").unwrap();
   }
   #[test]
+  fn test_parse_general_transform() {
+      use super::{Affine, Transform, ftransform, itransform, gen_transform_deserializer};
+      let skew = gen_transform_deserializer("translate(10 20) skewX(45)").unwrap();
+      assert_eq!(skew.matrix, Some(Affine{a:1., b:0., c:(std::f64::consts::PI / 4.).tan(), d:1., e:10., f:20.}));
+      assert_eq!(ftransform(&skew, (0., 0.)), (10., 20.));
+      let skewed = ftransform(&skew, (0., 10.));
+      assert!((skewed.0 - 20.).abs() < 1.0e-9 && skewed.1 == 30.);
+      let back = itransform(&skew, skewed).unwrap();
+      assert!(back.0.abs() < 1.0e-9 && (back.1 - 10.).abs() < 1.0e-9);
+      assert_eq!(gen_transform_deserializer(&skew.to_string().unwrap()).unwrap(), skew);
+
+      // a rotation about a point is exactly what the editor fields describe
+      let rotated = gen_transform_deserializer("rotate(90, 32, 32)").unwrap().recenter(32., 32.);
+      assert_eq!(rotated.matrix, None);
+      assert_eq!((rotated.tx, rotated.ty, rotated.rotate), (0., 0., 90.));
+      let inkscape = gen_transform_deserializer("matrix(0,1,-1,0,100,5)").unwrap().recenter(32., 32.);
+      assert_eq!(inkscape.matrix, None);
+      assert_eq!((inkscape.tx.round(), inkscape.ty.round(), inkscape.rotate), (36., 5., 90.));
+      let twice = gen_transform_deserializer("rotate(30) rotate(60) translate(1)").unwrap();
+      let expected = Affine::rotate(90.).mul(&Affine::translate(1., 0.));
+      assert!(twice.to_affine().approx_eq(&expected));
+
+      // space separated arguments look enough like the editor's forms to be matched by them
+      for list in &["scale(2 3) translate(5, 5)", "translate(10 20) rotate(30) translate(-5, -5)",
+                    "rotate(45 32 32) scale(2)", "scale(2) translate(5 5) rotate(30 10 10) translate(-5, -5)"] {
+          let parsed = gen_transform_deserializer(list).unwrap();
+          assert!(parsed.to_affine().approx_eq(&Affine::parse(list).unwrap()), "{}", list);
+      }
+      let svg = super::super::svgxml::SVG::from_str(r##"<svg version="2.0" width="500" height="500">
+<g transform="scale(2 3) translate(5, 5)"><rect x="0" y="0" width="64" height="64" fill="#000000" mask="url(#stamps/rect.bmp)"/></g>
+</svg>"##).unwrap();
+      assert_eq!(ftransform(&svg.stamps[0].transform, (1., 1.)), (12., 18.));
+
+      // legacy editor output keeps its fields
+      let legacy = gen_transform_deserializer("scale(2) translate(64, 64) rotate(8) translate(-64, -64)").unwrap();
+      assert_eq!(legacy, Transform{scalex:2., scaley:2., tx:0., ty:0., rotate:8., midx:64., midy:64., ..Transform::default()});
+
+      assert!(gen_transform_deserializer("rotate(1, 2)").is_err());
+      assert!(gen_transform_deserializer("perspective(3)").is_err());
+      assert!(gen_transform_deserializer("translate(1, 2").is_err());
+  }
+  #[test]
+  fn test_scale_and_flip() {
+      use super::{Transform, ftransform, itransform, gen_transform_deserializer};
+      let mut beam = Transform::new(64, 64);
+      beam.scalex = 4.;
+      beam.scaley = 0.5;
+      beam.tx = 10.;
+      assert_eq!(ftransform(&beam, (0., 0.)), (10. - 96., 16.));
+      assert_eq!(ftransform(&beam, (64., 64.)), (10. + 160., 48.));
+      beam.rotate = 90.;
+      let end = ftransform(&beam, (64., 32.));
+      assert!((end.0 - 42.).abs() < 1.0e-9 && (end.1 - 160.).abs() < 1.0e-9); // stretched along the stamp's axis
+      let back = itransform(&beam, end).unwrap();
+      assert!((back.0 - 64.).abs() < 1.0e-9 && (back.1 - 32.).abs() < 1.0e-9);
+      assert_eq!(beam.to_string().unwrap(), "translate(10, 0) translate(32, 32) rotate(90) scale(4, 0.5) translate(-32, -32)");
+      assert_eq!(gen_transform_deserializer(&beam.to_string().unwrap()).unwrap(), beam);
+      assert!(beam.to_affine().apply((64., 32.)) == ftransform(&beam, (64., 32.)));
+
+      let mut mirror = Transform::new(64, 64);
+      mirror.flipx = true;
+      assert_eq!(ftransform(&mirror, (17., 63.)), (47., 63.));
+      assert_eq!(itransform(&mirror, (47., 63.)), Some((17., 63.)));
+      assert_eq!(mirror.to_bbox()[0], (64., 0.));
+      assert_eq!(mirror.to_string().unwrap(), "translate(32, 32) scale(-1, 1) translate(-32, -32)");
+      assert_eq!(gen_transform_deserializer(&mirror.to_string().unwrap()).unwrap(), mirror);
+      let general = gen_transform_deserializer("matrix(1 0 0 -2 0 128)").unwrap().recenter(32., 32.);
+      assert_eq!((general.matrix, general.flipx, general.flipy, general.scalex, general.scaley), (None, false, true, 1., 2.));
+  }
+  #[test]
+  fn test_written_transform_matches_editor() {
+      use super::{Transform, Affine, ftransform, gen_transform_deserializer};
+      let mut uniform = Transform::new(64, 64);
+      uniform.set_signed_scale((2., 2.));
+      uniform.tx = 100.;
+      uniform.ty = 50.;
+      let mut stretched = uniform.clone();
+      stretched.set_signed_scale((2., 0.5));
+      stretched.rotate = 30.;
+      let mut flipped = uniform.clone();
+      flipped.set_signed_scale((-2., 2.));
+      flipped.rotate = -75.;
+      // as small as the brackets shrink a stamp
+      let mut shrunk = uniform.clone();
+      shrunk.set_signed_scale((1. / 16., 1. / 16.));
+      shrunk.rotate = 90.;
+      for t in &[uniform.clone(), stretched, flipped, shrunk] {
+          let written = t.to_string().unwrap();
+          // what any svg viewer makes of the attribute
+          let viewer = Affine::parse(&written).unwrap();
+          assert!(viewer.approx_eq(&t.to_affine()), "{}", written);
+          for p in &[(0., 0.), (64., 0.), (17., 40.)] {
+              let (a, b) = (viewer.apply(*p), ftransform(t, *p));
+              assert!((a.0 - b.0).abs() < 1.0e-9 && (a.1 - b.1).abs() < 1.0e-9, "{} at {:?}", written, p);
+          }
+          let reread = gen_transform_deserializer(&written).unwrap();
+          assert!(reread.to_affine().approx_eq(&t.to_affine()));
+      }
+      assert_eq!(uniform.to_string().unwrap(), "translate(100, 50) translate(32, 32) scale(2, 2) translate(-32, -32)");
+      assert_eq!(ftransform(&uniform, (0., 0.)), (68., 18.));
+  }
+  #[test]
   fn test_parse_polygon_points() {
       let st = "1 2,3 4, 5 6,7 8";
       let parsed = super::unpack_polygon_points(st).unwrap();
@@ -730,7 +1435,6 @@ use std::path::Path;
     use std::io::Read;
   #[test]
   fn test_parse_assets() {
-      use super::serde_xml_rs::from_str;
       let assets = vec!["assets/doric.svg",
                         "assets/thinrect.svg",
                         "assets/castle.svg",
@@ -769,50 +1473,119 @@ use std::path::Path;
                         "assets/halfthinrect.svg",
                         "assets/porthole.svg",
       ];
+      // (rings, vertices) at a quarter pixel: overlapping sub-shapes are merged, masks leave holes
+      // or split the stamp up, and bigger circles get more sides
       let sizes = [
-          21 as usize,
-          4,
-          18,
-          3,
-          42,
-          4,
-          4,
-          4,
-          4,
-          4,
-          4,
-          3,
-          4,
-          4,
-          4,
-          3,
-          4,
-          4,
-          4,
-          14,
-          4,
-          16,
-          16,
-          4,
-          4,
-          5,
-          8,
-          4,
-          3,
-          16,
-          9,
-          11,
-          3,
-          3,
-          3,
-          4,
-          4,
+          (4 as usize, 18 as usize),
+          (1, 4),
+          (1, 18),
+          (1, 3),
+          (3, 28),
+          (1, 4),
+          (3, 12),
+          (2, 8),
+          (1, 14),
+          (3, 12),
+          (1, 4),
+          (1, 3),
+          (1, 4),
+          (1, 11),
+          (1, 4),
+          (1, 3),
+          (2, 8),
+          (1, 4),
+          (1, 4),
+          (9, 36),
+          (1, 4),
+          (1, 18),
+          (1, 25),
+          (1, 18),
+          (1, 10),
+          (6, 30),
+          (1, 8),
+          (6, 31),
+          (1, 3),
+          (1, 13),
+          (1, 17),
+          (2, 10),
+          (1, 3),
+          (1, 3),
+          (1, 3),
+          (1, 4),
+          (2, 8),
       ];
       assert_eq!(sizes.len(), assets.len());
       for (asset, size) in assets.iter().zip(&sizes[..]) {
           //eprintln!("Testing Asset {}\n", asset);
-          let ramp:super::PolygonSVG = from_str(&read_to_string(Path::new(&asset)).unwrap()).unwrap();
-          assert_eq!(ramp.to_polygon().len(), *size);
+          let shape = super::to_shape(&read_to_string(Path::new(&asset)).unwrap(), 0.25).unwrap();
+          assert_eq!((shape.rings.len(), shape.rings.iter().map(|ring| ring.len()).sum::<usize>()), *size, "{}", asset);
+      }
+  }
+  #[test]
+  fn test_outline_skips_unknown_elements() {
+      use super::StampOutline;
+      // what another editor leaves in a stamp it saved
+      let stamp = r##"<svg version="2.0" width="64" height="64" xmlns="http://www.w3.org/2000/svg">
+  <title id="name">Door</title>
+  <desc>a plain <b>door</b></desc>
+  <metadata><rdf>someone</rdf></metadata>
+  <defs><mask id="hole"><title>hole</title><rect x="0" y="0" width="64" height="64" fill="white"/></mask></defs>
+  <g><title>panel</title><rect x="17" y="1" width="30" height="62" fill="white"/></g>
+</svg>"##;
+      let shape = super::to_shape(stamp, 0.25).unwrap();
+      assert_eq!(shape.rings.iter().map(|ring| ring.len()).collect::<Vec<usize>>(), vec![4]);
+      assert!((shape.area() - 30. * 62.).abs() < 1.0e-9);
+      let outline = StampOutline::from_str(stamp).unwrap();
+      assert_eq!(outline.sample((32., 32.)).alpha, 1.);
+      assert_eq!(outline.sample((5., 32.)).alpha, 0.);
+  }
+  #[test]
+  fn test_path_outline() {
+      // the rect stamp drawn as an editor would save it, and a frame whose inner subpath is a hole
+      let rect = super::to_shape(r#"<svg width="64" height="64"><g><path fill="white" d="M17 1H47V63h-30z"/></g></svg>"#, 0.1).unwrap();
+      assert_eq!(rect, super::to_shape(&read_to_string(Path::new("assets/rect.svg")).unwrap(), 0.1).unwrap());
+      let frame = super::StampOutline::from_str(r#"<svg width="64" height="64">
+<path fill="white" stroke="black" stroke-width="2" d="M0 0H64V64H0Z M16 16V48H48V16Z"/>
+<path fill="white" d="M32 24a8 8 0 1 1 0 16a8 8 0 1 1 0-16"/>
+</svg>"#).unwrap();
+      let shape = super::Shape::new(frame.to_rings(super::FLATTEN_TOLERANCE));
+      assert_eq!(shape.rings.len(), 3);
+      assert!(shape.contains((8., 8.)) && !shape.contains((20., 20.)) && shape.contains((32., 32.)));
+      // the flattened circle sits inside the true one, never further than the tolerance from it
+      let circle = std::f64::consts::PI * 64.;
+      let error = 64. * 64. - 32. * 32. + circle - shape.area();
+      assert!(error > 0. && error < super::FLATTEN_TOLERANCE * circle / 4.);
+      assert_eq!(frame.sample((8., 8.)).luminance, 1.);
+      assert_eq!(frame.sample((20., 20.)).alpha, 0.);
+      assert_eq!(frame.sample((16.5, 30.)).luminance, 0.); // the stroke runs around the hole too
+      assert_eq!(frame.sample((32., 32.)).luminance, 1.);
+  }
+  #[test]
+  fn test_nested_transforms() {
+      let outline = super::StampOutline::from_str(r#"<svg width="64" height="64">
+<mask id="right"><rect x="0" y="-8" width="8" height="16" fill="white"/></mask>
+<g transform="translate(32 0)">
+  <g transform="scale(2)" fill="white">
+    <rect x="0" y="0" width="4" height="4"/>
+    <rect x="0" y="0" width="4" height="4" transform="translate(0 10) rotate(45)"/>
+  </g>
+</g>
+<circle cx="0" cy="0" r="4" fill="white" transform="translate(10 50)" mask="url(#right)"/>
+</svg>"#).unwrap();
+      let shape = super::Shape::new(outline.to_rings(0.05));
+      // the mask is drawn in the circle's own coordinates, so it keeps the half right of its center
+      let inside = [(36., 4.), (32., 25.6), (35., 25.6), (12., 50.)];
+      let outside = [(31., 4.), (41., 4.), (38., 25.6), (32., 18.), (8., 50.), (2., 2.)];
+      for p in &inside {
+          assert!(shape.contains(*p), "{:?}", p);
+          assert_eq!(outline.sample(*p).luminance, 1., "{:?}", p);
+      }
+      for p in &outside {
+          assert!(!shape.contains(*p), "{:?}", p);
+          assert_eq!(outline.sample(*p).luminance, 0., "{:?}", p);
       }
+      // two 8x8 squares and half a circle, cut into chords that sag at most 0.05 inside it
+      let error = 128. + std::f64::consts::PI * 8. - shape.area();
+      assert!(error > 0. && error < 0.05 * std::f64::consts::PI * 4.);
   }
 }
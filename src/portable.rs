@@ -0,0 +1,172 @@
+// this file writes an SVG arrangement as a self-contained document: every stamp's artwork is copied in
+// once as a <symbol>, each mask draws its symbol with <use>, and ids are plain names rather than
+// asset paths, so the file renders the same anywhere without the asset directory next to it
+use std::path::Path;
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::io::Write;
+use super::error::Error;
+use super::svgxml::SVG;
+use super::polygonsvg::StampOutline;
+use super::xmlwriter::{XmlWriter, XmlStyle, to_xml_string};
+use super::foreign::unescape;
+use regex::{Captures, Regex};
+
+// letters, digits, - and _ survive; anything else becomes -, and a name already taken gets a number
+fn sanitize_id(prefix: &str, name: &str, taken: &mut BTreeSet<String>) -> String {
+    let clean: String = name.chars().map(
+        |c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' }).collect();
+    let base = format!("{}-{}", prefix, clean);
+    let mut ret = base.clone();
+    let mut suffix = 2;
+    while taken.contains(&ret) {
+        ret = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+    taken.insert(ret.clone());
+    ret
+}
+
+// the contents of an outline svg as a symbol, with its own ids renamed under the symbol's so that
+// two stamps' artwork can't collide
+fn write_symbol<W: io::Write>(xml: &mut XmlWriter<W>, id: &str, outline_xml: &str, taken: &mut BTreeSet<String>) -> Result<(), Error> {
+    let outline = StampOutline::from_str(outline_xml)?;
+    let body = Regex::new(r"(?s)<svg\b([^>]*)>(.*)</svg>").unwrap();
+    let captures = match body.captures(outline_xml) {
+        Some(captures) => captures,
+        None => return Err(Error::Invalid(format!("stamp artwork for {} has no <svg> element", id))),
+    };
+    let view_box = match Regex::new(r#"viewBox\s*=\s*"([^"]*)""#).unwrap().captures(&captures[1]) {
+        Some(view_box) => unescape(&view_box[1]).ok_or_else(|| Error::Invalid(format!("stamp artwork for {} has a bad viewBox", id)))?,
+        None => format!("0 0 {} {}", outline.width, outline.height),
+    };
+    let mut inner_ids = BTreeSet::new();
+    let mut renamed = HashMap::new();
+    for inner in Regex::new(r#"\bid\s*=\s*"([^"]*)""#).unwrap().captures_iter(&captures[2]) {
+        inner_ids.insert(inner[1].to_string());
+    }
+    for inner in inner_ids {
+        let new_id = sanitize_id(id, &inner, taken);
+        renamed.insert(inner, new_id);
+    }
+    let references = Regex::new(r##"(\bid\s*=\s*"|url\(#|href\s*=\s*"#)([^"\)]*)"##).unwrap();
+    let contents = references.replace_all(&captures[2], |reference: &Captures| {
+        match renamed.get(&reference[2]) {
+            Some(new_id) => format!("{}{}", &reference[1], new_id),
+            None => reference[0].to_string(),
+        }
+    });
+    xml.start("symbol", &[("id", id), ("viewBox", &view_box), ("preserveAspectRatio", "none")])?;
+    // already markup, with only sanitized ids swapped in
+    xml.raw(&contents)?;
+    xml.end()
+}
+
+pub fn portable_svg(svg: &SVG) -> Result<String, Error> {
+    let (width, height) = svg.extent();
+    let (width, height) = (width.to_string(), height.to_string());
+    let view_box = format!("0 0 {} {}", width, height);
+    to_xml_string(XmlStyle::Lines, |xml| {
+        xml.element("<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        xml.start("svg", &[("version", "1.1"), ("width", &width), ("height", &height), ("viewBox", &view_box),
+                           ("xmlns", "http://www.w3.org/2000/svg"), ("xmlns:xlink", "http://www.w3.org/1999/xlink")])?;
+        xml.start("defs", &[])?;
+        let mut taken = BTreeSet::new();
+        // stretched over whichever rect it masks, the way the raster stretches stamp bitmaps
+        let mut masks = HashMap::<&str, String>::new();
+        for stamp in &svg.stamps {
+            let url = stamp.rect.href.url.as_str();
+            if masks.contains_key(url) {
+                continue;
+            }
+            let stem = Path::new(url).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let symbol = sanitize_id("stamp", &stem, &mut taken);
+            let mask = sanitize_id("mask", &stem, &mut taken);
+            let outline = svg.outline_path(url);
+            let outline_xml = std::fs::read_to_string(&outline).map_err(|err| Error::asset(Path::new(&outline), err))?;
+            write_symbol(xml, &symbol, &outline_xml, &mut taken)?;
+            xml.start("mask", &[("id", &mask), ("maskContentUnits", "objectBoundingBox")])?;
+            xml.empty("use", &[("xlink:href", &("#".to_string() + &symbol)), ("width", "1"), ("height", "1")])?;
+            xml.end()?;
+            masks.insert(url, mask);
+        }
+        let mut clips = HashMap::<String, String>::new();
+        for clip_path in &svg.defs.clipPath {
+            let mut renamed = clip_path.clone();
+            renamed.id = sanitize_id("clip", &clip_path.id, &mut taken);
+            renamed.write_xml(xml)?;
+            clips.insert("url(#".to_string() + &clip_path.id + ")", renamed.id);
+        }
+        xml.end()?;
+        for stamp in &svg.stamps {
+            let rect = &stamp.rect;
+            let (x, y, rect_width, rect_height) = (rect.x.to_string(), rect.y.to_string(), rect.width.to_string(), rect.height.to_string());
+            let fill = rect.fill.to_string();
+            let mask = "url(#".to_string() + &masks[rect.href.url.as_str()] + ")";
+            let mut attrs = vec![("x", x.as_str()), ("y", &y), ("width", &rect_width), ("height", &rect_height), ("fill", &fill), ("mask", &mask)];
+            let clip;
+            if !rect.href.clip.is_empty() {
+                clip = match clips.get(&rect.href.clip) {
+                    Some(id) => "url(#".to_string() + id + ")",
+                    None => return Err(Error::UnknownClipPath(rect.href.clip.clone())),
+                };
+                attrs.push(("clip-path", &clip));
+            }
+            xml.start("g", &[("transform", &stamp.transform.to_string()?)])?;
+            xml.empty("rect", &attrs)?;
+            xml.end()?;
+        }
+        xml.end()
+    })
+}
+
+pub fn save_portable(svg: &SVG, filename: &Path) -> Result<(), Error> {
+    let data = portable_svg(svg)?;
+    let mut file = std::fs::File::create(filename).map_err(Error::io(filename))?;
+    file.write_all(data.as_bytes()).map_err(Error::io(filename))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::portable_svg;
+    use super::super::svgxml::{SVG, Transform, Color, ClipPath};
+
+    #[test]
+    fn test_portable_svg() {
+        let mut svg = SVG::new(300, 200);
+        svg.add(Transform::new(64, 64), "assets/stamps/larch.bmp".to_string(), String::new(), Color::default());
+        let mut moved = Transform::new(64, 64);
+        moved.tx = 100.;
+        svg.defs.clipPath.push(ClipPath::from_masks("0".to_string(), &moved, &[Transform::new(16, 16)]));
+        svg.add(moved, "assets/stamps/larch.bmp".to_string(), "url(#0)".to_string(), Color{r: 0xff, g: 0, b: 0});
+        // a pack's stamp with the same file name as a shipped one
+        svg.outlines.insert("pack/larch.bmp".to_string(), "assets/rarch.svg".to_string());
+        svg.add(Transform::new(64, 64), "pack/larch.bmp".to_string(), String::new(), Color::default());
+
+        let text = portable_svg(&svg).unwrap();
+        assert!(text.contains("viewBox=\"0 0 300 200\""));
+        assert!(!text.contains("assets/") && !text.contains(".bmp") && !text.contains("<svg version=\"2.0\""));
+        assert_eq!(text.matches("<symbol").count(), 2);
+        assert!(text.contains("<symbol id=\"stamp-larch\" viewBox=\"0 0 64 64\""));
+        assert!(text.contains("<use xlink:href=\"#stamp-larch-2\""));
+        assert_eq!(text.matches("mask=\"url(#mask-larch)\"").count(), 2);
+        assert_eq!(text.matches("mask=\"url(#mask-larch-2)\"").count(), 1);
+        assert!(text.contains("<clipPath id=\"clip-0\">") && text.contains("clip-path=\"url(#clip-0)\""));
+        // the artwork's own mask is renamed along with the reference to it
+        assert!(text.contains("<mask id=\"stamp-larch-larch\">") && text.contains("mask=\"url(#stamp-larch-larch)\""));
+        assert!(text.contains("<mask id=\"stamp-larch-2-rarch\">"));
+        assert!(!text.contains("id=\"larch\"") && !text.contains("url(#larch)"));
+
+        // the mask is drawn in the rect's own units, so artwork fills a stamp that isn't 64x64 too
+        svg.stamps[2].rect.width = 128;
+        svg.stamps[2].rect.height = 32;
+        let text = portable_svg(&svg).unwrap();
+        assert!(text.contains("<rect x=\"0\" y=\"0\" width=\"128\" height=\"32\" fill=\"#000000\" mask=\"url(#mask-larch-2)\"/>"));
+        assert!(text.contains("<mask id=\"mask-larch-2\" maskContentUnits=\"objectBoundingBox\">\n<use xlink:href=\"#stamp-larch-2\" width=\"1\" height=\"1\"/>"));
+        assert!(text.contains("<symbol id=\"stamp-larch-2\" viewBox=\"0 0 64 64\" preserveAspectRatio=\"none\">"));
+
+        svg.stamps[1].rect.href.clip = "url(#1)".to_string();
+        assert!(portable_svg(&svg).is_err());
+    }
+}
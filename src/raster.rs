@@ -0,0 +1,393 @@
+// this file composes an SVG arrangement into pixels without SDL, so drawings can be thumbnailed in batch
+use std::path::Path;
+use std::collections::HashMap;
+use std::vec::Vec;
+use std::io::{Read, Write};
+use super::error::Error;
+use super::svgxml::{SVG, Color, F64Point, itransform, ftransform};
+use super::polygonsvg::{Paint, StampOutline};
+
+const STAMP_SUPERSAMPLING: u32 = 4; // samples per pixel along each axis when painting a stamp
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>, // RGBA, row major, top row first
+}
+
+impl Raster {
+    pub fn new(width: u32, height: u32, background: Color) -> Raster {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for _ in 0..width as usize * height as usize {
+            pixels.extend_from_slice(&[background.r, background.g, background.b, 0xff]);
+        }
+        Raster{width, height, pixels}
+    }
+    pub fn get(&self, x: u32, y: u32) -> [u8;4] {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        [self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2], self.pixels[offset + 3]]
+    }
+    // alpha blends color over the pixel at x, y
+    fn blend(&mut self, x: u32, y: u32, color: [u8;3], alpha: u8) {
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        let a = alpha as u32;
+        for (channel, src) in color.iter().enumerate() {
+            let dst = self.pixels[offset + channel] as u32;
+            self.pixels[offset + channel] = ((*src as u32 * a + dst * (255 - a) + 127) / 255) as u8;
+        }
+        let dst_alpha = self.pixels[offset + 3] as u32;
+        self.pixels[offset + 3] = (a + dst_alpha * (255 - a) / 255) as u8;
+    }
+    pub fn load_bmp(filename: &Path) -> Result<Raster, Error> {
+        let mut f = std::fs::File::open(filename).map_err(|err| Error::asset(filename, err))?;
+        let mut data = Vec::new();
+        f.read_to_end(&mut data).map_err(Error::io(filename))?;
+        decode_bmp(&data).map_err(|e| Error::Invalid(format!("{}: {}", filename.display(), e)))
+    }
+    pub fn write_png<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8 bit RGBA, no interlace
+        let stride = self.width as usize * 4;
+        let mut scanlines = Vec::with_capacity((stride + 1) * self.height as usize);
+        for row in self.pixels.chunks(stride) {
+            scanlines.push(0); // no filter
+            scanlines.extend_from_slice(row);
+        }
+        w.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+        write_png_chunk(w, b"IHDR", &ihdr)?;
+        write_png_chunk(w, b"IDAT", &zlib_stored(&scanlines))?;
+        write_png_chunk(w, b"IEND", &[])
+    }
+    pub fn save_png(&self, filename: &Path) -> Result<(), std::io::Error> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(filename)?);
+        self.write_png(&mut file)?;
+        file.flush()
+    }
+    // 32 bit with an alpha mask, the flavor SDL loads stamps with transparency from
+    pub fn write_bmp<W: Write>(&self, w: &mut W) -> Result<(), std::io::Error> {
+        let image_size = self.width * self.height * 4;
+        let pixel_offset = 14 + 108u32; // file header and BITMAPV4HEADER
+        let mut data = Vec::with_capacity((pixel_offset + image_size) as usize);
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&(pixel_offset + image_size).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&pixel_offset.to_le_bytes());
+        data.extend_from_slice(&108u32.to_le_bytes());
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes()); // positive: bottom row first
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes()); // BI_BITFIELDS
+        data.extend_from_slice(&image_size.to_le_bytes());
+        data.extend_from_slice(&2835u32.to_le_bytes()); // 72 dpi
+        data.extend_from_slice(&2835u32.to_le_bytes());
+        data.extend_from_slice(&[0; 8]); // no palette
+        for mask in &[0xff0000u32, 0xff00, 0xff, 0xff000000] {
+            data.extend_from_slice(&mask.to_le_bytes());
+        }
+        data.extend_from_slice(b"BGRs"); // sRGB, so the endpoints and gamma that follow are unused
+        data.extend_from_slice(&[0; 48]);
+        for row in self.pixels.chunks(self.width as usize * 4).rev() {
+            for pixel in row.chunks(4) {
+                data.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+            }
+        }
+        w.write_all(&data)
+    }
+    pub fn save_bmp(&self, filename: &Path) -> Result<(), std::io::Error> {
+        let mut file = std::fs::File::create(filename)?;
+        self.write_bmp(&mut file)?;
+        file.flush()
+    }
+    // the stamp's coverage at a point of a width x height rect this bitmap is stretched over
+    fn coverage(&self, local: F64Point, width: u32, height: u32) -> u8 {
+        let x = (local.0 * self.width as f64 / width as f64) as u32;
+        let y = (local.1 * self.height as f64 / height as f64) as u32;
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        let pixel = self.get(x, y);
+        let luma = (pixel[0] as u32 * 54 + pixel[1] as u32 * 183 + pixel[2] as u32 * 19) >> 8;
+        (luma * pixel[3] as u32 / 255) as u8
+    }
+}
+
+// paints a stamp svg into a width x height bitmap: white where the stamp covers, transparent elsewhere
+pub fn render_stamp(outline: &str, width: u32, height: u32) -> Result<Raster, Error> {
+    let outline = StampOutline::from_str(outline)?;
+    let scale = (if outline.width > 0. { outline.width / width as f64 } else { 1. },
+                 if outline.height > 0. { outline.height / height as f64 } else { 1. });
+    let samples = STAMP_SUPERSAMPLING * STAMP_SUPERSAMPLING;
+    let mut ret = Raster{width, height, pixels: vec![0; width as usize * height as usize * 4]};
+    for y in 0..height {
+        for x in 0..width {
+            let mut total = Paint::default();
+            for sample in 0..samples {
+                let offset = ((sample % STAMP_SUPERSAMPLING) as f64 + 0.5, (sample / STAMP_SUPERSAMPLING) as f64 + 0.5);
+                let p = ((x as f64 + offset.0 / STAMP_SUPERSAMPLING as f64) * scale.0,
+                         (y as f64 + offset.1 / STAMP_SUPERSAMPLING as f64) * scale.1);
+                let paint = outline.sample(p);
+                total.luminance += paint.luminance;
+                total.alpha += paint.alpha;
+            }
+            let gray = if total.alpha > 0. { (total.luminance / total.alpha * 255.).round() as u8 } else { 0 };
+            let alpha = (total.alpha / samples as f64 * 255.).round() as u8;
+            let offset = (y as usize * width as usize + x as usize) * 4;
+            ret.pixels[offset..offset + 4].copy_from_slice(&[gray, gray, gray, alpha]);
+        }
+    }
+    Ok(ret)
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    if offset + 2 > data.len() {
+        return Err("truncated bmp".to_string());
+    }
+    Ok(u16::from_le_bytes([data[offset], data[offset + 1]]))
+}
+fn le_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    if offset + 4 > data.len() {
+        return Err("truncated bmp".to_string());
+    }
+    Ok(u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]))
+}
+
+fn mask_channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0xff;
+    }
+    let shifted = (pixel & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+    (shifted * 255 / max) as u8
+}
+
+// only the uncompressed 24 and 32 bit flavors that SDL saves for the stamps are supported
+fn decode_bmp(data: &[u8]) -> Result<Raster, String> {
+    if data.len() < 54 || &data[..2] != b"BM" {
+        return Err("not a bmp".to_string());
+    }
+    let pixel_offset = le_u32(data, 10)? as usize;
+    let header_size = le_u32(data, 14)?;
+    let width = le_u32(data, 18)? as i32;
+    let height = le_u32(data, 22)? as i32;
+    let bpp = le_u16(data, 28)?;
+    let compression = le_u32(data, 30)?;
+    if width <= 0 || height == 0 {
+        return Err(format!("bad bmp dimensions {}x{}", width, height));
+    }
+    let (mut rmask, mut gmask, mut bmask, mut amask) = (0xff0000u32, 0xff00u32, 0xffu32, 0u32);
+    match (bpp, compression) {
+        (24, 0) | (32, 0) => {},
+        (32, 3) => {
+            rmask = le_u32(data, 54)?;
+            gmask = le_u32(data, 58)?;
+            bmask = le_u32(data, 62)?;
+            if header_size >= 56 {
+                amask = le_u32(data, 66)?;
+            }
+        },
+        _ => return Err(format!("unsupported bmp: {} bpp compression {}", bpp, compression)),
+    }
+    let bytes_per_pixel = bpp as usize / 8;
+    let stride = (width as usize * bytes_per_pixel + 3) & !3;
+    let rows = height.unsigned_abs() as usize;
+    if pixel_offset + stride * rows > data.len() {
+        return Err("truncated bmp pixel data".to_string());
+    }
+    let mut ret = Raster::new(width as u32, rows as u32, Color::default());
+    for row in 0..rows {
+        let src_row = if height > 0 { rows - 1 - row } else { row }; // positive height is stored bottom up
+        let src = &data[pixel_offset + src_row * stride..];
+        for x in 0..width as usize {
+            let dst = (row * width as usize + x) * 4;
+            if bytes_per_pixel == 3 {
+                ret.pixels[dst] = src[x * 3 + 2];
+                ret.pixels[dst + 1] = src[x * 3 + 1];
+                ret.pixels[dst + 2] = src[x * 3];
+                ret.pixels[dst + 3] = 0xff;
+            } else {
+                let pixel = le_u32(src, x * 4)?;
+                ret.pixels[dst] = mask_channel(pixel, rmask);
+                ret.pixels[dst + 1] = mask_channel(pixel, gmask);
+                ret.pixels[dst + 2] = mask_channel(pixel, bmask);
+                ret.pixels[dst + 3] = mask_channel(pixel, amask);
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn crc32(data: &[&[u8]]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for chunk in data {
+        for byte in chunk.iter() {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            }
+        }
+    }
+    !crc
+}
+
+fn write_png_chunk<W: Write>(w: &mut W, kind: &[u8;4], data: &[u8]) -> Result<(), std::io::Error> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+// a zlib stream of uncompressed deflate blocks: larger files, but no compression dependency
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 11);
+    ret.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        ret.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        ret.push(if blocks.peek().is_none() { 1 } else { 0 });
+        ret.extend_from_slice(&len.to_le_bytes());
+        ret.extend_from_slice(&(!len).to_le_bytes());
+        ret.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    ret.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    ret
+}
+
+// the stamp's bitmap, or when there is none yet, its outline painted at the size it is placed at
+fn load_stamp_mask(svg: &SVG, url: &str, width: u32, height: u32) -> Result<Raster, Error> {
+    match Raster::load_bmp(&svg.asset_path(url)) {
+        Ok(bitmap) => Ok(bitmap),
+        Err(_) => {
+            let outline = svg.outline_path(url);
+            let data = std::fs::read_to_string(&outline).map_err(|err| Error::asset(Path::new(&outline), err))?;
+            render_stamp(&data, width, height)
+        },
+    }
+}
+
+// composes every stamp over a white page the size of SVG::extent
+pub fn rasterize(svg: &SVG) -> Result<Raster, Error> {
+    let (width, height) = svg.extent();
+    let mut ret = Raster::new(width, height, Color{r:0xff, g:0xff, b:0xff});
+    let mut masks = HashMap::<String, Raster>::new();
+    for stamp in &svg.stamps {
+        if !masks.contains_key(&stamp.rect.href.url) {
+            let mask = load_stamp_mask(svg, &stamp.rect.href.url, stamp.rect.width, stamp.rect.height)?;
+            masks.insert(stamp.rect.href.url.clone(), mask);
+        }
+        let mask = &masks[&stamp.rect.href.url];
+        let clip = if !stamp.rect.href.clip.is_empty() {
+            match svg.clip_path(&stamp.rect.href.clip) {
+                Some(clip_path) => Some(clip_path.to_shape()),
+                None => return Err(Error::UnknownClipPath(stamp.rect.href.clip.clone())),
+            }
+        } else {
+            None
+        };
+        let rect = &stamp.rect;
+        let (x0, y0) = (rect.x as f64, rect.y as f64);
+        let (x1, y1) = (x0 + rect.width as f64, y0 + rect.height as f64);
+        let corners = [
+            ftransform(&stamp.transform, (x0, y0)),
+            ftransform(&stamp.transform, (x1, y0)),
+            ftransform(&stamp.transform, (x1, y1)),
+            ftransform(&stamp.transform, (x0, y1)),
+        ];
+        let min_x = corners.iter().map(|c| c.0).fold(f64::MAX, f64::min).floor().max(0.0) as u32;
+        let min_y = corners.iter().map(|c| c.1).fold(f64::MAX, f64::min).floor().max(0.0) as u32;
+        let max_x = corners.iter().map(|c| c.0).fold(f64::MIN, f64::max).ceil().min(width as f64).max(0.0) as u32;
+        let max_y = corners.iter().map(|c| c.1).fold(f64::MIN, f64::max).ceil().min(height as f64).max(0.0) as u32;
+        let color = [rect.fill.r, rect.fill.g, rect.fill.b];
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let local = match itransform(&stamp.transform, (x as f64 + 0.5, y as f64 + 0.5)) {
+                    Some(local) => local,
+                    None => continue,
+                };
+                if local.0 < x0 || local.0 >= x1 || local.1 < y0 || local.1 >= y1 {
+                    continue;
+                }
+                if let Some(ref clip_shape) = clip {
+                    if !clip_shape.contains(local) {
+                        continue;
+                    }
+                }
+                let coverage = mask.coverage((local.0 - x0, local.1 - y0), rect.width, rect.height);
+                if coverage != 0 {
+                    ret.blend(x, y, color, coverage);
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+pub fn render_png(svg: &SVG, filename: &Path) -> Result<(), Error> {
+    rasterize(svg)?.save_png(filename).map_err(Error::io(filename))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Raster, rasterize, render_stamp};
+    use super::super::svgxml::{SVG, Color, Transform};
+    #[test]
+    fn test_load_bmp() {
+        let arch = Raster::load_bmp(std::path::Path::new("assets/stamps/arch.bmp")).unwrap();
+        assert_eq!((arch.width, arch.height), (64, 64));
+        assert_eq!(arch.get(2, 2), [0xff, 0xff, 0xff, 0xff]); // top corner of the arch is solid
+        assert_eq!(arch.get(32, 60)[3], 0); // the opening is transparent
+    }
+    #[test]
+    fn test_render_stamp() {
+        // the shipped bitmaps were drawn from the same svgs, so they should agree to within the odd edge pixel
+        for name in &["arch", "car", "gothic", "windows", "circle"] {
+            let shipped = Raster::load_bmp(std::path::Path::new(&format!("assets/stamps/{}.bmp", name))).unwrap();
+            let outline = std::fs::read_to_string(format!("assets/{}.svg", name)).unwrap();
+            let rendered = render_stamp(&outline, 64, 64).unwrap();
+            let mut disagree = 0;
+            for y in 0..64 {
+                for x in 0..64 {
+                    if (shipped.get(x, y)[3] >= 0x80) != (rendered.get(x, y)[3] >= 0x80) {
+                        disagree += 1;
+                    }
+                }
+            }
+            assert!(disagree < 64, "{}: {} pixels differ", name, disagree);
+        }
+        let arch = render_stamp(&std::fs::read_to_string("assets/arch.svg").unwrap(), 32, 32).unwrap();
+        assert_eq!(arch.get(1, 1), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(arch.get(16, 30)[3], 0); // the mask cuts the opening out
+        let mut bmp = Vec::new();
+        arch.write_bmp(&mut bmp).unwrap();
+        assert_eq!(super::decode_bmp(&bmp).unwrap(), arch);
+    }
+    #[test]
+    fn test_rasterize() {
+        let mut svg = SVG::new(100, 80);
+        let mut transform = Transform::new(64, 64);
+        transform.tx = 10.;
+        transform.ty = 5.;
+        svg.add(transform, "assets/stamps/arch.bmp".to_string(), String::new(), Color{r:0xff, g:0, b:0});
+        let raster = rasterize(&svg).unwrap();
+        assert_eq!((raster.width, raster.height), svg.extent());
+        assert_eq!(raster.get(0, 0), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(raster.get(12, 7), [0xff, 0, 0, 0xff]);
+        assert_eq!(raster.get(42, 65), [0xff, 0xff, 0xff, 0xff]); // through the arch
+        let mut png = Vec::new();
+        raster.write_png(&mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+}
@@ -0,0 +1,237 @@
+// how the editor writes a drawing out: a file is replaced only once its new contents are safely on
+// disk, older versions are kept aside as numbered backups, and autosaving waits for stamping to pause
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::vec::Vec;
+use super::error::Error;
+
+pub const AUTOSAVE_DELAY: Duration = Duration::from_secs(2); // save once stamping pauses this long
+pub const AUTOSAVE_MAX_DELAY: Duration = Duration::from_secs(30); // even if it never pauses
+pub const BACKUP_INTERVAL: Duration = Duration::from_secs(300);
+pub const BACKUP_COUNT: usize = 10;
+
+// writes beside filename and renames over it, so a crash mid-write never leaves a truncated file behind
+pub fn write_atomically(filename: &Path, s: &str) -> Result<(), Error> {
+    replace_with(filename, |file| {
+        file.write_all(s.as_bytes())?;
+        file.sync_all()
+    })
+}
+
+fn replace_with<F: FnOnce(&mut fs::File) -> io::Result<()>>(filename: &Path, write: F) -> Result<(), Error> {
+    let mut temp_name = filename.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = PathBuf::from(temp_name);
+    let mut file = fs::File::create(&temp_path).map_err(Error::io(&temp_path))?;
+    let written = write(&mut file);
+    drop(file);
+    if let Err(error) = written {
+        // a half written file, say on a full disk, isn't worth keeping next to the drawing
+        let _ = fs::remove_file(&temp_path);
+        return Err(Error::Io{path: temp_path, error});
+    }
+    fs::rename(&temp_path, filename).map_err(Error::io(filename))
+}
+
+// the backups of castle.svg are castle.svg.<seconds since 1970>.bak, oldest first
+pub fn list_backups(filename: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let name = match filename.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string() + ".",
+        None => return Ok(Vec::new()),
+    };
+    let dir = match filename.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut ret = Vec::new();
+    for entry in fs::read_dir(dir).map_err(Error::io(dir))? {
+        let path = entry.map_err(Error::io(dir))?.path();
+        let stamp = path.file_name().and_then(|entry_name| entry_name.to_str())
+            .and_then(|entry_name| entry_name.strip_prefix(&name))
+            .and_then(|rest| rest.strip_suffix(".bak"))
+            .and_then(|seconds| seconds.parse::<u64>().ok());
+        if let Some(stamp) = stamp {
+            ret.push((stamp, path));
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+// copies the current file aside and drops all but the newest count copies
+pub fn backup(filename: &Path, count: usize) -> Result<(), Error> {
+    if !filename.exists() {
+        return Ok(());
+    }
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    let mut backup_name = filename.as_os_str().to_owned();
+    backup_name.push(format!(".{}.bak", seconds));
+    let backup_path = PathBuf::from(backup_name);
+    fs::copy(filename, &backup_path).map_err(Error::io(&backup_path))?;
+    let backups = list_backups(filename)?;
+    if backups.len() > count {
+        for (_, stale) in &backups[..backups.len() - count] {
+            fs::remove_file(stale).map_err(Error::io(stale))?;
+        }
+    }
+    Ok(())
+}
+
+// when the pending changes to a drawing are due to be written, given when they were made and when
+// saving was last tried. every method takes the current time so the timing can be tested
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autosave {
+    unsaved_since: Option<Instant>, // the first change that has not been written out yet
+    last_change: Instant,
+    last_attempt: Option<Instant>,
+    last_backup: Option<Instant>,
+}
+
+impl Autosave {
+    pub fn new(now: Instant) -> Autosave {
+        Autosave{unsaved_since: None, last_change: now, last_attempt: None, last_backup: None}
+    }
+    pub fn changed(&mut self, now: Instant) {
+        self.last_change = now;
+        if self.unsaved_since.is_none() {
+            self.unsaved_since = Some(now);
+        }
+    }
+    pub fn is_unsaved(&self) -> bool {
+        self.unsaved_since.is_some()
+    }
+    // how long until the pending changes should be written, if there are any. after a failure,
+    // waits a quiet period before trying again rather than retrying every frame
+    pub fn due_in(&self, now: Instant) -> Option<Duration> {
+        let first_change = self.unsaved_since?;
+        let quiet = (self.last_change + AUTOSAVE_DELAY).saturating_duration_since(now);
+        let overdue = (first_change + AUTOSAVE_MAX_DELAY).saturating_duration_since(now);
+        let retry = self.last_attempt.map_or(Duration::from_secs(0),
+                                             |last| (last + AUTOSAVE_DELAY).saturating_duration_since(now));
+        Some(std::cmp::max(std::cmp::min(quiet, overdue), retry))
+    }
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.due_in(now) == Some(Duration::from_secs(0))
+    }
+    pub fn attempted(&mut self, now: Instant) {
+        self.last_attempt = Some(now);
+    }
+    // the first save of a session keeps the file as it was opened
+    pub fn backup_due(&self, now: Instant) -> bool {
+        match self.last_backup {
+            Some(last) => now.saturating_duration_since(last) >= BACKUP_INTERVAL,
+            None => true,
+        }
+    }
+    pub fn backed_up(&mut self, now: Instant) {
+        self.last_backup = Some(now);
+    }
+    pub fn saved(&mut self) {
+        self.unsaved_since = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+    use super::{Autosave, write_atomically, replace_with, backup, list_backups, AUTOSAVE_DELAY, AUTOSAVE_MAX_DELAY, BACKUP_INTERVAL};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("art-stamps-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = scratch_dir("write");
+        let drawing = dir.join("castle.svg");
+        write_atomically(&drawing, "<svg/>").unwrap();
+        write_atomically(&drawing, "<svg><g/></svg>").unwrap();
+        assert_eq!(fs::read_to_string(&drawing).unwrap(), "<svg><g/></svg>");
+        // the contents went through the temporary file, which the rename took away
+        assert!(!dir.join("castle.svg.tmp").exists());
+
+        // with no way to write the temporary file the drawing is left as it was
+        fs::create_dir(dir.join("castle.svg.tmp")).unwrap();
+        assert!(write_atomically(&drawing, "<svg>lost</svg>").is_err());
+        assert_eq!(fs::read_to_string(&drawing).unwrap(), "<svg><g/></svg>");
+        fs::remove_dir(dir.join("castle.svg.tmp")).unwrap();
+
+        // nor when writing it fails part way, which takes the partial copy away too
+        let failed = replace_with(&drawing, |file| {
+            file.write_all(b"<svg>")?;
+            Err(io::Error::new(io::ErrorKind::Other, "no space left"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&drawing).unwrap(), "<svg><g/></svg>");
+        assert!(!dir.join("castle.svg.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_rotation() {
+        let dir = scratch_dir("backup");
+        let drawing = dir.join("castle.svg");
+        backup(&drawing, 3).unwrap();
+        assert!(list_backups(&drawing).unwrap().is_empty());
+
+        fs::write(&drawing, "<svg/>").unwrap();
+        for seconds in 1..6 {
+            fs::write(dir.join(format!("castle.svg.{}.bak", seconds)), "old").unwrap();
+        }
+        fs::write(dir.join("castle.svg.notes.bak"), "not a backup").unwrap();
+        fs::write(dir.join("tower.svg.1.bak"), "another drawing").unwrap();
+        backup(&drawing, 3).unwrap();
+        let kept = list_backups(&drawing).unwrap();
+        assert_eq!(kept.iter().map(|backup| backup.0).take(2).collect::<Vec<u64>>(), vec![4, 5]);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(fs::read_to_string(&kept[2].1).unwrap(), "<svg/>");
+        assert!(dir.join("castle.svg.notes.bak").exists() && dir.join("tower.svg.1.bak").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_autosave_timing() {
+        let start = Instant::now();
+        let mut autosave = Autosave::new(start);
+        assert_eq!(autosave.due_in(start), None);
+        assert!(autosave.backup_due(start));
+
+        // each change pushes the save back until stamping pauses
+        autosave.changed(start);
+        assert_eq!(autosave.due_in(start), Some(AUTOSAVE_DELAY));
+        let later = start + Duration::from_secs(1);
+        autosave.changed(later);
+        assert_eq!(autosave.due_in(later), Some(AUTOSAVE_DELAY));
+        assert!(autosave.is_due(later + AUTOSAVE_DELAY));
+
+        // but never past the longest delay, however busy it stays
+        let mut busy = start;
+        while busy < start + AUTOSAVE_MAX_DELAY {
+            autosave.changed(busy);
+            busy += Duration::from_secs(1);
+        }
+        assert!(autosave.is_due(busy));
+
+        // a failed save waits a quiet period before trying again
+        autosave.attempted(busy);
+        assert_eq!(autosave.due_in(busy), Some(AUTOSAVE_DELAY));
+        assert!(autosave.is_due(busy + AUTOSAVE_DELAY));
+
+        autosave.backed_up(busy);
+        assert!(!autosave.backup_due(busy + AUTOSAVE_DELAY));
+        assert!(autosave.backup_due(busy + BACKUP_INTERVAL));
+        autosave.saved();
+        assert!(!autosave.is_unsaved());
+        assert_eq!(autosave.due_in(busy), None);
+    }
+}
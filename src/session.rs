@@ -0,0 +1,218 @@
+// editor state that is not part of the drawing itself, kept in a sidecar next to the svg
+// so that reopening a drawing resumes where the user left off
+use std::path::Path;
+use std::vec::Vec;
+use std::convert::TryFrom;
+use super::error::Error;
+use super::serde_xml_rs::from_str;
+use super::svgxml::{Color, Transform, transform_deserializer};
+use super::history::{History, edit};
+
+const SESSION_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub color: Color,
+    pub locked: bool,
+    pub camera: Transform,
+    pub masks: Vec<Transform>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize)]
+struct camera {
+    tx: f64,
+    ty: f64,
+    zoom: f64,
+    #[serde(default)]
+    rotate: f64,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize)]
+struct mask {
+    #[serde(deserialize_with="transform_deserializer")]
+    transform: Transform,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize, Default)]
+struct edits {
+    #[serde(default)]
+    edit: Vec<edit>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize)]
+struct session {
+    version: u32,
+    color: String,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    document: String,
+    camera: camera,
+    #[serde(default)]
+    mask: Vec<mask>,
+    #[serde(default)]
+    undo: edits,
+    #[serde(default)]
+    redo: edits,
+}
+
+// the sidecar for castle.svg is castle.svg.session
+pub fn session_path(svg_path: &Path) -> std::path::PathBuf {
+    let mut name = svg_path.as_os_str().to_owned();
+    name.push(".session");
+    std::path::PathBuf::from(name)
+}
+
+// a cheap fingerprint (64 bit FNV-1a) of the serialized drawing, so stale undo steps are not replayed
+// onto a document that changed behind the editor's back
+pub fn document_digest(svg: &str) -> String {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in svg.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+impl Session {
+    // document is the svg text this session and its history were saved alongside
+    pub fn to_string(&self, history: &History, document: &str) -> Result<String, Error> {
+        let mut ret = format!(
+            "<session version=\"{}\" color=\"{}\" locked=\"{}\" document=\"{}\">\n<camera tx=\"{}\" ty=\"{}\" zoom=\"{}\" rotate=\"{}\"/>\n",
+            SESSION_VERSION, self.color.to_string(), self.locked, document_digest(document),
+            self.camera.tx, self.camera.ty, self.camera.scalex, self.camera.rotate);
+        for mask in &self.masks {
+            ret += &format!("<mask transform=\"{}\"/>\n", mask.to_string()?);
+        }
+        ret += &history.to_string()?;
+        Ok(ret + "</session>\n")
+    }
+    // the undo history is only kept if document matches the text the session was saved with
+    pub fn from_str(s: &str, document: &str, history_limit: usize) -> Result<(Session, History), Error> {
+        let parsed: session = from_str(s)?;
+        if parsed.version > SESSION_VERSION {
+            return Err(Error::Invalid(format!("session version {} is newer than {}", parsed.version, SESSION_VERSION)));
+        }
+        let mut camera = Transform::new(0, 0);
+        camera.tx = parsed.camera.tx;
+        camera.ty = parsed.camera.ty;
+        camera.scalex = parsed.camera.zoom;
+        camera.scaley = parsed.camera.zoom;
+        camera.rotate = parsed.camera.rotate;
+        let history = if parsed.document == document_digest(document) {
+            History::from_edits(history_limit, parsed.undo.edit, parsed.redo.edit)?
+        } else {
+            History::new(history_limit)
+        };
+        Ok((Session{
+            color: Color::try_from(parsed.color.as_str()).map_err(Error::Color)?,
+            locked: parsed.locked,
+            camera,
+            masks: parsed.mask.into_iter().map(|m| m.transform).collect(),
+        }, history))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::{Session, session_path};
+    use super::super::svgxml::{SVG, g, Color, ClipPath, Polygon, Transform};
+    use super::super::history::{History, Edit, Layout};
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut svg = SVG::new(800, 600);
+        let mut history = History::new(16);
+        let mut transform = Transform::new(64, 64);
+        transform.tx = 10.25;
+        transform.ty = 3.;
+        transform.rotate = 45.;
+        transform.scalex = 2.;
+        transform.scaley = 2.;
+        let stamp = g::new(transform.clone(), "assets/stamps/rect.bmp".to_string(), "url(#0)".to_string(), Color{r:1, g:2, b:3});
+        history.apply(&mut svg, Edit::Batch(vec![
+            Edit::AddClipPath(ClipPath{id:"0".to_string(), polygon:Polygon{points:vec![(0., 0.), (4., 0.5), (4., 4.)]}, path:None}),
+            Edit::Insert{index: 0, stamp},
+        ]));
+        history.seal();
+        let mut after = transform.clone();
+        after.flipx = true;
+        after.tx = 11.;
+        history.apply(&mut svg, Edit::Move{index: 0, before: transform, after});
+        history.seal();
+        history.apply(&mut svg, Edit::Recolor{index: 0, before: Color{r:1, g:2, b:3}, after: Color{r:0xff, g:0, b:0x80}});
+        history.seal();
+        let before = Layout::capture(&svg);
+        let mut grown = before.clone();
+        grown.height += 7;
+        grown.origins[0].1 += 7.;
+        history.apply(&mut svg, Edit::Resize{before, after: grown});
+        history.undo(&mut svg);
+        let document = svg.to_string().unwrap();
+        // the editor fingerprints what it wrote, so reloading has to reproduce it exactly
+        assert_eq!(SVG::from_str(&document).unwrap().to_string().unwrap(), document);
+
+        let mut camera = Transform::new(0, 0);
+        camera.tx = -40.5;
+        camera.ty = 12.;
+        camera.scalex = 0.5;
+        camera.scaley = 0.5;
+        let mut mask = Transform::new(100, 50);
+        mask.tx = 7.;
+        mask.rotate = 30.;
+        let session = Session{
+            color: Color{r:0xee, g:0x40, b:0x35},
+            locked: true,
+            camera,
+            masks: vec![mask, Transform::new(100, 50)],
+        };
+        let saved = session.to_string(&history, &document).unwrap();
+        let (restored, mut restored_history) = Session::from_str(&saved, &document, 16).unwrap();
+        assert_eq!(restored, session);
+        assert_eq!(restored.to_string(&restored_history, &document).unwrap(), saved);
+        // the restored history walks the document through the same states
+        assert!(restored_history.redo(&mut svg));
+        assert_eq!(svg.height, 607);
+        while restored_history.undo(&mut svg) {
+        }
+        assert_eq!(svg.to_string().unwrap(), SVG::new(800, 600).to_string().unwrap());
+
+        // a drawing edited elsewhere keeps the view but not the undo steps
+        let (elsewhere, elsewhere_history) = Session::from_str(&saved, "<svg/>", 16).unwrap();
+        assert_eq!(elsewhere, session);
+        assert!(elsewhere_history.is_empty());
+        assert!(Session::from_str("<session version=\"99\" color=\"#000000\"><camera tx=\"0\" ty=\"0\" zoom=\"1\"/></session>", "", 16).is_err());
+        assert_eq!(session_path(Path::new("art/castle.svg")), Path::new("art/castle.svg.session"));
+    }
+
+    #[test]
+    fn test_session_restores_matrix_transforms() {
+        // skewed stamps from another editor, which only a matrix describes
+        let skew = r##"<g transform="matrix(1, 0, 0.5, 1, 10, 20)"><rect x="0" y="0" width="64" height="64" fill="#000000" mask="url(#stamps/rect.bmp)"/></g>"##;
+        let document = format!("<svg version=\"2.0\" width=\"800\" height=\"600\">\n{}\n{}\n</svg>", skew, skew);
+        let mut svg = SVG::from_str(&document).unwrap();
+        let skewed = svg.stamps[0].transform.clone();
+        assert!(skewed.matrix.is_some() && (skewed.midx, skewed.midy) == (32., 32.));
+        let mut history = History::new(16);
+        let mut turned = Transform::new(64, 64);
+        turned.rotate = 30.;
+        history.apply(&mut svg, Edit::Move{index: 0, before: skewed.clone(), after: turned.clone()});
+        history.seal();
+        let removed = svg.stamps[1].clone();
+        history.apply(&mut svg, Edit::Remove{index: 1, stamp: removed});
+        let session = Session{color: Color::default(), locked: false, camera: Transform::new(0, 0), masks: Vec::new()};
+        let (_, mut restored) = Session::from_str(&session.to_string(&history, &document).unwrap(), &document, 16).unwrap();
+
+        // undoing the restored edits puts the matrices back turning about the middle of the stamps
+        assert!(restored.undo(&mut svg));
+        assert_eq!(svg.stamps[1].transform, skewed);
+        assert!(restored.undo(&mut svg));
+        assert_eq!(svg.stamps[0].transform, skewed);
+        assert_eq!(svg.to_string().unwrap(), SVG::from_str(&document).unwrap().to_string().unwrap());
+    }
+}
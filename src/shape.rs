@@ -0,0 +1,132 @@
+// a region of the plane as a list of closed rings read with the even-odd rule: outer boundaries
+// wind one way and the holes inside them the other, and no ring crosses another, so sub-shapes
+// that overlapped in the svg have already been merged by the time anything collides with them
+use std::vec::Vec;
+use super::boolean;
+use super::boolean::Ring;
+use super::polygonsvg::{F64Point, Transform, ftransform, sub2d, point_in_polygon};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Shape {
+    pub rings: Vec<Ring>,
+}
+
+impl Shape {
+    // rings with fewer than three points enclose nothing and are dropped
+    pub fn new(rings: Vec<Ring>) -> Shape {
+        Shape{rings: rings.into_iter().filter(|ring| ring.len() >= 3).collect()}
+    }
+    pub fn from_ring(ring: Ring) -> Shape {
+        Shape::new(vec![ring])
+    }
+    // a single ring that may cross itself or double back, like the editor's clip paths,
+    // untangled into outer boundaries and holes that enclose the same points
+    pub fn from_even_odd(ring: &[F64Point]) -> Shape {
+        Shape::new(boolean::union(&[ring.to_vec()], &[]))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rings.is_empty()
+    }
+    pub fn contains(&self, p: F64Point) -> bool {
+        boolean::contains(&self.rings, p)
+    }
+    // every ring closes on itself, so no edge joins one ring to the next
+    pub fn edges<'a>(&'a self) -> impl Iterator<Item=(F64Point, F64Point)> + 'a {
+        self.rings.iter().flat_map(|ring| {
+            (0..ring.len()).map(move |index| (ring[index], ring[(index + 1) % ring.len()]))
+        })
+    }
+    pub fn transformed(&self, transform: &Transform) -> Shape {
+        Shape{rings: self.rings.iter().map(
+            |ring| ring.iter().map(|vertex| ftransform(transform, *vertex)).collect()).collect()}
+    }
+    // holes count against the outer boundaries around them
+    pub fn area(&self) -> f64 {
+        self.rings.iter().map(|ring| boolean::signed_area(ring)).sum::<f64>().abs() / 2.
+    }
+    // each outer boundary with the holes directly inside it. this relies on outer boundaries and
+    // holes winding opposite ways, as they do in the results of the boolean operations
+    pub fn components(&self) -> Vec<Shape> {
+        let outer_sign = match self.rings.iter().map(|ring| boolean::signed_area(ring)).max_by(
+            |a, b| a.abs().partial_cmp(&b.abs()).unwrap_or(std::cmp::Ordering::Equal)) {
+            Some(area) => area.signum(),
+            None => return Vec::new(),
+        };
+        let (outers, holes): (Vec<&Ring>, Vec<&Ring>) = self.rings.iter().partition(
+            |ring| boolean::signed_area(ring) * outer_sign > 0.);
+        let mut ret: Vec<Shape> = outers.iter().map(|ring| Shape{rings: vec![ring.to_vec()]}).collect();
+        for hole in holes {
+            // the middle of the longest edge is clear of any corner the hole may share with its boundary
+            let (a, b) = Shape{rings: vec![hole.clone()]}.edges().max_by(|x, y| {
+                let (dx, dy) = (sub2d(x.1, x.0), sub2d(y.1, y.0));
+                (dx.0 * dx.0 + dx.1 * dx.1).partial_cmp(&(dy.0 * dy.0 + dy.1 * dy.1)).unwrap_or(std::cmp::Ordering::Equal)
+            }).unwrap();
+            let middle = ((a.0 + b.0) / 2., (a.1 + b.1) / 2.);
+            let parent = (0..ret.len()).filter(|index| point_in_polygon(middle, &ret[*index].rings[0])).min_by(
+                |x, y| ret[*x].area().partial_cmp(&ret[*y].area()).unwrap_or(std::cmp::Ordering::Equal));
+            match parent {
+                Some(index) => ret[index].rings.push(hole.clone()),
+                None => ret.push(Shape{rings: vec![hole.clone()]}),
+            }
+        }
+        ret
+    }
+    pub fn union(&self, other: &Shape) -> Shape {
+        Shape::new(boolean::union(&self.rings, &other.rings))
+    }
+    pub fn intersection(&self, other: &Shape) -> Shape {
+        Shape::new(boolean::intersection(&self.rings, &other.rings))
+    }
+    pub fn difference(&self, other: &Shape) -> Shape {
+        Shape::new(boolean::difference(&self.rings, &other.rings))
+    }
+    pub fn xor(&self, other: &Shape) -> Shape {
+        Shape::new(boolean::xor(&self.rings, &other.rings))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Shape;
+    use super::super::boolean::signed_area;
+
+    #[test]
+    fn test_shape() {
+        // a clip path whose hole is reached by an edge walked there and back again
+        let clip = [(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.),
+                    (4., 4.), (6., 4.), (6., 6.), (4., 6.), (4., 4.), (0., 0.)];
+        let shape = Shape::from_even_odd(&clip);
+        assert_eq!(shape.rings.len(), 2);
+        assert!(signed_area(&shape.rings[0]) * signed_area(&shape.rings[1]) < 0.);
+        assert!((shape.area() - 96.).abs() < 1.0e-9);
+        assert!(shape.contains((2., 2.)) && !shape.contains((5., 5.)));
+        assert_eq!(shape.edges().count(), 8);
+
+        // overlapping squares, which an even-odd test of the raw rings would see a hole between
+        let a = Shape::from_ring(vec![(0., 0.), (10., 0.), (10., 10.), (0., 10.)]);
+        let b = Shape::from_ring(vec![(5., 5.), (15., 5.), (15., 15.), (5., 15.)]);
+        let overlapping = Shape::new(vec![a.rings[0].clone(), b.rings[0].clone()]);
+        assert!(!overlapping.contains((7., 7.)));
+        let merged = a.union(&b);
+        assert_eq!(merged.rings.len(), 1);
+        assert!(merged.contains((7., 7.)));
+        assert!((merged.area() - 175.).abs() < 1.0e-9);
+        assert!((a.intersection(&b).area() - 25.).abs() < 1.0e-9);
+        assert!((a.difference(&b).area() - 75.).abs() < 1.0e-9);
+        assert!((a.xor(&b).area() - 150.).abs() < 1.0e-9);
+
+        assert!(Shape::new(vec![vec![(0., 0.), (1., 1.)]]).is_empty());
+
+        // two islands, one of them with a lake that has an island of its own
+        let outer = Shape::from_ring(vec![(0., 0.), (30., 0.), (30., 30.), (0., 30.)]);
+        let lake = Shape::from_ring(vec![(5., 5.), (25., 5.), (25., 25.), (5., 25.)]);
+        let islet = Shape::from_ring(vec![(10., 10.), (20., 10.), (20., 20.), (10., 20.)]);
+        let apart = Shape::from_ring(vec![(40., 0.), (50., 0.), (50., 10.), (40., 10.)]);
+        let map = outer.difference(&lake).union(&islet).union(&apart);
+        let components = map.components();
+        let mut rings: Vec<usize> = components.iter().map(|component| component.rings.len()).collect();
+        rings.sort();
+        assert_eq!(rings, vec![1, 1, 2]);
+        assert!((components.iter().map(|component| component.area()).sum::<f64>() - map.area()).abs() < 1.0e-9);
+    }
+}
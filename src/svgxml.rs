@@ -1,38 +1,21 @@
 #[allow(unused_imports)]
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::vec::Vec;
 use super::serde_xml_rs::from_str;
-use super::serde_xml_rs;
+use std::io;
 use std::io::Read;
 use std::fmt::Write;
 use serde::{Deserialize, Deserializer};
 use serde;
 use regex::Regex;
+use super::assets::AssetResolver;
+use super::error::Error;
+use super::xmlwriter::{XmlWriter, XmlStyle, to_xml_string};
+use super::foreign;
+use super::foreign::{Foreign, Node};
 use std::convert::TryFrom;
-fn attr_escape<'a> (s:&'a String, scratch :&'a mut String) -> &'a str {
-    let mut any_found = false;
-    for c in s.chars() {
-        match c {
-            '>' | '<'  | '"' | '\'' | '&' => any_found = true,
-            _ =>{},
-        }
-    }
-    if any_found {
-        *scratch = s.chars().map(|c| match c{
-            '<' => "&lt;".to_string(),
-            '>' => "&gt;".to_string(),
-            '"' => "&quot;".to_string(),
-            '\'' => "&apos;".to_string(),
-            '&' => "&amp;".to_string(),
-            _ => c.to_string(),
-        }).collect();
-        scratch
-    } else {
-        s
-    }
-}
-
 #[derive(Debug, Default,Copy,Clone, Eq,PartialEq)]
 pub struct Color{
     pub r:u8,
@@ -107,7 +90,17 @@ impl serde::Serialize  for Color {
     }
 }
 
-pub use super::polygonsvg::{ftransform,itransform, F64Point, Transform, transform_deserializer, point_deserializer};
+pub use super::polygonsvg::{ftransform,itransform, F64Point, Transform, Affine, transform_deserializer, point_deserializer};
+use super::shape::Shape;
+use super::boolean::Ring;
+use super::path::{parse_path, flatten, FLATTEN_TOLERANCE};
+
+// the furthest, in document pixels, a curved stamp outline may be from the polygon it collides as
+pub const CHORD_TOLERANCE: f64 = 0.25;
+
+// collision shapes by outline url and the power of two their tolerance was rounded down to,
+// so that stamps placed at similar scales share one
+pub type ShapeCache = HashMap<(String, i32), Shape>;
 
 fn poly_helper(a: &[F64Point], b:&[F64Point]) -> bool {
     for (i1, p0) in a.iter().enumerate() {
@@ -137,15 +130,29 @@ pub fn poly_edge_intersect(a: &[F64Point], b:&[F64Point]) -> bool {
     poly_helper(a, b) || poly_helper(b, a)
 }
 
+// t applied after u. the fields stay a rotation and scale about u's mid point when t's scale
+// commutes with u's rotation; a non-uniform t turns a rotated u into a skew, kept as a matrix
 pub fn compose(t:&Transform, u:&Transform) -> Transform {
-    let txty = ftransform(t, (u.tx, u.ty));
+    // u pivots about its own mid point, so carry that point through t rather than the bare translation
+    let center = ftransform(t, (u.tx + u.midx, u.ty + u.midy));
+    let skewed = t.scalex != t.scaley && u.rotate % 360. != 0.;
+    // a mirror turns u's rotation the other way, unless it mirrors both axes, which is a half turn
+    let rotate = if t.flipx != t.flipy { t.rotate - u.rotate } else { t.rotate + u.rotate };
     Transform{
-        tx: txty.0,
-        ty: txty.1,
+        tx: center.0 - u.midx,
+        ty: center.1 - u.midy,
         midx: u.midx,
         midy: u.midy,
-        rotate: t.rotate + u.rotate,
-        scale: t.scale * u.scale,
+        rotate,
+        scalex: t.scalex * u.scalex,
+        scaley: t.scaley * u.scaley,
+        flipx: t.flipx != u.flipx,
+        flipy: t.flipy != u.flipy,
+        matrix: if skewed || t.matrix.is_some() || u.matrix.is_some() {
+            Some(t.to_affine().mul(&u.to_affine()))
+        } else {
+            None
+        },
     }
 }
 
@@ -163,16 +170,21 @@ struct image {
     pub y: i32,
     pub width: u32,
     pub height: u32,
+    #[serde(default)]
     pub mask: String,
     #[serde(default)]
     #[serde(rename="clip-path")]
     pub clip_mask: String,
+    #[serde(default)]
     pub fill: String,
+    // only present in legacy <image href="assets/stamps/foo.png"/> documents
+    #[serde(default)]
+    pub href: String,
 }
 
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Debug, Serialize, PartialEq, Clone)]
 pub struct Image {
     pub x: i32,
     pub y: i32,
@@ -180,21 +192,43 @@ pub struct Image {
     pub height: u32,
     pub href: HrefAndClipMask,
     pub fill: Color,
+    #[serde(skip)]
+    pub foreign: Foreign,
 }
 
 impl TryFrom<image> for Image {
-    type Error = String;
+    type Error = Error;
     fn try_from(im: image) -> Result<Self, Self::Error> {
+        if im.mask.is_empty() && !im.href.is_empty() {
+            // legacy document: the stamp was referenced directly as an image
+            return Ok(Image{
+                x:im.x,
+                y:im.y,
+                width:im.width,
+                height:im.height,
+                href:HrefAndClipMask{
+                    url:legacy_href_to_url(&im.href),
+                    clip:im.clip_mask,
+                },
+                fill:if im.fill.is_empty() {
+                    Color::default()
+                } else {
+                    Color::try_from(im.fill).map_err(Error::Color)?
+                },
+                foreign:Foreign::default(),
+            });
+        }
         Ok(Image{
             x:im.x,
             y:im.y,
             width:im.width,
             height:im.height,
             href:HrefAndClipMask{
-                url:parse_url_from_mask(&im.mask)?.to_string(),
+                url:parse_url_from_mask(&im.mask).map_err(Error::Invalid)?.to_string(),
                 clip:im.clip_mask,
             },
-            fill:Color::try_from(im.fill)?,
+            fill:Color::try_from(im.fill).map_err(Error::Color)?,
+            foreign:Foreign::default(),
         })
     }
 }
@@ -209,33 +243,46 @@ impl From<Image> for image {
             fill:im.fill.to_string(),
             mask:"url(#".to_string() + &im.href.url+")",
             clip_mask:im.href.clip,
+            href:String::new(),
         }
     }
 }
 
 impl Image {
-    fn to_string(&self) -> Result<String,serde_xml_rs::Error> {
-        let mut scratch = String::new();
-        let mut scratch2 = String::new();
-        if self.href.clip.len() != 0 {
-            Ok(format!(
-                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" mask=\"url(#{})\" clip-path=\"{}\"/>",
-                self.x,self.y,self.width,self.height, self.fill.to_string(), attr_escape(&self.href.url, &mut scratch), attr_escape(&self.href.clip, &mut scratch2),
-            ))
-        } else {
-            Ok(format!(
-                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" mask=\"url(#{})\"/>",
-                self.x,self.y,self.width,self.height,self.fill.to_string(),attr_escape(&self.href.url, &mut scratch),
-            ))
+    fn write_xml<W: io::Write>(&self, xml: &mut XmlWriter<W>) -> Result<(), Error> {
+        let (x, y, width, height) = (self.x.to_string(), self.y.to_string(), self.width.to_string(), self.height.to_string());
+        let fill = self.fill.to_string();
+        let mask = "url(#".to_string() + &self.href.url + ")";
+        let mut attrs = vec![("x", x.as_str()), ("y", &y), ("width", &width), ("height", &height), ("fill", &fill), ("mask", &mask)];
+        if !self.href.clip.is_empty() {
+            attrs.push(("clip-path", &self.href.clip));
+        }
+        let attrs = self.foreign.with_attrs(attrs);
+        if self.foreign.elements.is_empty() {
+            return xml.empty("rect", &attrs);
         }
+        xml.start("rect", &attrs)?;
+        self.foreign.write_elements(xml, 0, true)?;
+        xml.end()
     }
 }
 
 
 
-fn pack_polygon_points(input: &[F64Point]) -> String {
+pub fn pack_polygon_points(input: &[F64Point]) -> String {
     input.iter().map(|val|format!("{} {}", val.0, val.1)).collect::<Vec<String>>().join(",")
 }
+// path data drawing each ring as a closed subpath
+pub fn pack_path_data(rings: &[Ring]) -> String {
+    let mut d = Vec::new();
+    for ring in rings {
+        for (index, point) in ring.iter().enumerate() {
+            d.push(format!("{}{} {}", if index == 0 { "M" } else { "L" }, point.0, point.1));
+        }
+        d.push("Z".to_string());
+    }
+    d.join(" ")
+}
 
 const URL_REGEX_STR: &'static str = r"url\(#([^\)]+)\)";
 fn parse_url_from_mask<'a>(mask:&'a str) -> Result<&'a str, String> {
@@ -252,6 +299,14 @@ fn parse_url_from_mask<'a>(mask:&'a str) -> Result<&'a str, String> {
     Err("Unable to extract relative image url from match".to_string() + mask)
 }
 
+// legacy documents pointed at a png rendering of the stamp; the editor keys stamps by their bmp
+fn legacy_href_to_url(href: &str) -> String {
+    match href.strip_suffix(".png") {
+        Some(stem) => stem.to_string() + ".bmp",
+        None => href.to_string(),
+    }
+}
+
 fn image_deserializer<'de, D>(deserializer: D) -> Result<Image, D::Error>
 where
   D: Deserializer<'de>,
@@ -260,23 +315,54 @@ where
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct g {
     #[serde(deserialize_with="transform_deserializer")]
     pub transform: Transform,
     #[serde(rename="$value")]
     #[serde(deserialize_with="image_deserializer")]
     pub rect: Image,
+    #[serde(skip)]
+    pub foreign: Foreign, // elements after 0 came before the rect
+}
+impl g {
+    // a stamp whose rect is sized by the unscaled stamp, i.e. twice the transform's mid point
+    pub fn new(transform: Transform, img: String, clip_mask: String, color: Color) -> g {
+        let width = (transform.midx * 2.0) as u32;
+        let height = (transform.midy * 2.0) as u32;
+        g{
+            transform,
+            rect:Image{
+                x:0,
+                y:0,
+                fill:color,
+                width,
+                height,
+                href:HrefAndClipMask{url:img, clip:clip_mask},
+                foreign:Foreign::default(),
+            },
+            foreign:Foreign::default(),
+        }
+    }
+    // transform turning about the middle of this stamp's rect. hand edited transforms are parsed
+    // without knowing which rect they apply to, so they come in centered on the origin
+    pub fn centered(&self, transform: &Transform) -> Transform {
+        transform.recenter(self.rect.width as f64 / 2., self.rect.height as f64 / 2.)
+    }
 }
 
 
 impl g {
-    fn to_string(&self) -> Result<String,serde_xml_rs::Error> {
-        Ok(format!(
-            "<g transform=\"{}\">\n{}\n</g>",
-            self.transform.to_string()?,
-            self.rect.to_string()?,
-        ))
+    pub fn write_xml<W: io::Write>(&self, xml: &mut XmlWriter<W>) -> Result<(), Error> {
+        let transform = self.transform.to_string()?;
+        xml.start("g", &self.foreign.with_attrs(vec![("transform", &transform)]))?;
+        self.foreign.write_elements(xml, 0, false)?;
+        self.rect.write_xml(xml)?;
+        self.foreign.write_elements(xml, 1, true)?;
+        xml.end()
+    }
+    pub fn to_string(&self) -> Result<String,Error> {
+        to_xml_string(XmlStyle::Lines, |xml| self.write_xml(xml))
     }
 }
 
@@ -287,17 +373,49 @@ pub struct Polygon {
     pub points: Vec<F64Point>,
 }
 impl Polygon {
-    fn to_string(&self) -> Result<String, serde_xml_rs::Error> {
-        let mut scratch = String::new();
-        Ok(format!("<polygon points=\"{}\"/>\n",
-                   pack_polygon_points(&self.points),
-        ))
+    fn write_xml<W: io::Write>(&self, xml: &mut XmlWriter<W>) -> Result<(), Error> {
+        xml.empty("polygon", &[("points", &pack_polygon_points(&self.points))])
+    }
+    // clip paths carve their masks out with edges walked there and back, so they are read even-odd
+    pub fn to_shape(&self) -> Shape {
+        Shape::from_even_odd(&self.points)
     }
 }
-#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+// rings written as a single evenodd path, so that holes survive where a polygon could not hold them
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
+pub struct ClipRings {
+    #[serde(deserialize_with="rings_deserializer")]
+    pub d: Vec<Ring>,
+}
+impl ClipRings {
+    fn write_xml<W: io::Write>(&self, xml: &mut XmlWriter<W>) -> Result<(), Error> {
+        xml.empty("path", &[("clip-rule", "evenodd"), ("d", &pack_path_data(&self.d))])
+    }
+}
+impl std::fmt::Display for ClipRings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = to_xml_string(XmlStyle::Lines, |xml| self.write_xml(xml)).map_err(|_| std::fmt::Error)?;
+        writeln!(f, "{}", text)
+    }
+}
+fn rings_deserializer<'de, D>(deserializer: D) -> Result<Vec<Ring>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let input = String::deserialize(deserializer)?;
+  let segments = parse_path(input.as_str()).map_err(|err| serde::de::Error::custom(Error::PathData(err)))?;
+  Ok(flatten(&segments, FLATTEN_TOLERANCE).into_iter().map(|subpath| subpath.points).collect())
+}
+
+// older documents carve the paper masks out of a polygon with return edges; newer ones carry the
+// rings the masks leave behind
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
 pub struct ClipPath{
     pub id: String,
+    #[serde(default)]
     pub polygon: Polygon,
+    #[serde(default)]
+    pub path: Option<ClipRings>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
@@ -314,12 +432,40 @@ pub struct Mask{
     pub id: String,
 }
 impl ClipPath {
-    fn to_string(&self) -> Result<String, serde_xml_rs::Error> {
-        let mut scratch = String::new();
-        Ok(format!("<clipPath id=\"{}\">\n{}</clipPath>\n",
-                   attr_escape(&self.id, &mut scratch),
-                   self.polygon.to_string()?,
-        ))
+    // the part of a stamp placed with transform that none of the paper masks cover, in the
+    // stamp's own coordinates
+    pub fn from_masks(id: String, transform: &Transform, masks: &[Transform]) -> ClipPath {
+        let (width, height) = (transform.midx * 2., transform.midy * 2.);
+        let bounds = Shape::from_ring(vec![(0., 0.), (width, 0.), (width, height), (0., height)]);
+        let mut covered = Shape::default();
+        for mask in masks {
+            let (mask_width, mask_height) = (mask.midx * 2., mask.midy * 2.);
+            let corners: Option<Vec<F64Point>> = [(0., 0.), (mask_width, 0.), (mask_width, mask_height), (0., mask_height)].iter().map(
+                |corner| itransform(transform, ftransform(mask, *corner))).collect();
+            // a stamp flattened to a line shows nothing for a mask to cover
+            if let Some(corners) = corners {
+                covered = covered.union(&Shape::from_ring(corners));
+            }
+        }
+        ClipPath{id, polygon: Polygon::default(), path: Some(ClipRings{d: bounds.difference(&covered).rings})}
+    }
+    pub fn to_shape(&self) -> Shape {
+        match self.path {
+            Some(ref path) => Shape::new(path.d.clone()),
+            None => self.polygon.to_shape(),
+        }
+    }
+    pub fn write_xml<W: io::Write>(&self, xml: &mut XmlWriter<W>) -> Result<(), Error> {
+        xml.start("clipPath", &[("id", &self.id)])?;
+        match self.path {
+            Some(ref path) => path.write_xml(xml)?,
+            None => self.polygon.write_xml(xml)?,
+        }
+        xml.end()
+    }
+    // ends with a line break, like the edits that embed it
+    pub fn to_string(&self) -> Result<String, Error> {
+        Ok(to_xml_string(XmlStyle::Lines, |xml| self.write_xml(xml))? + "\n")
     }
 }
 #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
@@ -328,37 +474,54 @@ pub struct defs {
     pub clipPath: Vec<ClipPath>,
     #[serde(default)]
     pub mask: Vec<Mask>,
+    #[serde(skip)]
+    pub foreign: Foreign, // counted after clip paths; the masks are written from the stamps
+}
+
+// each stamps/foo.bmp mask is drawn from the outline in foo.svg beside the stamps directory
+pub fn stamp_svg_path(bmp_name: &str) -> String {
+    match bmp_name.strip_prefix("stamps/") {
+        Some(rest) => rest.replace(".bmp", ".svg"),
+        None => bmp_name.replace("/stamps/","/").replace(".bmp", ".svg"),
+    }
+}
+
+// a stamp library may point a bitmap at an outline elsewhere, otherwise the naming convention applies
+fn resolve_outline(outlines: &HashMap<String, String>, bmp_name: &str) -> String {
+    match outlines.get(bmp_name) {
+        Some(outline) => outline.clone(),
+        None => stamp_svg_path(bmp_name),
+    }
 }
 
-fn read_to_string(filename: &Path) ->  Result<String, serde_xml_rs::Error> {
-    let mut f = std::fs::File::open(filename)?;
+fn read_to_string(filename: &Path) ->  Result<String, Error> {
+    let mut f = std::fs::File::open(filename).map_err(|err| Error::asset(filename, err))?;
     let mut buffer = String::new();
     match f.read_to_string(&mut buffer) {
         Ok(_) => Ok(buffer),
-        Err(e) => Err(serde::de::Error::custom(e)),
+        Err(err) => Err(Error::asset(filename, err)),
     }
 }
 
 
 impl defs {
-    fn to_string(&self, stamps: &Vec<g>) -> Result<String,serde_xml_rs::Error> {
-        let mut ret = vec![String::new();self.clipPath.len()];
-        for (serialized, deserialized) in ret.iter_mut().zip(self.clipPath.iter())   {
-            *serialized = deserialized.to_string()?;
-        }
-	let mut active_images = std::collections::BTreeSet::<String>::new();
-	for stamp in stamps {
-	    if !active_images.contains(&stamp.rect.href.url) {
-	    active_images.insert(stamp.rect.href.url.clone());
-	    }
-	}
-	for active_image in active_images {
-        let svg_filename = active_image.replace("/stamps/","/").replace(".bmp", ".svg");
-        let asset_xml = read_to_string(&Path::new(&svg_filename))?;
-	    //ret.push(format!("<mask id=\"{}\"><image x=\"0\" y=\"0\" width=\"64\" height=\"64\" href=\"{}\"/></mask>\n",active_image, svg_filename));
-        ret.push(format!("<mask id=\"{}\">{}</mask>\n",active_image, asset_xml));
-	}
-        Ok(format!("<defs>\n{}</defs>\n", ret.join("")))
+    fn write_xml<W: io::Write, F: Fn(&str) -> String>(&self, xml: &mut XmlWriter<W>, stamps: &[g], outline_path: F) -> Result<(), Error> {
+        xml.start("defs", &self.foreign.with_attrs(Vec::new()))?;
+        for (index, clip_path) in self.clipPath.iter().enumerate() {
+            self.foreign.write_elements(xml, index, false)?;
+            clip_path.write_xml(xml)?;
+        }
+        self.foreign.write_elements(xml, self.clipPath.len(), true)?;
+        let active_images = stamps.iter().map(|stamp| &stamp.rect.href.url).collect::<std::collections::BTreeSet<&String>>();
+        for active_image in active_images {
+            let svg_filename = outline_path(active_image);
+            let asset_xml = read_to_string(Path::new(&svg_filename))?;
+            // the outline is copied in as it is, so the mask draws exactly what the stamp's svg does
+            xml.start("mask", &[("id", active_image)])?;
+            xml.raw(&asset_xml)?;
+            xml.end()?;
+        }
+        xml.end()
     }
 }
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -371,6 +534,85 @@ pub struct SVG {
     #[serde(default)]
     #[serde(rename="g")]
     pub stamps: Vec<g>,
+    #[serde(skip)]
+    pub outlines: HashMap<String, String>, // bitmap url to outline svg, filled in from the stamp library
+    #[serde(skip)]
+    pub assets: AssetResolver,
+    #[serde(skip)]
+    pub prolog: String, // whatever came before <svg>, an xml declaration or a comment
+    #[serde(skip)]
+    pub foreign: Foreign, // counted after stamps
+}
+
+// what SVG::from_str found in a document besides the stamps and clip paths it knows
+#[derive(Default)]
+struct Found {
+    spans: Vec<(usize, usize)>, // blanked out before the document is deserialized
+    root: Foreign,
+    defs: Foreign,
+    masks: Vec<Option<String>>, // the id of every foreign element of defs that is a mask
+    stamps: Vec<(Foreign, Foreign)>, // the g's and the rect's
+}
+
+const ROOT_ATTRS: [&str; 4] = ["version", "width", "height", "xmlns"];
+const RECT_ATTRS: [&str; 9] = ["x", "y", "width", "height", "fill", "mask", "clip-path", "href", "xlink:href"];
+
+impl Found {
+    fn keep(&mut self, document: &str, node: &Node, after: usize) -> foreign::ForeignElement {
+        self.spans.push((node.start, node.end));
+        node.keep(document, after)
+    }
+    fn new(document: &str, root: &Node) -> Found {
+        let mut ret = Found::default();
+        ret.root.attrs = root.foreign_attrs(&ROOT_ATTRS);
+        let mut seen_defs = false;
+        for child in &root.children {
+            match child.name.as_str() {
+                "g" => {
+                    let mut group = Foreign{attrs: child.foreign_attrs(&["transform"]), elements: Vec::new()};
+                    let mut rect: Option<Foreign> = None;
+                    for inner in &child.children {
+                        if rect.is_none() && (inner.name == "rect" || inner.name == "image") {
+                            let mut found = Foreign{attrs: inner.foreign_attrs(&RECT_ATTRS), elements: Vec::new()};
+                            for content in &inner.children {
+                                found.elements.push(ret.keep(document, content, 0));
+                            }
+                            rect = Some(found);
+                        } else {
+                            let after = if rect.is_some() { 1 } else { 0 };
+                            group.elements.push(ret.keep(document, inner, after));
+                        }
+                    }
+                    ret.stamps.push((group, rect.unwrap_or_default()));
+                },
+                "defs" if !seen_defs => {
+                    seen_defs = true;
+                    ret.defs.attrs = child.foreign_attrs(&[]);
+                    let mut clip_paths = 0;
+                    for inner in &child.children {
+                        match inner.name.as_str() {
+                            "clipPath" => clip_paths += 1,
+                            // left for the deserializer, and only foreign if no stamp uses it
+                            "mask" => {
+                                ret.masks.push(Some(inner.attr("id").unwrap_or_default().to_string()));
+                                ret.defs.elements.push(inner.keep(document, clip_paths));
+                            },
+                            _ => {
+                                ret.masks.push(None);
+                                let element = ret.keep(document, inner, clip_paths);
+                                ret.defs.elements.push(element);
+                            },
+                        }
+                    }
+                },
+                _ => {
+                    let element = ret.keep(document, child, ret.stamps.len());
+                    ret.root.elements.push(element);
+                },
+            }
+        }
+        ret
+    }
 }
 
 impl SVG {
@@ -380,41 +622,88 @@ impl SVG {
         width: width,
         height:height,
         stamps:Vec::new(),
-        defs:defs{clipPath:Vec::new(),mask:Vec::new()},
+        defs:defs{clipPath:Vec::new(),mask:Vec::new(),foreign:Foreign::default()},
+        outlines:HashMap::new(),
+        assets:AssetResolver::default(),
+        prolog:String::new(),
+        foreign:Foreign::default(),
       }
     }
-    pub fn from_str(s: &str) -> Result<Self,serde_xml_rs::Error> {
+    pub fn from_str(s: &str) -> Result<Self,Error> {
         use super::serde_xml_rs::from_str;
-        from_str(s)
+        // a document that doesn't scan goes to the deserializer as it is, to be reported from there
+        let root = foreign::scan(s);
+        let found = match root {
+            Some(ref root) => Found::new(s, root),
+            None => Found::default(),
+        };
+        let mut ret: SVG = from_str(&foreign::blank(s, &found.spans))?;
+        for stamp in ret.stamps.iter_mut() {
+            stamp.transform = stamp.centered(&stamp.transform);
+        }
+        if let Some(root) = root {
+            ret.prolog = s[..root.start].to_string();
+            ret.foreign = found.root;
+            let used = ret.stamps.iter().map(|stamp| &stamp.rect.href.url).collect::<std::collections::BTreeSet<&String>>();
+            #[allow(clippy::unnecessary_map_or)] // is_none_or needs rust 1.82
+            let elements = found.defs.elements.into_iter().zip(found.masks).filter(
+                |(_, mask)| mask.as_ref().map_or(true, |id| !used.contains(id))).map(|(element, _)| element).collect();
+            ret.defs.foreign = Foreign{attrs: found.defs.attrs, elements};
+            if found.stamps.len() == ret.stamps.len() {
+                for (stamp, (group, rect)) in ret.stamps.iter_mut().zip(found.stamps) {
+                    stamp.foreign = group;
+                    stamp.rect.foreign = rect;
+                }
+            }
+        }
+        Ok(ret)
     }
     pub fn resize(&mut self, width: u32, height: u32) {
         self.width = width;
         self.height = height;
     }
-    pub fn load_polygon(&self, bmp_name: &str) -> Result<Vec<F64Point>, serde_xml_rs::Error> {
-        
-        let asset_data = match read_to_string(&Path::new(&bmp_name.to_string().replace("/stamps/","/").replace(".bmp", ".svg"))) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("FAILED LOADING {:?}\n", Path::new(&bmp_name.to_string().replace("/stamps/","/").replace(".bmp", ".svg")));
-                return Err(serde::de::Error::custom(e));
-            },
-        };
-        super::polygonsvg::to_polygon(&asset_data, )
+    // finds the clip path a clip-path="url(#id)" reference in defs names
+    pub fn clip_path(&self, clip: &str) -> Option<&ClipPath> {
+        self.defs.clipPath.iter().find(|clips| "url(#".to_string() + &clips.id + ")" == clip)
+    }
+    // looks stamps up under assets from now on, rewriting hrefs saved relative to the working
+    // directory to be relative to the asset root instead
+    pub fn use_assets(&mut self, assets: AssetResolver) {
+        for stamp in self.stamps.iter_mut() {
+            stamp.rect.href.url = assets.relative(&stamp.rect.href.url);
+        }
+        self.assets = assets;
+    }
+    pub fn asset_path(&self, href: &str) -> PathBuf {
+        self.assets.resolve(href)
+    }
+    pub fn outline_path(&self, bmp_name: &str) -> String {
+        self.asset_path(&resolve_outline(&self.outlines, bmp_name)).to_string_lossy().into_owned()
+    }
+    // tolerance is in the stamp's own pixels
+    pub fn load_polygon(&self, bmp_name: &str, tolerance: f64) -> Result<Shape, Error> {
+        let outline = self.outline_path(bmp_name);
+        let asset_data = read_to_string(Path::new(&outline))?;
+        super::polygonsvg::to_shape(&asset_data, tolerance)
+    }
+    // the outline of stamp cut finely enough to stay within CHORD_TOLERANCE once it is scaled into place
+    pub fn stamp_shape<'a>(&self, stamp: &g, cache: &'a mut ShapeCache) -> Result<&'a Shape, Error> {
+        let scale = stamp.transform.to_affine().max_scale();
+        let level = (CHORD_TOLERANCE / scale).log2().floor().clamp(-16., 16.) as i32;
+        match cache.entry((stamp.rect.href.url.clone(), level)) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(self.load_polygon(&stamp.rect.href.url, 2f64.powi(level))?)),
+        }
     }
     //
-    pub fn intersect(&self, left: F64Point, right:F64Point, cache: &mut HashMap<String,Vec<F64Point>>) -> Result<Option<F64Point>, serde_xml_rs::Error> {
+    pub fn intersect(&self, left: F64Point, right:F64Point, cache: &mut ShapeCache) -> Result<Option<F64Point>, Error> {
         for stamp in &self.stamps {
-            let cur_poly:Vec<F64Point>;
-            let poly = match &cache.get(&stamp.rect.href.url) {
-                None => {
-                    cur_poly = self.load_polygon(&stamp.rect.href.url)?;
-                    cache.insert(stamp.rect.href.url.clone(), cur_poly.clone());
-                    &cur_poly
-                },
-                &Some(poly) => poly,
-            };
-            if poly.len() == 0 {
+            // a stamp flattened to a line or a point has nothing to bounce off
+            if stamp.transform.to_affine().invert().is_none() {
+                continue
+            }
+            let poly = self.stamp_shape(stamp, cache)?;
+            if poly.is_empty() {
                 continue
             }
             if let Some(bounce) = super::polygonsvg::segment_inside_polygon(left, right, &stamp.transform, poly, (0.,-1.)) {
@@ -425,43 +714,85 @@ impl SVG {
         }
         Ok(None)
     }
+    // index of the topmost stamp whose outline, less anything its clip path cuts away, covers p
+    pub fn stamp_at(&self, p: F64Point, cache: &mut ShapeCache) -> Result<Option<usize>, Error> {
+        for (index, stamp) in self.stamps.iter().enumerate().rev() {
+            let local = match itransform(&stamp.transform, p) {
+                Some(local) => local,
+                None => continue, // flattened to a line or a point, so covering nothing
+            };
+            let shape = self.stamp_shape(stamp, cache)?;
+            let inside = if shape.is_empty() {
+                local.0 >= 0. && local.1 >= 0. && local.0 <= stamp.rect.width as f64 && local.1 <= stamp.rect.height as f64
+            } else {
+                shape.contains(local)
+            };
+            if !inside {
+                continue;
+            }
+            if let Some(clip) = self.clip_path(&stamp.rect.href.clip) {
+                if !clip.to_shape().contains(local) {
+                    continue;
+                }
+            }
+            return Ok(Some(index));
+        }
+        Ok(None)
+    }
     pub fn add(&mut self, transform: Transform, img: String, clip_mask: String, color: Color) {
-        let width = (transform.midx * 2.0) as u32;
-        let height = (transform.midy * 2.0) as u32;
-        self.stamps.push(g{
-            transform:transform,
-            rect:Image{
-                x:0,
-                y:0,
-                fill:color,
-                width:width,
-                height:height,
-                href:HrefAndClipMask{url:img, clip:clip_mask},
-            },
-        });
+        self.stamps.push(g::new(transform, img, clip_mask, color));
     }
-    pub fn to_string(&self) -> Result<String,serde_xml_rs::Error> {
-        let mut ret = vec![String::new();self.stamps.len()];
-        for (serialized, deserialized) in ret.iter_mut().zip(self.stamps.iter())   {
-            *serialized = deserialized.to_string()?;
+    // the smallest axis aligned box holding every placed stamp rect
+    pub fn bounds(&self) -> Option<(F64Point, F64Point)> {
+        let mut ret: Option<(F64Point, F64Point)> = None;
+        for stamp in &self.stamps {
+            let (x0, y0) = (stamp.rect.x as f64, stamp.rect.y as f64);
+            let (x1, y1) = (x0 + stamp.rect.width as f64, y0 + stamp.rect.height as f64);
+            for corner in &[(x0, y0), (x1, y0), (x1, y1), (x0, y1)] {
+                let p = ftransform(&stamp.transform, *corner);
+                ret = Some(match ret {
+                    None => (p, p),
+                    Some((lo, hi)) => ((lo.0.min(p.0), lo.1.min(p.1)), (hi.0.max(p.0), hi.1.max(p.1))),
+                });
+            }
         }
+        ret
+    }
+    // the document size grown to cover every stamp, however it is rotated
+    pub fn extent(&self) -> (u32, u32) {
         let mut max_width = self.width;
         let mut max_height = self.height;
         for stamp in &self.stamps {
-            let diag = ((stamp.transform.midx * stamp.transform.midx) + (stamp.transform.midy * stamp.transform.midy)).sqrt();
+            let diag = ((stamp.transform.midx * stamp.transform.midx) + (stamp.transform.midy * stamp.transform.midy)).sqrt()
+                * stamp.transform.scalex.max(stamp.transform.scaley);
             let x = (stamp.transform.tx + diag + stamp.transform.midx) as u32;
             let y = (stamp.transform.ty + diag + stamp.transform.midy) as u32;
             max_width = std::cmp::max(max_width, x);
             max_height = std::cmp::max(max_height, y);
         }
-        Ok(format!(
-            "<svg version=\"{}\" width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">\n{}\n{}</svg>",
-            self.version,
-            max_width,
-            max_height,
-            ret.join("\n"),
-            self.defs.to_string(&self.stamps)?
-        ))
+        (max_width, max_height)
+    }
+    // streams the document to out, which is handed back once the closing tag is written
+    pub fn write_to<W: io::Write>(&self, out: W, style: XmlStyle) -> Result<W, Error> {
+        let (max_width, max_height) = self.extent();
+        let (width, height) = (max_width.to_string(), max_height.to_string());
+        let mut xml = XmlWriter::new(out, style);
+        xml.raw(&self.prolog)?;
+        xml.start("svg", &self.foreign.with_attrs(vec![("version", &self.version), ("width", &width),
+                                                        ("height", &height), ("xmlns", "http://www.w3.org/2000/svg")]))?;
+        for (index, stamp) in self.stamps.iter().enumerate() {
+            self.foreign.write_elements(&mut xml, index, false)?;
+            stamp.write_xml(&mut xml)?;
+        }
+        self.foreign.write_elements(&mut xml, self.stamps.len(), true)?;
+        self.defs.write_xml(&mut xml, &self.stamps, |url| self.outline_path(url))?;
+        xml.finish()
+    }
+    pub fn to_string_styled(&self, style: XmlStyle) -> Result<String, Error> {
+        String::from_utf8(self.write_to(Vec::new(), style)?).map_err(|err| Error::Invalid(err.to_string()))
+    }
+    pub fn to_string(&self) -> Result<String,Error> {
+        self.to_string_styled(XmlStyle::Lines)
     }
 }
 
@@ -507,6 +838,10 @@ mod test {
 </mask>
 </defs>
 </svg>"##;
+    #[cfg(test)]
+    static LEGACY_SCALED: &str = "scale(2) translate(64, 64) rotate(8) translate(-64, -64)";
+    #[cfg(test)]
+    static SCALED: &str = "translate(64, 64) rotate(8) scale(2, 2) translate(-64, -64)";
     #[test]
     fn test_basic_serde() {
         use super::{SVG, HrefAndClipMask, Image, Transform, g, defs};
@@ -517,7 +852,8 @@ mod test {
             version:"2.0".to_string(),
             stamps:vec![
                 g{
-                  transform:Transform{scale:2.0, tx:0.0, ty:0.0, rotate:8.0, midx:64.0, midy:64.0},
+                  transform:Transform{scalex:2.0, scaley:2.0, tx:0.0, ty:0.0, rotate:8.0, midx:64.0, midy:64.0, ..Transform::default()},
+                  foreign:Default::default(),
                     rect:Image{
 		    fill:Color{r:0,g:0,b:0},
                         x:0,
@@ -525,10 +861,12 @@ mod test {
                         height:128,
                         width:128,
                         href:HrefAndClipMask{url:"assets/stamps/larch.bmp".to_string(),clip:String::new()},
+                        foreign:Default::default(),
                     }
                 },
                 g{
-                  transform:Transform{scale:1.0, tx:290.0, ty:80.0, rotate:220.0, midx:64.0, midy:64.0},
+                  transform:Transform{scalex:1.0, scaley:1.0, tx:290.0, ty:80.0, rotate:220.0, midx:64.0, midy:64.0, ..Transform::default()},
+                  foreign:Default::default(),
                     rect:Image{
 		    fill:Color{r:255,g:16,b:8},
                         x:0,
@@ -536,23 +874,31 @@ mod test {
                         height:128,
                         width:128,
                         href:HrefAndClipMask{url:"assets/stamps/rarch.bmp".to_string(),clip:String::new()},
+                        foreign:Default::default(),
                     }                        
                 },
             ],
             defs:defs{
                 clipPath:Vec::new(),
+                foreign:Default::default(),
                 mask:vec![
                     Mask { id: "assets/stamps/larch.bmp".to_string() },
                     Mask { id: "assets/stamps/rarch.bmp".to_string() }
                 ],
             },
+            outlines:std::collections::HashMap::new(),
+            assets:super::AssetResolver::default(),
+            prolog:String::new(),
+            foreign:Default::default(),
         };
-        use super::serde_xml_rs::from_str;
+        use super::super::serde_xml_rs::from_str;
         let svg_deserialized: SVG = from_str(LARCH_RARCH).unwrap();
         assert_eq!(svg_deserialized, svg_struct);
         let svg_serialized = svg_struct.to_string().unwrap();
         eprintln!("{}",svg_serialized);
-        assert_eq!(svg_serialized, LARCH_RARCH);
+        // the legacy leading scale is written back in the order svg viewers apply it
+        assert_eq!(svg_serialized, LARCH_RARCH.replace(LEGACY_SCALED, SCALED));
+        assert_eq!(from_str::<SVG>(&svg_serialized).unwrap(), svg_struct);
     }
     #[test]
     fn test_clip_mask_serde() {
@@ -607,7 +953,8 @@ mod test {
             version:"2.0".to_string(),
             stamps:vec![
                 g{
-                  transform:Transform{scale:2.0, tx:0.0, ty:0.0, rotate:8.0, midx:64.0, midy:64.0},
+                  transform:Transform{scalex:2.0, scaley:2.0, tx:0.0, ty:0.0, rotate:8.0, midx:64.0, midy:64.0, ..Transform::default()},
+                  foreign:Default::default(),
                     rect:Image{
                         x:0,
                         y:0,
@@ -615,10 +962,12 @@ mod test {
                         height:128,
                         width:128,
                         href:HrefAndClipMask{url:"assets/stamps/larch.bmp".to_string(),clip:"url(#clippy)".to_string()},
+                        foreign:Default::default(),
                     }
                 },
                 g{
-                  transform:Transform{scale:1.0, tx:290.0, ty:80.0, rotate:220.0, midx:64.0, midy:64.0},
+                  transform:Transform{scalex:1.0, scaley:1.0, tx:290.0, ty:80.0, rotate:220.0, midx:64.0, midy:64.0, ..Transform::default()},
+                  foreign:Default::default(),
                     rect:Image{
                         x:0,
                         y:0,
@@ -626,10 +975,12 @@ mod test {
                         height:128,
                         width:128,
                         href:HrefAndClipMask{url:"assets/stamps/rarch.bmp".to_string(),clip:String::new()},
+                        foreign:Default::default(),
                     }                        
                 },
             ],
             defs:defs{
+                foreign:Default::default(),
                 mask:vec![
                     Mask { id: "assets/stamps/larch.bmp".to_string()},
                     Mask { id: "assets/stamps/rarch.bmp".to_string()}
@@ -644,6 +995,7 @@ mod test {
                                         (4., 4.25),
                             ],
                         },
+                        path:None,
                     },
                     ClipPath {
                         id: "goodbyte".to_string(),
@@ -654,30 +1006,104 @@ mod test {
                                         (-3., 3.),
                         ],
                         },
+                        path:None,
                     },
                     ],
             },
+            outlines:std::collections::HashMap::new(),
+            assets:super::AssetResolver::default(),
+            prolog:String::new(),
+            foreign:Default::default(),
         };
-        use super::serde_xml_rs::from_str;
+        use super::super::serde_xml_rs::from_str;
         let svg_deserialized: SVG = from_str(s).unwrap();
         assert_eq!(svg_deserialized, svg_struct);
         let svg_serialized = svg_struct.to_string().unwrap();
         eprintln!("{}",svg_serialized);
         eprintln!("{:?}",svg_deserialized);
-        assert_eq!(svg_serialized, s);
+        assert_eq!(svg_serialized, s.replace(LEGACY_SCALED, SCALED));
+    }
+    #[test]
+    fn test_legacy_image_href() {
+        use super::{SVG, HrefAndClipMask, Image, Transform, g};
+        let s = r##"<svg version="2.0" width="800" height="600" xmlns="http://www.w3.org/2000/svg">
+<g transform="translate(509, 536) translate(32, 32) translate(-32, -32)">
+<image x="0" y="0" width="64" height="64" href="assets/stamps/rarch.png"/>
+</g>
+<g transform="translate(353, 481) translate(32, 32) rotate(90) translate(-32, -32)">
+<rect x="0" y="0" width="64" height="64" fill="#ff1008" mask="url(#assets/stamps/rect.bmp)"/>
+</g>
+</svg>"##;
+        let svg_deserialized = SVG::from_str(s).unwrap();
+        assert_eq!(svg_deserialized.stamps, vec![
+            g{
+                transform:Transform{scalex:1.0, scaley:1.0, tx:509.0, ty:536.0, rotate:0.0, midx:32.0, midy:32.0, ..Transform::default()},
+                foreign:Default::default(),
+                rect:Image{
+                    x:0,
+                    y:0,
+                    fill:Color{r:0,g:0,b:0},
+                    height:64,
+                    width:64,
+                    href:HrefAndClipMask{url:"assets/stamps/rarch.bmp".to_string(),clip:String::new()},
+                    foreign:Default::default(),
+                },
+            },
+            g{
+                transform:Transform{scalex:1.0, scaley:1.0, tx:353.0, ty:481.0, rotate:90.0, midx:32.0, midy:32.0, ..Transform::default()},
+                foreign:Default::default(),
+                rect:Image{
+                    x:0,
+                    y:0,
+                    fill:Color{r:255,g:16,b:8},
+                    height:64,
+                    width:64,
+                    href:HrefAndClipMask{url:"assets/stamps/rect.bmp".to_string(),clip:String::new()},
+                    foreign:Default::default(),
+                },
+            },
+        ]);
+        let svg_serialized = svg_deserialized.to_string().unwrap();
+        assert!(svg_serialized.contains(r##"<rect x="0" y="0" width="64" height="64" fill="#000000" mask="url(#assets/stamps/rarch.bmp)"/>"##));
+        assert_eq!(SVG::from_str(&svg_serialized).unwrap().stamps, svg_deserialized.stamps);
     }
     #[test]
-    fn test_attr_escape() {
-        use super::attr_escape;
-
-        let mut scratch = String::new();
-        assert_eq!("HELLOTE", attr_escape(&"HELLOTE".to_string(), &mut scratch));
-        assert_eq!("HEL&lt;LOTE", attr_escape(&"HEL<LOTE".to_string(), &mut scratch));
-        assert_eq!("HEL&lt;LOTE&gt;", attr_escape(&"HEL<LOTE>".to_string(), &mut scratch));
-        assert_eq!("HEL&lt;LOTE&amp;", attr_escape(&"HEL<LOTE&".to_string(), &mut scratch));
-        assert_eq!("HEL&quot;LOTE&apos;", attr_escape(&"HEL\"LOTE'".to_string(), &mut scratch));
-        assert_eq!("H\u{0026bE}EL&quot;LOTE&apos;",
-                   attr_escape(&"H\u{0026bE}EL\"LOTE'".to_string(), &mut scratch));
+    fn test_general_transform_serde() {
+        use super::{SVG, Affine};
+        let s = r##"<svg version="2.0" width="800" height="600" xmlns="http://www.w3.org/2000/svg">
+<g transform="translate(100, 50) skewX(30) scale(2, 1)">
+<rect x="0" y="0" width="64" height="64" fill="#102030" mask="url(#assets/stamps/square.bmp)"/>
+</g>
+<g transform="translate(10 20) rotate(45 32 32)">
+<rect x="0" y="0" width="64" height="64" fill="#102030" mask="url(#assets/stamps/square.bmp)"/>
+</g>
+</svg>"##;
+        let svg = SVG::from_str(s).unwrap();
+        let skewed = &svg.stamps[0].transform;
+        let expected = Affine::translate(100., 50.).mul(&Affine::skew_x(30.)).mul(&Affine::scale(2., 1.));
+        assert!(skewed.matrix.unwrap().approx_eq(&expected));
+        assert_eq!((skewed.midx, skewed.midy), (32., 32.));
+        let rotated = &svg.stamps[1].transform;
+        assert_eq!(rotated.matrix, None);
+        assert_eq!((rotated.tx.round(), rotated.ty.round(), rotated.rotate.round(), rotated.midx), (10., 20., 45., 32.));
+        let reparsed = SVG::from_str(&svg.to_string().unwrap()).unwrap();
+        assert!(reparsed.stamps[0].transform.matrix.unwrap().approx_eq(&expected));
+        assert!(reparsed.stamps[1].transform.to_affine().approx_eq(&rotated.to_affine()));
+    }
+    #[test]
+    fn test_legacy_examples() {
+        use super::SVG;
+        use std::io::Read;
+        for example in &["examples/towerhouse.svg", "examples/talltower.svg"] {
+            let mut data = String::new();
+            std::fs::File::open(example).unwrap().read_to_string(&mut data).unwrap();
+            let svg = SVG::from_str(&data).unwrap();
+            assert!(!svg.stamps.is_empty());
+            for stamp in &svg.stamps {
+                assert!(stamp.rect.href.url.ends_with(".bmp"));
+                assert!(std::path::Path::new(&stamp.rect.href.url).exists());
+            }
+        }
     }
   #[test]
   fn test_pack_polygon_points() {
@@ -709,7 +1135,7 @@ mod test {
   }
     #[test]
     fn test_intersect() {
-        use super::serde_xml_rs::from_str;
+        use super::super::serde_xml_rs::from_str;
         use super::SVG;
         static LHALFRAMP_RHALFRAMP:&'static str = r##"<svg version="2.0" width="500" height="500" xmlns="http://www.w3.org/2000/svg">
 <g transform="scale(2) translate(64, 64) rotate(8) translate(-64, -64)">
@@ -769,59 +1195,345 @@ mod test {
         let hit4 = intersection4.unwrap();
         assert_eq!((hit4.0.floor(), hit4.1.floor()), (0.0,-26.0));
     }
+    #[test]
+    fn test_flip_intersect() {
+        use super::{SVG, Transform, Color};
+        use std::collections::HashMap;
+        let mut right = SVG::new(500, 500);
+        let mut flipped_left = SVG::new(500, 500);
+        let mut transform = Transform::new(64, 64);
+        transform.tx = 100.;
+        transform.ty = 50.;
+        right.add(transform.clone(), "assets/stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        transform.flipx = true;
+        flipped_left.add(transform, "assets/stamps/lhalframp.bmp".to_string(), String::new(), Color::default());
+        let mut right_cache = HashMap::new();
+        let mut left_cache = HashMap::new();
+        for segment in &[((90., 110.), (200., 110.)), ((120., 60.), (121., 60.)), ((140., 60.), (141., 60.)), ((0., 0.), (10., 0.))] {
+            assert_eq!(right.intersect(segment.0, segment.1, &mut right_cache).unwrap(),
+                       flipped_left.intersect(segment.0, segment.1, &mut left_cache).unwrap());
+        }
+        assert!(right.intersect((120., 60.), (121., 60.), &mut right_cache).unwrap().is_some());
+        assert!(right.intersect((140., 60.), (141., 60.), &mut right_cache).unwrap().is_none());
+    }
+    #[test]
+    fn test_stamp_at() {
+        use super::{SVG, Transform, Color, ClipPath, Polygon};
+        use std::collections::HashMap;
+        let mut svg = SVG::new(500, 500);
+        let mut transform = Transform::new(64, 64);
+        transform.tx = 100.;
+        transform.ty = 50.;
+        svg.add(transform.clone(), "assets/stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        svg.add(transform, "assets/stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        let mut cache = HashMap::new();
+        assert_eq!(svg.stamp_at((120., 60.), &mut cache).unwrap(), Some(1));
+        assert_eq!(svg.stamp_at((140., 60.), &mut cache).unwrap(), None);
+        assert_eq!(svg.stamp_at((10., 10.), &mut cache).unwrap(), None);
+        // clip the top stamp down to its lower half so the one underneath shows through
+        svg.defs.clipPath.push(ClipPath{
+            id:"0".to_string(),
+            polygon:Polygon{points:vec![(0., 32.), (64., 32.), (64., 64.), (0., 64.)]},
+            path:None,
+        });
+        svg.stamps[1].rect.href.clip = "url(#0)".to_string();
+        assert_eq!(svg.stamp_at((120., 60.), &mut cache).unwrap(), Some(0));
+        assert_eq!(svg.stamp_at((120., 110.), &mut cache).unwrap(), Some(1));
+    }
+    #[test]
+    fn test_scaled_stamp_bounds() {
+        use super::{SVG, Transform, Color};
+        // the rect keeps the stamp's own 64x64 and the transform carries the cursor's scale
+        let mut svg = SVG::new(400, 300);
+        let mut transform = Transform::new(64, 64);
+        transform.set_signed_scale((4., 4.));
+        transform.tx = 300. - transform.midx;
+        transform.ty = 200. - transform.midy;
+        svg.add(transform, "stamps/rect.bmp".to_string(), String::new(), Color::default());
+        assert_eq!(svg.bounds(), Some(((172., 72.), (428., 328.))));
+        let (width, height) = svg.extent();
+        assert!(width >= 428 && height >= 328, "{:?}", (width, height));
+        assert!(svg.to_string().unwrap().contains("<rect x=\"0\" y=\"0\" width=\"64\" height=\"64\""));
+    }
+    #[test]
+    fn test_flattened_stamps_are_skipped() {
+        use super::{SVG, Transform, Affine, Color, itransform};
+        use std::collections::HashMap;
+        let mut svg = SVG::new(500, 500);
+        // at the origin, where mapping through the identity would land on the stamp as well
+        let transform = Transform::new(64, 64);
+        svg.add(transform.clone(), "stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        // scaled to nothing, and skewed onto a line, on top of it
+        let mut zero = transform.clone();
+        zero.scalex = 0.;
+        let mut skewed = transform.clone();
+        skewed.matrix = Some(Affine{a: 1., b: 1., c: 2., d: 2., e: 0., f: 0.});
+        assert_eq!(itransform(&zero, (20., 10.)), None);
+        assert_eq!(itransform(&skewed, (20., 10.)), None);
+        svg.add(zero.clone(), "stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        svg.add(skewed, "stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        let mut cache = HashMap::new();
+        assert_eq!(svg.stamp_at((20., 10.), &mut cache).unwrap(), Some(0));
+        assert_eq!(svg.stamp_at((120., 10.), &mut cache).unwrap(), None);
+        // and nothing bounces off one standing alone
+        zero.tx = 300.;
+        svg.stamps = Vec::new();
+        svg.add(zero, "stamps/rhalframp.bmp".to_string(), String::new(), Color::default());
+        assert_eq!(svg.intersect((300., 80.), (400., 80.), &mut cache).unwrap(), None);
+        assert_eq!(svg.intersect((332., 0.), (332., 200.), &mut cache).unwrap(), None);
+    }
+    #[test]
+    fn test_outline_tolerance_follows_scale() {
+        use super::{SVG, Transform, Color, ShapeCache, CHORD_TOLERANCE};
+        let mut svg = SVG::new(2000, 2000);
+        svg.add(Transform::new(64, 64), "assets/stamps/circle.bmp".to_string(), String::new(), Color::default());
+        let mut transform = Transform::new(64, 64);
+        transform.scalex = 8.;
+        transform.scaley = 8.;
+        transform.tx = 1000.;
+        transform.ty = 300.;
+        svg.add(transform, "assets/stamps/circle.bmp".to_string(), String::new(), Color::default());
+        let mut cache = ShapeCache::new();
+        // a 16-gon would sag almost five pixels inside the big circle; every point a hair in is found
+        for step in 0..64 {
+            let angle = step as f64 * std::f64::consts::PI / 32. + 0.01;
+            let radius = 31. * 8. - CHORD_TOLERANCE;
+            let p = (1032. + radius * angle.cos(), 332. + radius * angle.sin());
+            assert_eq!(svg.stamp_at(p, &mut cache).unwrap(), Some(1), "{:?}", p);
+        }
+        assert_eq!(svg.stamp_at((32., 32.), &mut cache).unwrap(), Some(0));
+        // one shape per scale, the bigger one cut finer
+        assert_eq!(cache.len(), 2);
+        let mut vertices: Vec<usize> = cache.values().map(|shape| shape.rings[0].len()).collect();
+        vertices.sort();
+        assert!(vertices[1] > vertices[0] * 2);
+        svg.stamp_at((40., 40.), &mut cache).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
+    #[test]
+    fn test_clip_path_from_masks() {
+        use super::{SVG, Transform, ClipPath};
+        let mut stamp = Transform::new(64, 64);
+        stamp.tx = 100.;
+        stamp.ty = 100.;
+        // one mask over the middle of the stamp, and two overlapping each other across its right edge
+        let mut hole = Transform::new(16, 16);
+        hole.tx = 124.;
+        hole.ty = 124.;
+        let mut edge = Transform::new(32, 32);
+        edge.tx = 150.;
+        edge.ty = 90.;
+        let mut below = edge.clone();
+        below.ty = 110.;
+        let clip = ClipPath::from_masks("0".to_string(), &stamp, &[hole, edge, below]);
+        let shape = clip.to_shape();
+        assert_eq!(shape.rings.iter().map(|ring| ring.len()).collect::<Vec<usize>>(), vec![6, 4]);
+        assert!((shape.area() - (64. * 64. - 16. * 16. - 14. * 42.)).abs() < 1.0e-9);
+        assert!(shape.contains((4., 4.)) && shape.contains((60., 50.)));
+        assert!(!shape.contains((32., 32.)) && !shape.contains((60., 20.)) && !shape.contains((70., 50.)));
+
+        let mut svg = SVG::new(500, 500);
+        svg.defs.clipPath.push(clip.clone());
+        let text = svg.to_string().unwrap();
+        assert!(text.contains("clip-rule=\"evenodd\""));
+        assert_eq!(SVG::from_str(&text).unwrap().defs.clipPath, vec![clip]);
+    }
+    #[test]
+    fn test_escaped_round_trip() {
+        use super::{SVG, Transform, Color, ClipPath, XmlStyle};
+        let mut svg = SVG::new(300, 300);
+        svg.version = "2.0 \"draft\"".to_string();
+        svg.defs.clipPath.push(ClipPath::from_masks("a<b>&'c'".to_string(), &Transform::new(64, 64), &[Transform::new(16, 16)]));
+        svg.add(Transform::new(64, 64), "stamps/rect.bmp".to_string(), "url(#a<b>&'c')".to_string(), Color{r: 1, g: 2, b: 3});
+        // an href read back from a document that spelled it with entities
+        svg.add(Transform::new(64, 64), "stamps/rect.bmp?a=1&b=\"2\"".to_string(), String::new(), Color::default());
+        svg.outlines.insert("stamps/rect.bmp?a=1&b=\"2\"".to_string(), "rect.svg".to_string());
+        let lines = svg.to_string().unwrap();
+        assert!(lines.contains("<mask id=\"stamps/rect.bmp?a=1&amp;b=&quot;2&quot;\">"));
+        assert!(lines.contains("<clipPath id=\"a&lt;b&gt;&amp;&apos;c&apos;\">"));
+        assert!(!lines.contains("&'"));
+        for style in &[XmlStyle::Compact, XmlStyle::Lines, XmlStyle::Pretty] {
+            let text = svg.to_string_styled(*style).unwrap();
+            let reparsed = SVG::from_str(&text).unwrap();
+            assert_eq!((&reparsed.version, &reparsed.defs.clipPath, &reparsed.stamps), (&svg.version, &svg.defs.clipPath, &svg.stamps));
+            assert_eq!(reparsed.defs.mask.len(), 2);
+        }
+        assert!(svg.to_string_styled(XmlStyle::Compact).unwrap().contains("svg\"><g transform="));
+        assert!(svg.to_string_styled(XmlStyle::Pretty).unwrap().contains("\n  <g transform="));
+        let streamed = svg.write_to(Vec::new(), XmlStyle::Lines).unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), lines);
+    }
+    #[test]
+    fn test_foreign_round_trip() {
+        use super::{SVG, Transform, Color, XmlStyle};
+        let document = r##"<?xml version="1.0" encoding="UTF-8"?>
+<!-- drawn for the north wall -->
+<svg version="2.0" width="500" height="500" xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" id="wall">
+<title>North &amp; wall</title>
+<desc>Arches</desc>
+<g transform="translate(10, 20)" id="door">
+<title>door</title>
+<rect x="0" y="0" width="64" height="64" fill="#000000" mask="url(#stamps/rect.bmp)" opacity="0.5"/>
+</g>
+<metadata><author>someone</author></metadata>
+<g transform="translate(100, 20)">
+<rect x="0" y="0" width="64" height="64" fill="#000000" mask="url(#stamps/rect.bmp)"><animate attributeName="x"/></rect>
+</g>
+<defs class="shared">
+<linearGradient id="fade"><stop offset="0"/></linearGradient>
+<mask id="stamps/rect.bmp"><svg/></mask>
+<mask id="vignette"><rect x="0" y="0" width="10" height="10" fill="url(#fade)"/></mask>
+</defs>
+</svg>"##;
+        let svg = SVG::from_str(document).unwrap();
+        assert_eq!(svg.stamps.len(), 2);
+        assert_eq!(svg.foreign.attrs, vec![("xmlns:xlink".to_string(), "http://www.w3.org/1999/xlink".to_string()), ("id".to_string(), "wall".to_string())]);
+        assert_eq!(svg.foreign.elements.iter().map(|element| element.after).collect::<Vec<usize>>(), vec![0, 0, 1]);
+        assert_eq!(svg.stamps[0].foreign.attrs, vec![("id".to_string(), "door".to_string())]);
+        assert_eq!(svg.stamps[0].rect.foreign.attrs, vec![("opacity".to_string(), "0.5".to_string())]);
+        assert_eq!(svg.stamps[1].rect.foreign.elements[0].markup, "<animate attributeName=\"x\"/>");
+        // the stamp's own mask is written from its outline, the one nothing stamps with is kept
+        assert_eq!(svg.defs.foreign.elements.len(), 2);
+
+        let saved = svg.to_string().unwrap();
+        assert!(saved.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- drawn for the north wall -->\n<svg version="));
+        for kept in &["xmlns:xlink=\"http://www.w3.org/1999/xlink\" id=\"wall\">\n<title>North &amp; wall</title>\n<desc>Arches</desc>\n<g transform=",
+                      " id=\"door\">\n<title>door</title>\n<rect ",
+                      "mask=\"url(#stamps/rect.bmp)\" opacity=\"0.5\"/>\n</g>\n<metadata><author>someone</author></metadata>\n<g ",
+                      "<animate attributeName=\"x\"/>\n</rect>",
+                      "<defs class=\"shared\">\n<linearGradient id=\"fade\"><stop offset=\"0\"/></linearGradient>\n<mask id=\"vignette\">"] {
+            assert!(saved.contains(kept), "{} not in {}", kept, saved);
+        }
+        assert_eq!(saved.matches("<mask id=\"stamps/rect.bmp\">").count(), 1);
+        for style in &[XmlStyle::Compact, XmlStyle::Lines, XmlStyle::Pretty] {
+            let reopened = SVG::from_str(&svg.to_string_styled(*style).unwrap()).unwrap();
+            assert_eq!(reopened.to_string().unwrap(), saved);
+        }
+
+        // editing keeps foreign elements among what's left
+        let mut edited = svg;
+        edited.stamps.remove(0);
+        edited.add(Transform::new(32, 32), "stamps/rect.bmp".to_string(), String::new(), Color::default());
+        let text = edited.to_string().unwrap();
+        assert!(text.contains("<desc>Arches</desc>\n<g transform=") && text.contains("</g>\n<metadata>"));
+        assert_eq!(SVG::from_str(&text).unwrap().stamps.len(), 2);
+    }
+    #[test]
+    fn test_intersect_through_masks() {
+        use super::{SVG, Transform, Color};
+        use std::collections::HashMap;
+        let mut svg = SVG::new(500, 500);
+        let mut transform = Transform::new(64, 64);
+        transform.tx = 100.;
+        transform.ty = 50.;
+        svg.add(transform.clone(), "assets/stamps/arch.bmp".to_string(), String::new(), Color::default());
+        transform.tx = 300.;
+        svg.add(transform, "assets/stamps/porthole.bmp".to_string(), String::new(), Color::default());
+        let mut cache = HashMap::new();
+        // straight down through the doorway, and through the porthole's window
+        assert_eq!(svg.intersect((132., 90.), (132., 112.), &mut cache).unwrap(), None);
+        assert_eq!(svg.intersect((330., 75.), (335., 90.), &mut cache).unwrap(), None);
+        // the masonry around both still collides
+        assert!(svg.intersect((104., 60.), (104., 110.), &mut cache).unwrap().is_some());
+        assert!(svg.intersect((310., 60.), (310., 110.), &mut cache).unwrap().is_some());
+        assert_eq!(svg.stamp_at((132., 100.), &mut cache).unwrap(), None);
+        assert_eq!(svg.stamp_at((332., 80.), &mut cache).unwrap(), None);
+        assert_eq!(svg.stamp_at((305., 80.), &mut cache).unwrap(), Some(1));
+    }
+    #[test]
+    fn test_compose() {
+        use super::{compose, ftransform, Transform};
+        let mut camera = Transform::new(0, 0);
+        camera.scalex = 2.5;
+        camera.scaley = 2.5;
+        camera.tx = -30.;
+        camera.ty = 12.;
+        let mut stamp = Transform::new(64, 32);
+        stamp.tx = 100.;
+        stamp.ty = 50.;
+        stamp.rotate = 30.;
+        stamp.scalex = 0.5;
+        stamp.scaley = 0.5;
+        let composed = compose(&camera, &stamp);
+        for p in &[(0., 0.), (64., 0.), (10., 32.), (32., 16.)] {
+            assert_approx_eq!(ftransform(&composed, *p), ftransform(&camera, ftransform(&stamp, *p)));
+        }
+        camera.rotate = 90.;
+        let composed = compose(&camera, &stamp);
+        for p in &[(0., 0.), (64., 0.), (10., 32.)] {
+            assert_approx_eq!(ftransform(&composed, *p), ftransform(&camera, ftransform(&stamp, *p)));
+        }
+        // mirrored, then stretched, around a turned stamp
+        stamp.flipy = true;
+        let mut mirrored = camera.clone();
+        mirrored.flipx = true;
+        let mut stretched = camera.clone();
+        stretched.scaley = 0.75;
+        stretched.flipx = true;
+        stretched.flipy = true;
+        for t in &[mirrored, stretched] {
+            let composed = compose(t, &stamp);
+            assert!(composed.to_affine().approx_eq(&t.to_affine().mul(&stamp.to_affine())), "{:?}", t);
+            for p in &[(0., 0.), (64., 0.), (10., 32.)] {
+                assert_approx_eq!(ftransform(&composed, *p), ftransform(t, ftransform(&stamp, *p)));
+            }
+        }
+        assert_eq!(compose(&camera, &stamp).matrix, None);
+    }
   #[test]
     fn test_transform() {
         use super::ftransform;
         use super::itransform;
         use super::Transform;
         assert_eq!(ftransform(&Transform::new(1,1), (10.,10.)), (10., 10.));
-        assert_eq!(itransform(&Transform::new(1,1), (10.,10.)), (10., 10.));
+        assert_eq!(itransform(&Transform::new(1,1), (10.,10.)), Some((10., 10.)));
         let mut transf = Transform::new(1,1);
         let xstart = (10.5,0.5);
         let ystart = (0.5,10.5);
         transf.rotate = 90.;
         let rot90 = ftransform(&transf, ystart);
         assert_approx_eq!(rot90, (-9.5, 0.5));
-        assert_approx_eq!(itransform(&transf, rot90), ystart);
+        assert_approx_eq!(itransform(&transf, rot90).unwrap(), ystart);
         transf.rotate = 180.;
         let rot180 = ftransform(&transf, ystart);
         assert_approx_eq!(rot180, (0.5, -9.5));
-        assert_approx_eq!(itransform(&transf, rot180), ystart);
+        assert_approx_eq!(itransform(&transf, rot180).unwrap(), ystart);
         transf.rotate = 60.;
         let rot60 = ftransform(&transf, xstart);
         assert_approx_eq!(rot60, (5.5, 9.16025));
-        assert_approx_eq!(itransform(&transf, rot60), xstart);
+        assert_approx_eq!(itransform(&transf, rot60).unwrap(), xstart);
 
         transf.rotate = 90.;
         transf.tx = 4.;
         transf.ty = 5.;
         let rot90 = ftransform(&transf, ystart);
         assert_approx_eq!(rot90, (-5.5, 5.5));
-        assert_approx_eq!(itransform(&transf, rot90), ystart);
+        assert_approx_eq!(itransform(&transf, rot90).unwrap(), ystart);
         transf.rotate = 180.;
         let rot180 = ftransform(&transf, ystart);
         assert_approx_eq!(rot180, (4.5, -4.5));
-        assert_approx_eq!(itransform(&transf, rot180), ystart);
+        assert_approx_eq!(itransform(&transf, rot180).unwrap(), ystart);
         transf.rotate = 60.;
         let rot60 = ftransform(&transf, xstart);
         assert_approx_eq!(rot60, (9.5, 14.16025));
-        assert_approx_eq!(itransform(&transf, rot60), xstart);
+        assert_approx_eq!(itransform(&transf, rot60).unwrap(), xstart);
 
         transf.rotate = 90.;
         transf.tx = 4.;
         transf.ty = 5.;
-        transf.scale=2.;
+        transf.scalex=2.;
+        transf.scaley=2.;
         let rot90 = ftransform(&transf, ystart);
         assert_approx_eq!(rot90, (-15.5, 5.5));
-        assert_approx_eq!(itransform(&transf, rot90), ystart);
+        assert_approx_eq!(itransform(&transf, rot90).unwrap(), ystart);
         transf.rotate = 180.;
         let rot180 = ftransform(&transf, ystart);
         assert_approx_eq!(rot180, (4.5, -14.5));
-        assert_approx_eq!(itransform(&transf, rot180), ystart);
+        assert_approx_eq!(itransform(&transf, rot180).unwrap(), ystart);
         transf.rotate = 60.;
         let rot60 = ftransform(&transf, xstart);
         assert_approx_eq!(rot60, (14.5, 22.8205));
-        assert_approx_eq!(itransform(&transf, rot60), xstart);
+        assert_approx_eq!(itransform(&transf, rot60).unwrap(), xstart);
 
   }
 }
@@ -0,0 +1,170 @@
+// this file writes xml a tag at a time to anything io::Write, escaping every attribute value and
+// text node on the way out, so whatever a document holds comes back unchanged through from_str
+use std::io::Write;
+use std::vec::Vec;
+use super::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XmlStyle {
+    Compact, // no whitespace between tags
+    Lines, // a tag per line, the way documents have always been saved
+    Pretty, // a tag per line, indented two spaces a level
+}
+
+pub fn attr_escape<'a> (s:&'a str, scratch :&'a mut String) -> &'a str {
+    let mut any_found = false;
+    for c in s.chars() {
+        match c {
+            '>' | '<'  | '"' | '\'' | '&' => any_found = true,
+            _ =>{},
+        }
+    }
+    if any_found {
+        *scratch = s.chars().map(|c| match c{
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            '&' => "&amp;".to_string(),
+            _ => c.to_string(),
+        }).collect();
+        scratch
+    } else {
+        s
+    }
+}
+
+pub struct XmlWriter<W: Write> {
+    out: W,
+    style: XmlStyle,
+    open: Vec<String>, // names of the elements started and not yet ended, outermost first
+    fresh: bool, // nothing written yet
+    inline: bool, // text or raw markup was just written, and the next tag follows it directly
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(out: W, style: XmlStyle) -> XmlWriter<W> {
+        XmlWriter{out, style, open: Vec::new(), fresh: true, inline: false}
+    }
+    fn put(&mut self, s: &str) -> Result<(), Error> {
+        self.out.write_all(s.as_bytes()).map_err(Error::Output)
+    }
+    // the line break and indent ahead of a tag at depth
+    fn separate(&mut self, depth: usize) -> Result<(), Error> {
+        let skip = self.fresh || self.inline;
+        self.fresh = false;
+        self.inline = false;
+        match self.style {
+            _ if skip => Ok(()),
+            XmlStyle::Compact => Ok(()),
+            XmlStyle::Lines => self.put("\n"),
+            XmlStyle::Pretty => self.put(&("\n".to_string() + &"  ".repeat(depth))),
+        }
+    }
+    fn tag(&mut self, name: &str, attrs: &[(&str, &str)], close: &str) -> Result<(), Error> {
+        let mut tag = "<".to_string() + name;
+        let mut scratch = String::new();
+        for (key, value) in attrs {
+            tag += &format!(" {}=\"{}\"", key, attr_escape(value, &mut scratch));
+        }
+        tag += close;
+        self.put(&tag)
+    }
+    pub fn start(&mut self, name: &str, attrs: &[(&str, &str)]) -> Result<(), Error> {
+        let depth = self.open.len();
+        self.separate(depth)?;
+        self.tag(name, attrs, ">")?;
+        self.open.push(name.to_string());
+        Ok(())
+    }
+    // an element with no content, written as <name/>
+    pub fn empty(&mut self, name: &str, attrs: &[(&str, &str)]) -> Result<(), Error> {
+        let depth = self.open.len();
+        self.separate(depth)?;
+        self.tag(name, attrs, "/>")
+    }
+    // closes the innermost element still open
+    pub fn end(&mut self) -> Result<(), Error> {
+        let name = match self.open.pop() {
+            Some(name) => name,
+            None => return Err(Error::Invalid("xml end tag with no element open".to_string())),
+        };
+        let depth = self.open.len();
+        self.separate(depth)?;
+        self.put(&format!("</{}>", name))
+    }
+    pub fn text(&mut self, text: &str) -> Result<(), Error> {
+        let mut scratch = String::new();
+        self.put(attr_escape(text, &mut scratch))?;
+        self.fresh = false;
+        self.inline = true;
+        Ok(())
+    }
+    // markup that is already xml, like a stamp's outline copied into its mask, written as it is
+    pub fn raw(&mut self, markup: &str) -> Result<(), Error> {
+        self.put(markup)?;
+        self.fresh = false;
+        self.inline = true;
+        Ok(())
+    }
+    // an element already written out, like one kept from a loaded document, placed like any tag
+    pub fn element(&mut self, markup: &str) -> Result<(), Error> {
+        let depth = self.open.len();
+        self.separate(depth)?;
+        self.put(markup)
+    }
+    // ends whatever is still open and hands back the output
+    pub fn finish(mut self) -> Result<W, Error> {
+        while !self.open.is_empty() {
+            self.end()?;
+        }
+        self.out.flush().map_err(Error::Output)?;
+        Ok(self.out)
+    }
+}
+
+// the markup write produces, as a string
+pub fn to_xml_string<F>(style: XmlStyle, write: F) -> Result<String, Error>
+where
+  F: FnOnce(&mut XmlWriter<Vec<u8>>) -> Result<(), Error>,
+{
+    let mut xml = XmlWriter::new(Vec::new(), style);
+    write(&mut xml)?;
+    String::from_utf8(xml.finish()?).map_err(|err| Error::Invalid(err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{XmlStyle, to_xml_string};
+    #[test]
+    fn test_attr_escape() {
+        use super::attr_escape;
+
+        let mut scratch = String::new();
+        assert_eq!("HELLOTE", attr_escape(&"HELLOTE".to_string(), &mut scratch));
+        assert_eq!("HEL&lt;LOTE", attr_escape(&"HEL<LOTE".to_string(), &mut scratch));
+        assert_eq!("HEL&lt;LOTE&gt;", attr_escape(&"HEL<LOTE>".to_string(), &mut scratch));
+        assert_eq!("HEL&lt;LOTE&amp;", attr_escape(&"HEL<LOTE&".to_string(), &mut scratch));
+        assert_eq!("HEL&quot;LOTE&apos;", attr_escape(&"HEL\"LOTE'".to_string(), &mut scratch));
+        assert_eq!("H\u{0026bE}EL&quot;LOTE&apos;",
+                   attr_escape(&"H\u{0026bE}EL\"LOTE'".to_string(), &mut scratch));
+    }
+    #[test]
+    fn test_xml_styles() {
+        let write = |style| to_xml_string(style, |xml| {
+            xml.start("a", &[("id", "x<\"y\">")])?;
+            xml.empty("b", &[])?;
+            xml.start("c", &[])?;
+            xml.text("1 < 2 & 3")?;
+            xml.end()?;
+            xml.start("d", &[])?;
+            xml.raw("<e/>")?;
+            xml.end()
+        }).unwrap();
+        // the unfinished <a> is closed by finish
+        assert_eq!(write(XmlStyle::Compact), "<a id=\"x&lt;&quot;y&quot;&gt;\"><b/><c>1 &lt; 2 &amp; 3</c><d><e/></d></a>");
+        assert_eq!(write(XmlStyle::Lines), "<a id=\"x&lt;&quot;y&quot;&gt;\">\n<b/>\n<c>1 &lt; 2 &amp; 3</c>\n<d><e/></d>\n</a>");
+        assert_eq!(write(XmlStyle::Pretty), "<a id=\"x&lt;&quot;y&quot;&gt;\">\n  <b/>\n  <c>1 &lt; 2 &amp; 3</c>\n  <d><e/></d>\n</a>");
+        assert!(to_xml_string(XmlStyle::Lines, |xml| xml.end()).is_err());
+    }
+}